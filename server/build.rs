@@ -0,0 +1,62 @@
+// Embeds the built front-end when the `host-frontend` feature is enabled
+//
+// The front-end is taken from `PASSER_WEB_BUILD`, or from `../web/build` by default
+fn main() {
+    if std::env::var_os("CARGO_FEATURE_HOST_FRONTEND").is_none() {
+        return;
+    }
+
+    println!("cargo:rerun-if-env-changed=PASSER_WEB_BUILD");
+
+    let root = std::env::var_os("PASSER_WEB_BUILD").map_or_else(
+        || {
+            std::path::PathBuf::from(std::env::var_os("CARGO_MANIFEST_DIR").unwrap())
+                .join("../web/build")
+        },
+        std::path::PathBuf::from,
+    );
+    println!("cargo:rerun-if-changed={}", root.display());
+
+    let root = root.canonicalize().unwrap_or_else(|e| {
+        panic!(
+            "could not find the front-end at {}: {e}\nBuild it or point PASSER_WEB_BUILD to it",
+            root.display()
+        )
+    });
+    assert!(
+        root.join("index.html").is_file(),
+        "{} does not contain an index.html",
+        root.display()
+    );
+
+    let mut files = Vec::new();
+    collect(&root, &root, &mut files);
+    files.sort();
+
+    let mut output = String::from("pub static FILES: &[(&str, &[u8])] = &[\n");
+    for (name, path) in files {
+        output.push_str(&format!("    ({name:?}, include_bytes!({path:?})),\n"));
+    }
+    output.push_str("];\n");
+
+    let out_dir = std::path::PathBuf::from(std::env::var_os("OUT_DIR").unwrap());
+    std::fs::write(out_dir.join("web.rs"), output).unwrap();
+}
+
+fn collect(root: &std::path::Path, dir: &std::path::Path, files: &mut Vec<(String, String)>) {
+    for entry in std::fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            collect(root, &path, files);
+        } else {
+            let name = path
+                .strip_prefix(root)
+                .unwrap()
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            files.push((name, path.to_string_lossy().into_owned()));
+        }
+    }
+}
@@ -0,0 +1 @@
+hashed
@@ -0,0 +1,865 @@
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Address {
+    Tcp(std::net::SocketAddr),
+    Unix {
+        path: std::path::PathBuf,
+        mode: Option<u32>,
+    },
+}
+
+impl std::str::FromStr for Address {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if let Some(unix) = value.strip_prefix("unix:") {
+            let (path, mode) = match unix.rsplit_once(':') {
+                Some((path, mode))
+                    if (3..=4).contains(&mode.len())
+                        && mode.bytes().all(|c| (b'0'..=b'7').contains(&c)) =>
+                {
+                    let mode = u32::from_str_radix(mode, 8)
+                        .map_err(|e| format!("invalid socket mode: {e}"))?;
+                    (path, Some(mode))
+                }
+                _ => (unix, None),
+            };
+
+            if path.is_empty() {
+                return Err(String::from("unix socket path is empty"));
+            }
+
+            Ok(Self::Unix {
+                path: path.into(),
+                mode,
+            })
+        } else {
+            value
+                .parse()
+                .map(Self::Tcp)
+                .map_err(|e| format!("invalid socket address: {e}"))
+        }
+    }
+}
+
+impl std::fmt::Display for Address {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Tcp(addr) => write!(fmt, "http://{addr}"),
+            Self::Unix {
+                path,
+                mode: Some(mode),
+            } => write!(fmt, "unix:{}:{mode:o}", path.display()),
+            Self::Unix { path, mode: None } => write!(fmt, "unix:{}", path.display()),
+        }
+    }
+}
+
+pub enum Listener {
+    Tcp(tokio::net::TcpListener),
+    // Allowed because the socket file is only held to be removed on drop
+    #[allow(dead_code)]
+    Unix(tokio::net::UnixListener, Option<SocketFile>),
+}
+
+impl Listener {
+    pub async fn bind(address: &Address) -> std::io::Result<Self> {
+        match address {
+            Address::Tcp(addr) => tokio::net::TcpListener::bind(addr).await.map(Self::Tcp),
+            Address::Unix { path, mode } => {
+                remove_stale_socket(path)?;
+
+                let listener = match mode {
+                    Some(mode) => bind_with_mode(path, *mode)?,
+                    None => tokio::net::UnixListener::bind(path)?,
+                };
+                let file = SocketFile(path.clone());
+
+                Ok(Self::Unix(listener, Some(file)))
+            }
+        }
+    }
+
+    // Adopts an already listening socket, such as the ones passed by the service manager
+    pub fn from_fd(fd: std::os::fd::OwnedFd) -> std::io::Result<Self> {
+        let listener = std::net::TcpListener::from(fd);
+        if listener.local_addr().is_ok() {
+            listener.set_nonblocking(true)?;
+            return tokio::net::TcpListener::from_std(listener).map(Self::Tcp);
+        }
+
+        let listener = std::os::unix::net::UnixListener::from(std::os::fd::OwnedFd::from(listener));
+        listener.local_addr()?;
+        listener.set_nonblocking(true)?;
+        tokio::net::UnixListener::from_std(listener).map(|listener| Self::Unix(listener, None))
+    }
+
+    pub fn address(&self) -> std::io::Result<Address> {
+        match self {
+            Self::Tcp(listener) => listener.local_addr().map(Address::Tcp),
+            // Sockets bound with a mode were moved after binding
+            Self::Unix(_, Some(file)) => Ok(Address::Unix {
+                path: file.0.clone(),
+                mode: None,
+            }),
+            Self::Unix(listener, None) => listener.local_addr().map(|addr| Address::Unix {
+                path: addr
+                    .as_pathname()
+                    .map_or_else(|| "unnamed".into(), std::path::Path::to_path_buf),
+                mode: None,
+            }),
+        }
+    }
+}
+
+// Removes a socket left behind by a previous run, but neither one still served nor any other file
+fn remove_stale_socket(path: &std::path::Path) -> std::io::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+
+    if !std::fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+        return Ok(());
+    }
+    match std::os::unix::net::UnixStream::connect(path) {
+        Ok(_) => Err(std::io::Error::new(
+            std::io::ErrorKind::AddrInUse,
+            "socket is already served",
+        )),
+        Err(e) if e.kind() == std::io::ErrorKind::ConnectionRefused => {
+            log::info!("Removing stale socket at {}", path.display());
+            std::fs::remove_file(path)
+        }
+        Err(_) => Ok(()),
+    }
+}
+
+// Binds in a private directory next to `path` and moves the socket into place once it has its
+// permissions, so that it is never reachable with the permissions of the umask
+fn bind_with_mode(path: &std::path::Path, mode: u32) -> std::io::Result<tokio::net::UnixListener> {
+    use std::os::unix::fs::DirBuilderExt;
+    use std::os::unix::fs::PermissionsExt;
+
+    let parent = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or(std::path::Path::new("."));
+    let private = parent.join(format!(".passer-{:016x}", rand::random::<u64>()));
+    std::fs::DirBuilder::new().mode(0o700).create(&private)?;
+
+    let staged = private.join("socket");
+    let result = tokio::net::UnixListener::bind(&staged).and_then(|listener| {
+        std::fs::set_permissions(&staged, std::fs::Permissions::from_mode(mode))?;
+        // Unlike a rename, linking never replaces what is already there
+        std::fs::hard_link(&staged, path)?;
+        Ok(listener)
+    });
+    let _ = std::fs::remove_file(&staged);
+    std::fs::remove_dir(&private)?;
+    result
+}
+
+// Removes the socket file once the listener is gone so that the next bind does not fail
+pub struct SocketFile(std::path::PathBuf);
+
+impl std::ops::Drop for SocketFile {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.0) {
+            log::warn!("Could not remove socket file {}: {e}", self.0.display());
+        }
+    }
+}
+
+pub async fn bind(addresses: &[Address]) -> std::io::Result<Vec<Listener>> {
+    let mut listeners = Vec::with_capacity(addresses.len());
+    for address in addresses {
+        let listener = Listener::bind(address).await.map_err(|e| {
+            std::io::Error::new(e.kind(), format!("could not bind to {address}: {e}"))
+        })?;
+        listeners.push(listener);
+    }
+    Ok(listeners)
+}
+
+// How long clients may keep a connection without making progress
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    pub header_timeout: std::time::Duration,
+    pub idle_timeout: std::time::Duration,
+}
+
+// Told about the connections closed for breaking the limits, with how long they were open
+pub type Closed = std::sync::Arc<
+    dyn Fn(std::net::SocketAddr, &crate::server::Error, std::time::Duration) + Send + Sync,
+>;
+
+struct Protocol {
+    http: gotham::hyper::server::conn::Http,
+    limits: Limits,
+    closed: Closed,
+}
+
+pub async fn serve(
+    listeners: Vec<Listener>,
+    router: gotham::router::Router,
+    limits: Limits,
+    closed: Closed,
+) {
+    let protocol = std::sync::Arc::new(Protocol {
+        http: gotham::hyper::server::conn::Http::new(),
+        limits,
+        closed,
+    });
+
+    let tasks = listeners
+        .into_iter()
+        .map(|listener| {
+            if let Ok(address) = listener.address() {
+                log::info!("Listening on {address}");
+            }
+            tokio::spawn(accept(listener, router.clone(), protocol.clone()))
+        })
+        .collect::<Vec<_>>();
+
+    for task in tasks {
+        if let Err(e) = task.await {
+            log::error!("Listener stopped: {e}");
+        }
+    }
+}
+
+async fn accept(
+    listener: Listener,
+    router: gotham::router::Router,
+    protocol: std::sync::Arc<Protocol>,
+) {
+    // Unix sockets have no peer address, so they are reported as the unspecified address
+    const UNIX_PEER: std::net::SocketAddr =
+        std::net::SocketAddr::new(std::net::IpAddr::V4(std::net::Ipv4Addr::UNSPECIFIED), 0);
+
+    loop {
+        let result = match &listener {
+            Listener::Tcp(listener) => listener.accept().await.map(|(socket, addr)| {
+                connect(socket, addr, false, router.clone(), protocol.clone());
+            }),
+            Listener::Unix(listener, _) => listener.accept().await.map(|(socket, _)| {
+                connect(socket, UNIX_PEER, true, router.clone(), protocol.clone());
+            }),
+        };
+
+        match result {
+            Ok(()) => {}
+            // Only that connection is lost
+            Err(e) if is_connection_error(&e) => log::debug!("Connection error: {e}"),
+            // Such as running out of file descriptors, which accepting again right away would not
+            // solve
+            Err(e) => {
+                log::error!("Socket error: {e}");
+                tokio::time::sleep(ACCEPT_BACKOFF).await;
+            }
+        }
+    }
+}
+
+// How long to wait before accepting again after the listener failed
+const ACCEPT_BACKOFF: std::time::Duration = std::time::Duration::from_secs(1);
+
+fn is_connection_error(error: &std::io::Error) -> bool {
+    matches!(
+        error.kind(),
+        std::io::ErrorKind::ConnectionRefused
+            | std::io::ErrorKind::ConnectionAborted
+            | std::io::ErrorKind::ConnectionReset
+    )
+}
+
+// Marks requests received over a Unix domain socket, whose access is guarded by the file system
+#[derive(Clone, Copy, gotham_derive::StateData)]
+pub struct UnixPeer;
+
+fn connect<S>(
+    socket: S,
+    addr: std::net::SocketAddr,
+    unix: bool,
+    router: gotham::router::Router,
+    protocol: std::sync::Arc<Protocol>,
+) where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send + 'static,
+{
+    let socket = Tracked::new(socket);
+    let activity = socket.activity.clone();
+
+    let service = {
+        let activity = activity.clone();
+        gotham::hyper::service::service_fn(move |request| {
+            let mut state = gotham::state::State::from_request(request, addr);
+            if unix {
+                state.put(UnixPeer);
+            }
+
+            let request = Handling::new(activity.clone());
+            let response =
+                gotham::service::call_handler(router.clone(), std::panic::AssertUnwindSafe(state));
+            async move {
+                let response = response.await;
+                drop(request);
+                response
+            }
+        })
+    };
+
+    tokio::spawn(async move {
+        let limits = protocol.limits;
+        let error = tokio::select! {
+            // Protocol errors only concern the client, so the connection is just dropped
+            _ = protocol.http.serve_connection(socket, service) => return,
+            () = activity.head(limits.header_timeout) => {
+                crate::server::Error::HeaderTimeout(limits.header_timeout)
+            }
+            () = activity.idle(limits.idle_timeout) => {
+                crate::server::Error::IdleTimeout(limits.idle_timeout)
+            }
+        };
+        (protocol.closed)(addr, &error, activity.start.elapsed());
+    });
+}
+
+// The last times a connection made progress, in milliseconds since `start`
+struct Activity {
+    start: std::time::Instant,
+    // Anything read or written
+    last: std::sync::atomic::AtomicU64,
+    // Anything written, or the end of a response
+    written: std::sync::atomic::AtomicU64,
+    // Requests whose head was received, but not yet answered
+    handling: std::sync::atomic::AtomicUsize,
+}
+
+impl Activity {
+    fn now(&self) -> u64 {
+        u64::try_from(self.start.elapsed().as_millis()).unwrap_or(u64::MAX)
+    }
+
+    fn read(&self) {
+        self.last
+            .store(self.now(), std::sync::atomic::Ordering::Relaxed);
+    }
+
+    fn wrote(&self) {
+        let now = self.now();
+        self.last.store(now, std::sync::atomic::Ordering::Relaxed);
+        self.written
+            .store(now, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    fn instant(&self, time: &std::sync::atomic::AtomicU64) -> std::time::Instant {
+        self.start
+            + std::time::Duration::from_millis(time.load(std::sync::atomic::Ordering::Relaxed))
+    }
+
+    // Resolves once nothing was read or written for `timeout`
+    async fn idle(&self, timeout: std::time::Duration) {
+        self.expire(timeout, |activity| Some(activity.instant(&activity.last)))
+            .await;
+    }
+
+    // Resolves once the client took longer than `timeout` to send the head of its next request,
+    // counted from the opening of the connection or the last response
+    async fn head(&self, timeout: std::time::Duration) {
+        self.expire(timeout, |activity| {
+            (activity.handling.load(std::sync::atomic::Ordering::Relaxed) == 0)
+                .then(|| activity.instant(&activity.written))
+        })
+        .await;
+    }
+
+    // Resolves once `since` is at least `timeout` ago, which never happens while it is `None`
+    async fn expire(
+        &self,
+        timeout: std::time::Duration,
+        since: impl Fn(&Self) -> Option<std::time::Instant>,
+    ) {
+        let mut deadline = self.start + timeout;
+        loop {
+            tokio::time::sleep_until(deadline.into()).await;
+
+            let now = std::time::Instant::now();
+            match since(self) {
+                Some(since) if since + timeout <= now => return,
+                Some(since) => deadline = since + timeout,
+                None => deadline = now + timeout,
+            }
+        }
+    }
+}
+
+// Holds the header timeout off while a request is handled
+struct Handling(std::sync::Arc<Activity>);
+
+impl Handling {
+    fn new(activity: std::sync::Arc<Activity>) -> Self {
+        activity
+            .handling
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        Self(activity)
+    }
+}
+
+impl std::ops::Drop for Handling {
+    fn drop(&mut self) {
+        self.0.wrote();
+        self.0
+            .handling
+            .fetch_sub(1, std::sync::atomic::Ordering::Relaxed);
+    }
+}
+
+// A socket that records its activity
+struct Tracked<S> {
+    socket: S,
+    activity: std::sync::Arc<Activity>,
+}
+
+impl<S> Tracked<S> {
+    fn new(socket: S) -> Self {
+        Self {
+            socket,
+            activity: std::sync::Arc::new(Activity {
+                start: std::time::Instant::now(),
+                last: std::sync::atomic::AtomicU64::new(0),
+                written: std::sync::atomic::AtomicU64::new(0),
+                handling: std::sync::atomic::AtomicUsize::new(0),
+            }),
+        }
+    }
+}
+
+impl<S: tokio::io::AsyncRead + Unpin> tokio::io::AsyncRead for Tracked<S> {
+    fn poll_read(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        let filled = buf.filled().len();
+        let poll = std::pin::Pin::new(&mut self.socket).poll_read(cx, buf);
+        if buf.filled().len() > filled {
+            self.activity.read();
+        }
+        poll
+    }
+}
+
+impl<S: tokio::io::AsyncWrite + Unpin> tokio::io::AsyncWrite for Tracked<S> {
+    fn poll_write(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &[u8],
+    ) -> std::task::Poll<std::io::Result<usize>> {
+        let poll = std::pin::Pin::new(&mut self.socket).poll_write(cx, buf);
+        if let std::task::Poll::Ready(Ok(written)) = poll
+            && written > 0
+        {
+            self.activity.wrote();
+        }
+        poll
+    }
+
+    fn poll_write_vectored(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        bufs: &[std::io::IoSlice<'_>],
+    ) -> std::task::Poll<std::io::Result<usize>> {
+        let poll = std::pin::Pin::new(&mut self.socket).poll_write_vectored(cx, bufs);
+        if let std::task::Poll::Ready(Ok(written)) = poll
+            && written > 0
+        {
+            self.activity.wrote();
+        }
+        poll
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.socket.is_write_vectored()
+    }
+
+    fn poll_flush(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        std::pin::Pin::new(&mut self.socket).poll_flush(cx)
+    }
+
+    fn poll_shutdown(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        std::pin::Pin::new(&mut self.socket).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Address;
+
+    fn request(stream: &mut (impl std::io::Read + std::io::Write)) -> String {
+        get(stream, "/foo")
+    }
+
+    fn get(stream: &mut (impl std::io::Read + std::io::Write), path: &str) -> String {
+        write!(
+            stream,
+            "GET {path} HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n"
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    fn router() -> gotham::router::Router {
+        gotham::router::builder::build_simple_router(|route| {
+            use gotham::router::builder::DefineSingleRoute;
+            use gotham::router::builder::DrawRoutes;
+
+            route.get("/foo").to(|state| (state, "bar"));
+            route.get("/slow").to_async(|state| async {
+                tokio::time::sleep(std::time::Duration::from_millis(400)).await;
+                let response = gotham::handler::IntoResponse::into_response("done", &state);
+                Ok((state, response))
+            });
+            route.get("/peer").to(|state| {
+                use gotham::state::FromState;
+
+                let peer = if super::UnixPeer::try_borrow_from(&state).is_some() {
+                    "unix"
+                } else {
+                    "tcp"
+                };
+                (state, peer)
+            });
+        })
+    }
+
+    fn limits() -> super::Limits {
+        super::Limits {
+            header_timeout: std::time::Duration::from_secs(10),
+            idle_timeout: std::time::Duration::from_mins(1),
+        }
+    }
+
+    // The codes of the errors connections were closed with
+    type Reasons = std::sync::Arc<std::sync::Mutex<Vec<&'static str>>>;
+
+    fn reasons() -> (Reasons, super::Closed) {
+        let reasons = Reasons::default();
+        let closed = reasons.clone();
+        (
+            reasons,
+            std::sync::Arc::new(move |_, error: &crate::server::Error, _| {
+                closed.lock().unwrap().push(error.code());
+            }),
+        )
+    }
+
+    // Serves on a local port, keeping the runtime alive with the result
+    fn serve_with(
+        limits: super::Limits,
+    ) -> (tokio::runtime::Runtime, std::net::SocketAddr, Reasons) {
+        let (reasons, closed) = reasons();
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let address = runtime.block_on(async {
+            let listeners = super::bind(&["127.0.0.1:0".parse().unwrap()])
+                .await
+                .unwrap();
+            let Ok(Address::Tcp(address)) = listeners[0].address() else {
+                panic!();
+            };
+
+            tokio::spawn(super::serve(listeners, router(), limits, closed));
+            address
+        });
+        (runtime, address, reasons)
+    }
+
+    // Waits for the server to close the connection
+    fn closed(stream: &mut std::net::TcpStream) -> std::time::Duration {
+        use std::io::Read;
+
+        let start = std::time::Instant::now();
+        stream
+            .set_read_timeout(Some(std::time::Duration::from_secs(5)))
+            .unwrap();
+        let mut buffer = Vec::new();
+        stream.read_to_end(&mut buffer).unwrap();
+        start.elapsed()
+    }
+
+    // Waits for the reasons of the closed connections, told just after closing them
+    fn reported(reasons: &Reasons, count: usize) -> Vec<&'static str> {
+        for _ in 0..100 {
+            let reported = reasons.lock().unwrap().clone();
+            if reported.len() >= count {
+                return reported;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        reasons.lock().unwrap().clone()
+    }
+
+    #[test]
+    fn header_timeout() {
+        use std::io::Write;
+
+        let (_runtime, address, reasons) = serve_with(super::Limits {
+            header_timeout: std::time::Duration::from_millis(200),
+            ..limits()
+        });
+        let mut stream = std::net::TcpStream::connect(address).unwrap();
+        stream.write_all(b"GET /foo HTTP/1.1\r\nhost: loc").unwrap();
+
+        let elapsed = closed(&mut stream);
+        assert!(
+            elapsed < std::time::Duration::from_millis(400),
+            "{elapsed:?}"
+        );
+        assert_eq!(reported(&reasons, 1), ["header_timeout"]);
+    }
+
+    #[test]
+    fn idle_timeout() {
+        use std::io::Read;
+        use std::io::Write;
+
+        let (_runtime, address, reasons) = serve_with(super::Limits {
+            idle_timeout: std::time::Duration::from_millis(400),
+            ..limits()
+        });
+        let mut stream = std::net::TcpStream::connect(address).unwrap();
+
+        // Keeping the connection busy holds it open past the timeout
+        for _ in 0..3 {
+            stream
+                .write_all(b"GET /foo HTTP/1.1\r\nhost: localhost\r\n\r\n")
+                .unwrap();
+            let mut response = [0; 1024];
+            let read = stream.read(&mut response).unwrap();
+            assert!(response[..read].starts_with(b"HTTP/1.1 200 OK"));
+            std::thread::sleep(std::time::Duration::from_millis(200));
+        }
+
+        let elapsed = closed(&mut stream);
+        assert!(
+            elapsed < std::time::Duration::from_millis(400),
+            "{elapsed:?}"
+        );
+        assert_eq!(reported(&reasons, 1), ["idle_timeout"]);
+    }
+
+    #[test]
+    fn header_timeout_between_requests() {
+        use std::io::Read;
+        use std::io::Write;
+
+        let (_runtime, address, reasons) = serve_with(super::Limits {
+            header_timeout: std::time::Duration::from_millis(200),
+            ..limits()
+        });
+        let mut stream = std::net::TcpStream::connect(address).unwrap();
+
+        // Handling a request for longer than the timeout does not count
+        stream
+            .write_all(b"GET /slow HTTP/1.1\r\nhost: localhost\r\n\r\n")
+            .unwrap();
+        let mut response = [0; 1024];
+        let read = stream.read(&mut response).unwrap();
+        assert!(response[..read].starts_with(b"HTTP/1.1 200 OK"));
+
+        // But the next request has to come soon after the response
+        let elapsed = closed(&mut stream);
+        assert!(
+            elapsed > std::time::Duration::from_millis(100)
+                && elapsed < std::time::Duration::from_millis(400),
+            "{elapsed:?}"
+        );
+        assert_eq!(reported(&reasons, 1), ["header_timeout"]);
+    }
+
+    #[test]
+    fn parse_ipv4() {
+        assert_eq!(
+            "127.0.0.1:8080".parse::<Address>().unwrap(),
+            Address::Tcp(([127, 0, 0, 1], 8080).into())
+        );
+    }
+
+    #[test]
+    fn parse_ipv6() {
+        assert_eq!(
+            "[::1]:8080".parse::<Address>().unwrap(),
+            Address::Tcp((std::net::Ipv6Addr::LOCALHOST, 8080).into())
+        );
+    }
+
+    #[test]
+    fn parse_unix() {
+        assert_eq!(
+            "unix:/run/passer.sock".parse::<Address>().unwrap(),
+            Address::Unix {
+                path: "/run/passer.sock".into(),
+                mode: None
+            }
+        );
+    }
+
+    #[test]
+    fn parse_unix_with_mode() {
+        assert_eq!(
+            "unix:/run/passer.sock:0660".parse::<Address>().unwrap(),
+            Address::Unix {
+                path: "/run/passer.sock".into(),
+                mode: Some(0o660)
+            }
+        );
+        assert_eq!(
+            "unix:/run/pass:er.sock".parse::<Address>().unwrap(),
+            Address::Unix {
+                path: "/run/pass:er.sock".into(),
+                mode: None
+            }
+        );
+    }
+
+    #[test]
+    fn reject_invalid_addresses() {
+        assert!("unix:".parse::<Address>().is_err());
+        assert!("localhost".parse::<Address>().is_err());
+        assert!("0.0.0.0".parse::<Address>().is_err());
+        assert!("::1:80".parse::<Address>().is_err());
+    }
+
+    #[test]
+    fn serve_multiple_listeners() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = std::env::temp_dir().join("passer_test_serve_multiple_listeners.sock");
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+
+        let (tcp, unix) = runtime.block_on(async {
+            let listeners = super::bind(&[
+                "127.0.0.1:0".parse().unwrap(),
+                format!("unix:{}:600", path.display()).parse().unwrap(),
+            ])
+            .await
+            .unwrap();
+
+            let Ok(Address::Tcp(tcp)) = listeners[0].address() else {
+                panic!();
+            };
+            let Ok(unix) = listeners[1].address() else {
+                panic!();
+            };
+
+            tokio::spawn(super::serve(listeners, router(), limits(), reasons().1));
+            (tcp, unix)
+        });
+
+        assert_eq!(
+            unix,
+            Address::Unix {
+                path: path.clone(),
+                mode: None
+            }
+        );
+        assert_eq!(path.metadata().unwrap().permissions().mode() & 0o777, 0o600);
+
+        let response = request(&mut std::net::TcpStream::connect(tcp).unwrap());
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.ends_with("bar"));
+
+        let response = request(&mut std::os::unix::net::UnixStream::connect(&path).unwrap());
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.ends_with("bar"));
+
+        let response = get(&mut std::net::TcpStream::connect(tcp).unwrap(), "/peer");
+        assert!(response.ends_with("tcp"));
+
+        let response = get(
+            &mut std::os::unix::net::UnixStream::connect(&path).unwrap(),
+            "/peer",
+        );
+        assert!(response.ends_with("unix"));
+
+        drop(runtime);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn replace_only_stale_sockets() {
+        let dir = std::env::temp_dir().join("passer_test_replace_only_stale_sockets");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir(&dir).unwrap();
+        let path = dir.join("passer.sock");
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let address = format!("unix:{}:660", path.display())
+            .parse::<Address>()
+            .unwrap();
+
+        // Other files are left alone
+        std::fs::write(&path, "data").unwrap();
+        assert!(
+            runtime
+                .block_on(super::bind(std::slice::from_ref(&address)))
+                .is_err()
+        );
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "data");
+        std::fs::remove_file(&path).unwrap();
+
+        // So are sockets still served
+        let served = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        let error = runtime
+            .block_on(super::bind(std::slice::from_ref(&address)))
+            .err()
+            .unwrap();
+        assert_eq!(error.kind(), std::io::ErrorKind::AddrInUse);
+
+        // But not those left behind
+        drop(served);
+        let listeners = runtime.block_on(super::bind(&[address])).unwrap();
+        assert_eq!(listeners.len(), 1);
+
+        // No private directory is left behind either
+        let files = std::fs::read_dir(&dir).unwrap().count();
+        assert_eq!(files, 1);
+
+        drop(listeners);
+        assert!(!path.exists());
+        std::fs::remove_dir(dir).unwrap();
+    }
+
+    #[test]
+    fn adopt_file_descriptors() {
+        let path = std::env::temp_dir().join("passer_test_adopt_file_descriptors.sock");
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+
+        let tcp = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let tcp_address = Address::Tcp(tcp.local_addr().unwrap());
+        let unix = std::os::unix::net::UnixListener::bind(&path).unwrap();
+
+        runtime.block_on(async {
+            let tcp = super::Listener::from_fd(tcp.into()).unwrap();
+            assert!(matches!(tcp, super::Listener::Tcp(_)));
+            assert_eq!(tcp.address().unwrap(), tcp_address);
+
+            let unix = super::Listener::from_fd(unix.into()).unwrap();
+            assert!(matches!(unix, super::Listener::Unix(_, None)));
+            assert_eq!(
+                unix.address().unwrap(),
+                Address::Unix {
+                    path: path.clone(),
+                    mode: None
+                }
+            );
+        });
+
+        // Adopted sockets belong to whoever created them
+        assert!(path.exists());
+        std::fs::remove_file(path).unwrap();
+    }
+}
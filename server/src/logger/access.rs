@@ -0,0 +1,540 @@
+// What is known about a request once it has been answered
+pub struct Access<'a> {
+    pub client_ip: Option<String>,
+    pub forwarded: bool,
+    // The label of the API key the request was made with
+    pub key: Option<&'a str>,
+    pub method: &'a str,
+    pub path: String,
+    pub route: &'static str,
+    pub status: u16,
+    pub request_size: Option<u64>,
+    pub response_size: Option<u64>,
+    pub latency: std::time::Duration,
+    pub error: Option<(&'static str, String)>,
+}
+
+impl Access<'_> {
+    fn write_field(&self, fmt: &mut std::fmt::Formatter<'_>, field: Field) -> std::fmt::Result {
+        use colored::Colorize;
+
+        match field {
+            Field::ClientIp => fmt.write_str(self.client_ip.as_deref().unwrap_or("-")),
+            Field::Forwarded => write!(fmt, "{}", self.forwarded),
+            Field::Key => fmt.write_str(self.key.unwrap_or("-")),
+            Field::Method => fmt.write_str(self.method),
+            Field::Path => write!(fmt, "{}", self.path.white()),
+            Field::Route => fmt.write_str(self.route),
+            Field::Status => write!(fmt, "{}", status_to_color(self.status)),
+            Field::RequestSize => write_size(fmt, self.request_size),
+            Field::ResponseSize => write_size(fmt, self.response_size),
+            Field::Latency => write!(fmt, "{:?}", self.latency),
+            Field::Error => fmt.write_str(self.error.as_ref().map_or("-", |(_, e)| e)),
+        }
+    }
+
+    fn to_value(&self, field: Field) -> log::kv::Value<'_> {
+        match field {
+            Field::ClientIp => optional(self.client_ip.as_deref()),
+            Field::Forwarded => self.forwarded.into(),
+            Field::Key => optional(self.key),
+            Field::Method => self.method.into(),
+            Field::Path => self.path.as_str().into(),
+            Field::Route => self.route.into(),
+            Field::Status => self.status.into(),
+            Field::RequestSize => optional(self.request_size),
+            Field::ResponseSize => optional(self.response_size),
+            Field::Latency => (self.latency.as_secs_f64() * 1000.0).into(),
+            Field::Error => optional(self.error.as_ref().map(|(code, _)| *code)),
+        }
+    }
+
+    // Writes the record, restricted to the fields of `template` if there is one
+    pub fn log(&self, level: log::Level, template: Option<&Template>) {
+        if level > log::max_level() {
+            return;
+        }
+
+        if template.is_some_and(|template| template.parts.is_empty()) {
+            return;
+        }
+
+        let message = Message(self, template);
+        let fields = Fields(self, template);
+
+        log::logger().log(
+            &log::Record::builder()
+                .level(level)
+                .target(super::ACCESS)
+                .module_path_static(Some(module_path!()))
+                .args(format_args!("{message}"))
+                .key_values(&fields)
+                .build(),
+        );
+    }
+}
+
+struct Message<'a>(&'a Access<'a>, Option<&'a Template>);
+
+impl std::fmt::Display for Message<'_> {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let access = self.0;
+
+        if let Some(template) = self.1 {
+            for part in &template.parts {
+                match part {
+                    Part::Literal(literal) => fmt.write_str(literal)?,
+                    Part::Field(field) => access.write_field(fmt, *field)?,
+                }
+            }
+            return Ok(());
+        }
+
+        access.write_field(fmt, Field::ClientIp)?;
+        if access.forwarded {
+            fmt.write_str(" [p]")?;
+        }
+        if let Some(key) = access.key {
+            write!(fmt, " as {key}")?;
+        }
+        fmt.write_str(" ")?;
+        access.write_field(fmt, Field::Method)?;
+        fmt.write_str(" ")?;
+        access.write_field(fmt, Field::Path)?;
+        if let Some(size) = access.request_size {
+            write!(fmt, " {size}b")?;
+        }
+        fmt.write_str(" - ")?;
+        access.write_field(fmt, Field::Status)?;
+        match &access.error {
+            Some((_, error)) => write!(fmt, " [{error}]")?,
+            None => {
+                if let Some(size) = access.response_size {
+                    write!(fmt, " {size}b")?;
+                }
+            }
+        }
+        fmt.write_str(" - ")?;
+        access.write_field(fmt, Field::Latency)
+    }
+}
+
+struct Fields<'a>(&'a Access<'a>, Option<&'a Template>);
+
+impl log::kv::Source for Fields<'_> {
+    fn visit<'kvs>(
+        &'kvs self,
+        visitor: &mut dyn log::kv::VisitSource<'kvs>,
+    ) -> Result<(), log::kv::Error> {
+        let mut visit = |field: Field| {
+            visitor.visit_pair(log::kv::Key::from_str(field.name()), self.0.to_value(field))
+        };
+
+        if let Some(template) = self.1 {
+            for part in &template.parts {
+                if let Part::Field(field) = part {
+                    visit(*field)?;
+                }
+            }
+        } else {
+            for field in Field::ALL {
+                visit(field)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn optional<'v, T: Into<log::kv::Value<'v>>>(value: Option<T>) -> log::kv::Value<'v> {
+    value.map_or_else(log::kv::Value::null, Into::into)
+}
+
+fn write_size(fmt: &mut std::fmt::Formatter<'_>, size: Option<u64>) -> std::fmt::Result {
+    match size {
+        Some(size) => write!(fmt, "{size}b"),
+        None => fmt.write_str("-"),
+    }
+}
+
+fn status_to_color(status: u16) -> colored::ColoredString {
+    use colored::Colorize;
+    if status < 200 {
+        status.to_string().blue()
+    } else if status < 400 {
+        status.to_string().green()
+    } else if status < 500 {
+        status.to_string().yellow()
+    } else if status < 600 {
+        status.to_string().red()
+    } else {
+        status.to_string().white()
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Field {
+    ClientIp,
+    Forwarded,
+    Key,
+    Method,
+    Path,
+    Route,
+    Status,
+    RequestSize,
+    ResponseSize,
+    Latency,
+    Error,
+}
+
+impl Field {
+    const ALL: [Self; 11] = [
+        Self::ClientIp,
+        Self::Forwarded,
+        Self::Key,
+        Self::Method,
+        Self::Path,
+        Self::Route,
+        Self::Status,
+        Self::RequestSize,
+        Self::ResponseSize,
+        Self::Latency,
+        Self::Error,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::ClientIp => "client_ip",
+            Self::Forwarded => "forwarded",
+            Self::Key => "key",
+            Self::Method => "method",
+            Self::Path => "path",
+            Self::Route => "route",
+            Self::Status => "status",
+            Self::RequestSize => "request_size",
+            Self::ResponseSize => "response_size",
+            Self::Latency => "latency_ms",
+            Self::Error => "error",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum Part {
+    Literal(String),
+    Field(Field),
+}
+
+// Selects the fields recorded for a route, parsed from `<route>=<text with {field} placeholders>`
+//
+// An empty template silences the route
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Template {
+    pub route: String,
+    parts: Vec<Part>,
+}
+
+impl std::str::FromStr for Template {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (route, mut template) = value
+            .split_once('=')
+            .ok_or_else(|| String::from("expected <route>=<template>"))?;
+
+        if route.is_empty() {
+            return Err(String::from("route is empty"));
+        }
+
+        let mut parts = Vec::new();
+        while let Some(start) = template.find('{') {
+            if start > 0 {
+                parts.push(Part::Literal(template[..start].into()));
+            }
+
+            let end = template[start..]
+                .find('}')
+                .ok_or_else(|| String::from("unclosed placeholder"))?;
+            let name = &template[start + 1..start + end];
+            let field = Field::ALL
+                .into_iter()
+                .find(|field| {
+                    field.name() == name || (*field == Field::Latency && name == "latency")
+                })
+                .ok_or_else(|| format!("unknown field: {name}"))?;
+
+            parts.push(Part::Field(field));
+            template = &template[start + end + 1..];
+        }
+        if !template.is_empty() {
+            parts.push(Part::Literal(template.into()));
+        }
+
+        Ok(Self {
+            route: route.into(),
+            parts,
+        })
+    }
+}
+
+// Replaces every path segment that looks like a secret id
+pub fn sanitize_path(path: &str, policy: crate::options::IdPolicy) -> String {
+    use crate::options::IdPolicy;
+
+    if matches!(policy, IdPolicy::Full) {
+        return path.into();
+    }
+
+    let (path, query) = path
+        .find('?')
+        .map_or((path, ""), |index| path.split_at(index));
+
+    let mut sanitized = path
+        .split('/')
+        .map(|segment| {
+            if !is_id(segment) {
+                return std::borrow::Cow::Borrowed(segment);
+            }
+
+            match policy {
+                IdPolicy::Hash => std::borrow::Cow::Owned(format!("<id:{}>", digest(segment))),
+                IdPolicy::Redact | IdPolicy::Full => std::borrow::Cow::Borrowed("<id>"),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("/");
+
+    if let Some(query) = query.strip_prefix('?') {
+        let parameters = query
+            .split('&')
+            .map(|parameter| sanitize_parameter(parameter, policy))
+            .collect::<Vec<_>>();
+        sanitized.push('?');
+        sanitized.push_str(&parameters.join("&"));
+    }
+    sanitized
+}
+
+// Query parameters that only tune the request, and are logged as they are
+const PLAIN_PARAMETERS: [&str; 3] = ["ttl", "size", "scope"];
+
+// Values of other parameters, such as callback URLs, can be as sensitive as ids
+fn sanitize_parameter(
+    parameter: &str,
+    policy: crate::options::IdPolicy,
+) -> std::borrow::Cow<'_, str> {
+    use crate::options::IdPolicy;
+
+    match parameter.split_once('=') {
+        Some((name, value)) if !value.is_empty() && !PLAIN_PARAMETERS.contains(&name) => {
+            match policy {
+                IdPolicy::Hash => format!("{name}=<value:{}>", digest(value)).into(),
+                IdPolicy::Redact | IdPolicy::Full => format!("{name}=<value>").into(),
+            }
+        }
+        _ => parameter.into(),
+    }
+}
+
+fn is_id(segment: &str) -> bool {
+    segment.len() == 43
+        && segment
+            .bytes()
+            .all(|c| c.is_ascii_alphanumeric() || c == b'_' || c == b'-')
+}
+
+// A short, stable stand-in for an id, which still allows following a secret through the logs
+pub fn digest(value: &str) -> String {
+    use sha2::Digest;
+    use std::fmt::Write;
+
+    sha2::Sha256::digest(value.as_bytes())[..8].iter().fold(
+        String::with_capacity(16),
+        |mut hex, byte| {
+            write!(hex, "{byte:02x}").unwrap();
+            hex
+        },
+    )
+}
+
+pub fn mask_ip(ip: std::net::IpAddr, policy: crate::options::IpPolicy) -> Option<std::net::IpAddr> {
+    use crate::options::IpPolicy;
+
+    match policy {
+        IpPolicy::Full => Some(ip),
+        IpPolicy::Drop => None,
+        IpPolicy::Truncate => Some(match ip {
+            std::net::IpAddr::V4(ip) => {
+                let [a, b, c, _] = ip.octets();
+                std::net::Ipv4Addr::new(a, b, c, 0).into()
+            }
+            std::net::IpAddr::V6(ip) => {
+                let [a, b, c, ..] = ip.segments();
+                std::net::Ipv6Addr::new(a, b, c, 0, 0, 0, 0, 0).into()
+            }
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Access;
+    use super::Template;
+    use crate::options::IdPolicy;
+    use crate::options::IpPolicy;
+
+    const ID: &str = "VhmE7GuDMxsrCM6Mu8zvBX5Hr8_COegK4EomGENCRCQ";
+
+    fn access() -> Access<'static> {
+        Access {
+            client_ip: Some(String::from("10.0.0.1")),
+            forwarded: true,
+            key: None,
+            method: "GET",
+            path: String::from("/api/<id>"),
+            route: "retrieve",
+            status: 404,
+            request_size: None,
+            response_size: None,
+            latency: std::time::Duration::from_millis(2),
+            error: Some(("secret_not_found", String::from("secret not found"))),
+        }
+    }
+
+    fn fields(access: &Access<'_>, template: Option<&Template>) -> Vec<(String, String)> {
+        struct Collect(Vec<(String, String)>);
+
+        impl<'kvs> log::kv::VisitSource<'kvs> for Collect {
+            fn visit_pair(
+                &mut self,
+                key: log::kv::Key<'kvs>,
+                value: log::kv::Value<'kvs>,
+            ) -> Result<(), log::kv::Error> {
+                self.0.push((key.to_string(), value.to_string()));
+                Ok(())
+            }
+        }
+
+        let mut collect = Collect(Vec::new());
+        log::kv::Source::visit(&super::Fields(access, template), &mut collect).unwrap();
+        collect.0
+    }
+
+    #[test]
+    fn redact_ids() {
+        assert_eq!(
+            super::sanitize_path(&format!("/api/{ID}?foo=bar"), IdPolicy::Redact),
+            "/api/<id>?foo=<value>"
+        );
+        assert_eq!(
+            super::sanitize_path(
+                "/?ttl=1m&callback=https://example.com/hook?token=foo",
+                IdPolicy::Redact
+            ),
+            "/?ttl=1m&callback=<value>"
+        );
+        assert_eq!(
+            super::sanitize_path(&format!("/{ID}"), IdPolicy::Full),
+            format!("/{ID}")
+        );
+        assert_eq!(
+            super::sanitize_path("/?ttl=1m", IdPolicy::Redact),
+            "/?ttl=1m"
+        );
+        assert_eq!(
+            super::sanitize_path("/static/js/main.js", IdPolicy::Redact),
+            "/static/js/main.js"
+        );
+    }
+
+    #[test]
+    fn hash_ids() {
+        let hashed = super::sanitize_path(&format!("/{ID}"), IdPolicy::Hash);
+        assert_eq!(hashed, format!("/<id:{}>", super::digest(ID)));
+        assert_eq!(hashed.len(), "/<id:>".len() + 16);
+        assert!(!hashed.contains(ID));
+
+        let hashed = super::sanitize_path("/?callback=https://example.com", IdPolicy::Hash);
+        assert_eq!(
+            hashed,
+            format!(
+                "/?callback=<value:{}>",
+                super::digest("https://example.com")
+            )
+        );
+    }
+
+    #[test]
+    fn mask_ips() {
+        let ipv4 = std::net::IpAddr::from([192, 168, 10, 20]);
+        let ipv6 = "2001:db8:1234:5678::1".parse().unwrap();
+
+        assert_eq!(super::mask_ip(ipv4, IpPolicy::Full), Some(ipv4));
+        assert_eq!(super::mask_ip(ipv4, IpPolicy::Drop), None);
+        assert_eq!(
+            super::mask_ip(ipv4, IpPolicy::Truncate),
+            Some([192, 168, 10, 0].into())
+        );
+        assert_eq!(
+            super::mask_ip(ipv6, IpPolicy::Truncate),
+            Some("2001:db8:1234::".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn parse_template() {
+        let template = "retrieve={method} {route} -> {status} in {latency}"
+            .parse::<Template>()
+            .unwrap();
+        assert_eq!(template.route, "retrieve");
+        assert_eq!(template.parts.len(), 7);
+
+        assert!("retrieve={nope}".parse::<Template>().is_err());
+        assert!("retrieve={method".parse::<Template>().is_err());
+        assert!("{method}".parse::<Template>().is_err());
+        assert!("=".parse::<Template>().is_err());
+    }
+
+    #[test]
+    fn default_message() {
+        colored::control::set_override(false);
+        assert_eq!(
+            super::Message(&access(), None).to_string(),
+            "10.0.0.1 [p] GET /api/<id> - 404 [secret not found] - 2ms"
+        );
+
+        let access = Access {
+            key: Some("ci"),
+            ..access()
+        };
+        assert_eq!(
+            super::Message(&access, None).to_string(),
+            "10.0.0.1 [p] as ci GET /api/<id> - 404 [secret not found] - 2ms"
+        );
+    }
+
+    #[test]
+    fn template_message() {
+        colored::control::set_override(false);
+        let template = "*=[{route}] {status} {response_size} {error}"
+            .parse::<Template>()
+            .unwrap();
+        assert_eq!(
+            super::Message(&access(), Some(&template)).to_string(),
+            "[retrieve] 404 - secret not found"
+        );
+    }
+
+    #[test]
+    fn template_fields() {
+        let template = "*={client_ip} {status}".parse::<Template>().unwrap();
+        assert_eq!(
+            fields(&access(), Some(&template)),
+            [
+                (String::from("client_ip"), String::from("10.0.0.1")),
+                (String::from("status"), String::from("404")),
+            ]
+        );
+        assert_eq!(fields(&access(), None).len(), 11);
+    }
+}
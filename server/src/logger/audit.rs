@@ -0,0 +1,554 @@
+// Append-only record of when secrets were created, retrieved, expired, revoked or purged
+//
+// Each line is a JSON object holding the hash of the line before it, and ends with its own
+// SHA-256 over the rest of the line, so that editing, inserting, reordering or removing lines
+// breaks the chain from there on. Ids are only ever written hashed, and contents never
+//
+// Expired secrets are noticed when the next event is recorded, and dated by their expiry
+//
+// Each entry is written at once and synced before the next, so that a crash leaves at most an
+// unfinished last line. It is not part of the chain, and is dropped when the log is opened again
+
+use std::io::BufRead;
+use std::io::Write;
+
+// Hash before the first line
+const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Event {
+    Created,
+    Retrieved,
+    Expired,
+    Revoked,
+    Purged,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("{0}")]
+    Io(#[from] std::io::Error),
+    #[error("line {line}: {reason}")]
+    Broken { line: u64, reason: &'static str },
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Entry {
+    seq: u64,
+    time: u64,
+    event: Event,
+    // SHA-256 of the id, as it appears in URLs
+    secret: String,
+    size: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expires: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    key: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    client_ip: Option<std::net::IpAddr>,
+    prev: String,
+}
+
+// The secrets created but not yet gone, so that their expiry can be recorded
+#[derive(Clone, Copy)]
+struct Pending {
+    size: u64,
+    expires: u64,
+}
+
+// Where the chain ends
+struct Chain {
+    entries: u64,
+    head: String,
+    pending: std::collections::HashMap<String, Pending>,
+}
+
+impl Chain {
+    fn new() -> Self {
+        Self {
+            entries: 0,
+            head: String::from(GENESIS),
+            pending: std::collections::HashMap::new(),
+        }
+    }
+
+    // Follows the chain through `line`, after checking that it carries on from the last one
+    fn push(&mut self, line: &str) -> Result<(), &'static str> {
+        let (body, hash) = split(line).ok_or("not an audit entry")?;
+        if digest(&body) != hash {
+            return Err("hash does not match the entry");
+        }
+        let entry = serde_json::from_str::<Entry>(&body).map_err(|_| "not an audit entry")?;
+        if entry.prev != self.head {
+            return Err("does not follow the previous entry");
+        }
+        if entry.seq != self.entries {
+            return Err("out of sequence");
+        }
+
+        match entry.event {
+            Event::Created => {
+                self.pending.insert(
+                    entry.secret,
+                    Pending {
+                        size: entry.size,
+                        expires: entry.expires.unwrap_or_default(),
+                    },
+                );
+            }
+            Event::Retrieved | Event::Expired | Event::Revoked | Event::Purged => {
+                self.pending.remove(&entry.secret);
+            }
+        }
+        self.entries += 1;
+        self.head = String::from(hash);
+        Ok(())
+    }
+}
+
+// What a verified log holds
+pub struct Summary {
+    pub entries: u64,
+    pub head: String,
+    // Whether it ends with a line left unfinished by a crash
+    pub unfinished: bool,
+}
+
+// Checks the whole chain of the log at `path`
+pub fn verify(path: &std::path::Path) -> Result<Summary, Error> {
+    let file = std::fs::File::open(path)?;
+    let size = file.metadata()?.len();
+    let (chain, length) = replay(file)?;
+    Ok(Summary {
+        entries: chain.entries,
+        head: chain.head,
+        unfinished: length < size,
+    })
+}
+
+// Follows the chain through the finished lines of `file`, returning it with their length
+fn replay(file: impl std::io::Read) -> Result<(Chain, u64), Error> {
+    let mut reader = std::io::BufReader::new(file);
+    let mut chain = Chain::new();
+    let mut length = 0;
+    let mut text = Vec::new();
+    for line in 1.. {
+        text.clear();
+        let read = reader.read_until(b'\n', &mut text)?;
+        if text.pop() != Some(b'\n') {
+            break;
+        }
+
+        let text = std::str::from_utf8(&text).map_err(|_| Error::Broken {
+            line,
+            reason: "not an audit entry",
+        })?;
+        chain
+            .push(text)
+            .map_err(|reason| Error::Broken { line, reason })?;
+        length += read as u64;
+    }
+    Ok((chain, length))
+}
+
+// Who caused an event, as far as it is known
+#[derive(Clone, Copy, Default)]
+pub struct Client<'a> {
+    pub key: Option<&'a str>,
+    pub ip: Option<std::net::IpAddr>,
+}
+
+pub struct Log {
+    file: std::fs::File,
+    // Of the finished lines, which the next one is appended to
+    length: u64,
+    chain: Chain,
+    client_ip: crate::options::IpPolicy,
+}
+
+impl Log {
+    // Opens the log for appending, after checking the chain it already holds
+    pub fn open(
+        path: &std::path::Path,
+        client_ip: crate::options::IpPolicy,
+    ) -> Result<Self, Error> {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path)?;
+        let (chain, length) = replay(&file)?;
+
+        if file.metadata()?.len() > length {
+            log::warn!(
+                "Dropping the unfinished last line of the audit log {}",
+                path.display()
+            );
+            file.set_len(length)?;
+            file.sync_data()?;
+        }
+
+        Ok(Self {
+            file,
+            length,
+            chain,
+            client_ip,
+        })
+    }
+
+    // Records an event of the secret `id`. The size is taken from its creation when not given
+    pub fn record(
+        &mut self,
+        event: Event,
+        id: &str,
+        size: Option<u64>,
+        expires: Option<std::time::SystemTime>,
+        client: Client<'_>,
+        now: std::time::SystemTime,
+    ) -> Result<(), Error> {
+        self.expire(now)?;
+
+        let secret = digest(id);
+        let size = size
+            .or_else(|| self.chain.pending.get(&secret).map(|pending| pending.size))
+            .unwrap_or_default();
+        self.append(
+            event,
+            secret,
+            size,
+            expires.map(seconds),
+            client,
+            seconds(now),
+        )
+    }
+
+    // Records every outstanding secret as purged
+    pub fn purge(&mut self, client: Client<'_>, now: std::time::SystemTime) -> Result<(), Error> {
+        self.expire(now)?;
+
+        let mut pending = self.chain.pending.drain().collect::<Vec<_>>();
+        pending.sort_by(|(a, _), (b, _)| a.cmp(b));
+        for (secret, Pending { size, .. }) in pending {
+            self.append(Event::Purged, secret, size, None, client, seconds(now))?;
+        }
+        Ok(())
+    }
+
+    // Records the secrets that expired since the last event, in the order they did
+    pub fn expire(&mut self, now: std::time::SystemTime) -> Result<(), Error> {
+        let now = seconds(now);
+        let mut expired = self
+            .chain
+            .pending
+            .iter()
+            .filter(|(_, pending)| pending.expires <= now)
+            .map(|(secret, pending)| (secret.clone(), *pending))
+            .collect::<Vec<_>>();
+        expired.sort_by(|(a, x), (b, y)| x.expires.cmp(&y.expires).then_with(|| a.cmp(b)));
+
+        for (secret, Pending { size, expires }) in expired {
+            self.append(
+                Event::Expired,
+                secret,
+                size,
+                None,
+                Client::default(),
+                expires,
+            )?;
+        }
+        Ok(())
+    }
+
+    fn append(
+        &mut self,
+        event: Event,
+        secret: String,
+        size: u64,
+        expires: Option<u64>,
+        client: Client<'_>,
+        time: u64,
+    ) -> Result<(), Error> {
+        let entry = Entry {
+            seq: self.chain.entries,
+            time,
+            event,
+            secret,
+            size,
+            expires,
+            key: client.key.map(String::from),
+            client_ip: client
+                .ip
+                .and_then(|ip| super::access::mask_ip(ip, self.client_ip)),
+            prev: self.chain.head.clone(),
+        };
+        let body = serde_json::to_string(&entry).map_err(std::io::Error::other)?;
+        let hash = digest(&body);
+        let line = format!("{},\"hash\":\"{hash}\"}}\n", &body[..body.len() - 1]);
+
+        // A line that fails half-way is taken back, so that the next one still follows the chain
+        if let Err(e) = self
+            .file
+            .write_all(line.as_bytes())
+            .and_then(|()| self.file.sync_data())
+        {
+            let _ = self.file.set_len(self.length);
+            return Err(e.into());
+        }
+        self.length += line.len() as u64;
+        self.chain
+            .push(line.trim_end())
+            .map_err(|reason| Error::Broken {
+                line: self.chain.entries + 1,
+                reason,
+            })
+    }
+}
+
+// Separates a line into the entry it hashes and its hash
+fn split(line: &str) -> Option<(String, &str)> {
+    let (body, hash) = line.rsplit_once(",\"hash\":\"")?;
+    let hash = hash.strip_suffix("\"}")?;
+    (hash.len() == GENESIS.len()).then(|| (format!("{body}}}"), hash))
+}
+
+// The SHA-256 of `value` in hexadecimal
+fn digest(value: &str) -> String {
+    use sha2::Digest;
+    use std::fmt::Write;
+
+    sha2::Sha256::digest(value.as_bytes()).iter().fold(
+        String::with_capacity(GENESIS.len()),
+        |mut hex, byte| {
+            write!(hex, "{byte:02x}").unwrap();
+            hex
+        },
+    )
+}
+
+fn seconds(time: std::time::SystemTime) -> u64 {
+    time.duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Client, Error, Event, Log};
+    use crate::options::IpPolicy;
+
+    const ID: &str = "0___________________foo___________________0";
+
+    struct TempFile(std::path::PathBuf);
+
+    impl TempFile {
+        fn new(name: &'static str) -> Self {
+            let path = std::env::temp_dir().join(format!("passer_test_{name}"));
+            let _ = std::fs::remove_file(&path);
+            Self(path)
+        }
+
+        fn lines(&self) -> Vec<String> {
+            std::fs::read_to_string(&self.0)
+                .unwrap()
+                .lines()
+                .map(String::from)
+                .collect()
+        }
+
+        fn rewrite(&self, lines: &[String]) {
+            std::fs::write(&self.0, lines.join("\n") + "\n").unwrap();
+        }
+
+        fn broken_at(&self) -> Option<u64> {
+            match super::verify(&self.0) {
+                Err(Error::Broken { line, .. }) => Some(line),
+                _ => None,
+            }
+        }
+    }
+
+    impl std::ops::Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn at(seconds: u64) -> std::time::SystemTime {
+        std::time::UNIX_EPOCH + std::time::Duration::from_secs(seconds)
+    }
+
+    // Creates and retrieves a secret, and creates another that expires at 200
+    fn fill(log: &mut Log) {
+        let client = Client {
+            key: Some("ci"),
+            ip: Some("192.168.1.17".parse().unwrap()),
+        };
+        log.record(Event::Created, ID, Some(3), Some(at(160)), client, at(100))
+            .unwrap();
+        log.record(Event::Retrieved, ID, None, None, Client::default(), at(110))
+            .unwrap();
+        log.record(
+            Event::Created,
+            "other",
+            Some(5),
+            Some(at(200)),
+            client,
+            at(120),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn chain() {
+        let file = TempFile::new("audit_chain");
+        let mut log = Log::open(&file.0, IpPolicy::Truncate).unwrap();
+        fill(&mut log);
+        drop(log);
+
+        let lines = file.lines();
+        assert_eq!(lines.len(), 3);
+        let first = serde_json::from_str::<serde_json::Value>(&lines[0]).unwrap();
+        assert_eq!(first["seq"], 0);
+        assert_eq!(first["event"], "created");
+        assert_eq!(first["secret"], super::digest(ID));
+        assert_eq!(first["size"], 3);
+        assert_eq!(first["key"], "ci");
+        assert_eq!(first["client_ip"], "192.168.1.0");
+        assert_eq!(first["prev"], super::GENESIS);
+        let second = serde_json::from_str::<serde_json::Value>(&lines[1]).unwrap();
+        assert_eq!(second["size"], 3);
+        assert_eq!(second["prev"], first["hash"]);
+        assert!(lines.iter().all(|line| !line.contains(ID)));
+
+        // Reopening carries on with the chain and the outstanding secrets
+        let mut log = Log::open(&file.0, IpPolicy::Truncate).unwrap();
+        log.expire(at(300)).unwrap();
+        drop(log);
+
+        let lines = file.lines();
+        let last = serde_json::from_str::<serde_json::Value>(&lines[3]).unwrap();
+        assert_eq!(last["seq"], 3);
+        assert_eq!(last["event"], "expired");
+        assert_eq!(last["time"], 200);
+        assert_eq!(last["secret"], super::digest("other"));
+
+        let summary = super::verify(&file.0).unwrap();
+        assert_eq!(summary.entries, 4);
+        assert_eq!(summary.head, last["hash"]);
+    }
+
+    #[test]
+    fn purge() {
+        let file = TempFile::new("audit_purge");
+        let mut log = Log::open(&file.0, IpPolicy::Drop).unwrap();
+        log.record(
+            Event::Created,
+            ID,
+            Some(3),
+            Some(at(160)),
+            Client::default(),
+            at(100),
+        )
+        .unwrap();
+        log.record(
+            Event::Created,
+            "other",
+            Some(5),
+            Some(at(900)),
+            Client::default(),
+            at(100),
+        )
+        .unwrap();
+        log.purge(Client::default(), at(500)).unwrap();
+        log.purge(Client::default(), at(600)).unwrap();
+        drop(log);
+
+        let events = file
+            .lines()
+            .iter()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .map(|entry| (entry["event"].clone(), entry["time"].clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            events,
+            [
+                ("created", 100),
+                ("created", 100),
+                ("expired", 160),
+                ("purged", 500)
+            ]
+            .map(|(event, time)| (event.into(), time.into()))
+        );
+    }
+
+    #[test]
+    fn drop_unfinished_line() {
+        let file = TempFile::new("audit_unfinished");
+        let mut log = Log::open(&file.0, IpPolicy::Full).unwrap();
+        fill(&mut log);
+        drop(log);
+        let lines = file.lines();
+
+        // Cut short by a crash while writing the last entry
+        let mut torn = lines[..2].join("\n") + "\n";
+        torn.push_str(&lines[2][..lines[2].len() / 2]);
+        std::fs::write(&file.0, torn).unwrap();
+        let summary = super::verify(&file.0).unwrap();
+        assert_eq!(summary.entries, 2);
+        assert!(summary.unfinished);
+
+        let mut log = Log::open(&file.0, IpPolicy::Full).unwrap();
+        assert_eq!(file.lines(), lines[..2]);
+        log.record(
+            Event::Created,
+            "other",
+            Some(5),
+            Some(at(200)),
+            Client::default(),
+            at(130),
+        )
+        .unwrap();
+        drop(log);
+
+        let summary = super::verify(&file.0).unwrap();
+        assert_eq!(summary.entries, 3);
+        assert!(!summary.unfinished);
+    }
+
+    #[test]
+    fn detect_tampering() {
+        let file = TempFile::new("audit_tampering");
+        let mut log = Log::open(&file.0, IpPolicy::Full).unwrap();
+        fill(&mut log);
+        drop(log);
+        let lines = file.lines();
+        assert_eq!(file.broken_at(), None);
+
+        // Edited
+        let mut edited = lines.clone();
+        edited[1] = edited[1].replace("\"size\":3", "\"size\":4");
+        file.rewrite(&edited);
+        assert_eq!(file.broken_at(), Some(2));
+
+        // Removed
+        file.rewrite(&[lines[0].clone(), lines[2].clone()]);
+        assert_eq!(file.broken_at(), Some(2));
+
+        // Reordered
+        file.rewrite(&[lines[1].clone(), lines[0].clone(), lines[2].clone()]);
+        assert_eq!(file.broken_at(), Some(1));
+
+        // Given an extra field
+        let mut extended = lines.clone();
+        extended[2] = extended[2].replacen('{', "{\"note\":1,", 1);
+        file.rewrite(&extended);
+        assert_eq!(file.broken_at(), Some(3));
+
+        // A broken log is not appended to
+        assert!(matches!(
+            Log::open(&file.0, IpPolicy::Full),
+            Err(Error::Broken { line: 3, .. })
+        ));
+    }
+}
@@ -0,0 +1,335 @@
+pub mod access;
+pub mod audit;
+
+// Target of the per-request records, which the JSON format renders only from their fields
+pub const ACCESS: &str = "access";
+
+pub fn init(options: &crate::options::Options) {
+    let level = options.log_level.unwrap_or(
+        #[cfg(debug_assertions)]
+        log::LevelFilter::Debug,
+        #[cfg(not(debug_assertions))]
+        log::LevelFilter::Info,
+    );
+
+    let file = options.log_file.as_ref().map(|path| {
+        // Escape codes have no place in a file
+        colored::control::set_override(false);
+        RotatingFile::open(path.clone(), options.log_file_size, options.log_file_count)
+            .expect("Could not open log file")
+    });
+
+    match options.log_format {
+        crate::options::LogFormat::Text => init_text(level, file),
+        crate::options::LogFormat::Json => {
+            colored::control::set_override(false);
+
+            let output: Box<dyn std::io::Write + Send> = match file {
+                Some(file) => Box::new(file),
+                None => Box::new(std::io::stdout()),
+            };
+
+            log::set_max_level(level);
+            log::set_boxed_logger(Box::new(JsonLogger {
+                level,
+                output: std::sync::Mutex::new(output),
+            }))
+            .expect("Could not initialize logger");
+        }
+    }
+}
+
+fn init_text(level: log::LevelFilter, file: Option<RotatingFile>) {
+    let config = simplelog::ConfigBuilder::new()
+        .set_time_format_custom(time::macros::format_description!(
+            "[year]-[month]-[day]T[hour]:[minute]:[second]Z"
+        ))
+        .build();
+
+    if let Some(file) = file {
+        simplelog::WriteLogger::init(level, config, file).expect("Could not initialize logger");
+        return;
+    }
+
+    let color_choice = std::env::var("CLICOLOR_FORCE")
+        .ok()
+        .filter(|force| force != "0")
+        .map(|_| simplelog::ColorChoice::Always)
+        .or({
+            std::env::var("CLICOLOR")
+                .ok()
+                .filter(|clicolor| clicolor == "0")
+                .map(|_| simplelog::ColorChoice::Never)
+        })
+        .unwrap_or(simplelog::ColorChoice::Auto);
+
+    simplelog::TermLogger::init(level, config, simplelog::TerminalMode::Mixed, color_choice)
+        .expect("Could not initialize logger");
+}
+
+struct JsonLogger {
+    level: log::LevelFilter,
+    output: std::sync::Mutex<Box<dyn std::io::Write + Send>>,
+}
+
+impl log::Log for JsonLogger {
+    fn enabled(&self, metadata: &log::Metadata<'_>) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &log::Record<'_>) {
+        use std::io::Write;
+
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let line = to_json(record, time::OffsetDateTime::now_utc());
+        if let Ok(mut output) = self.output.lock() {
+            // There is nowhere left to report a failure to log
+            let _ = writeln!(output, "{line}");
+        }
+    }
+
+    fn flush(&self) {
+        use std::io::Write;
+
+        if let Ok(mut output) = self.output.lock() {
+            let _ = output.flush();
+        }
+    }
+}
+
+fn to_json(record: &log::Record<'_>, now: time::OffsetDateTime) -> serde_json::Value {
+    struct Visitor<'a>(&'a mut serde_json::Map<String, serde_json::Value>);
+
+    impl<'kvs> log::kv::VisitSource<'kvs> for Visitor<'_> {
+        fn visit_pair(
+            &mut self,
+            key: log::kv::Key<'kvs>,
+            value: log::kv::Value<'kvs>,
+        ) -> Result<(), log::kv::Error> {
+            let value = serde_json::to_value(value).map_err(log::kv::Error::boxed)?;
+            self.0.insert(key.to_string(), value);
+            Ok(())
+        }
+    }
+
+    let mut object = serde_json::Map::new();
+    object.insert(
+        String::from("timestamp"),
+        now.format(time::macros::format_description!(
+            "[year]-[month]-[day]T[hour]:[minute]:[second].[subsecond digits:3]Z"
+        ))
+        .unwrap_or_default()
+        .into(),
+    );
+    object.insert(String::from("level"), record.level().as_str().into());
+
+    if record.target() != ACCESS {
+        object.insert(String::from("target"), record.target().into());
+        object.insert(String::from("message"), record.args().to_string().into());
+    }
+
+    // A field that cannot be serialized is not worth losing the whole record for
+    let _ = record.key_values().visit(&mut Visitor(&mut object));
+
+    serde_json::Value::Object(object)
+}
+
+// Writes to a file, moving it to `<path>.1`, `<path>.2`, ... once it grows beyond `max_size`
+pub struct RotatingFile {
+    path: std::path::PathBuf,
+    file: std::fs::File,
+    size: u64,
+    max_size: u64,
+    keep: u8,
+    line_start: bool,
+}
+
+impl RotatingFile {
+    fn open(path: std::path::PathBuf, max_size: u64, keep: u8) -> std::io::Result<Self> {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)?;
+        let size = file.metadata()?.len();
+
+        Ok(Self {
+            path,
+            file,
+            size,
+            max_size,
+            keep,
+            line_start: true,
+        })
+    }
+
+    fn rotated(&self, index: u8) -> std::path::PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{index}"));
+        path.into()
+    }
+
+    fn rotate(&mut self) -> std::io::Result<()> {
+        if self.keep == 0 {
+            self.file.set_len(0)?;
+        } else {
+            for index in (1..self.keep).rev() {
+                let from = self.rotated(index);
+                if from.exists() {
+                    std::fs::rename(from, self.rotated(index + 1))?;
+                }
+            }
+            std::fs::rename(&self.path, self.rotated(1))?;
+        }
+
+        *self = Self::open(self.path.clone(), self.max_size, self.keep)?;
+        Ok(())
+    }
+}
+
+impl std::io::Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        // Records may arrive in pieces, so only rotate between lines
+        if self.line_start && self.size > 0 && self.size + buf.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        if written > 0 {
+            self.line_start = buf[written - 1] == b'\n';
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RotatingFile;
+
+    struct TempDir(std::path::PathBuf);
+
+    impl TempDir {
+        fn new(name: &'static str) -> Self {
+            let path = std::env::temp_dir().join(format!("passer_test_{name}"));
+            std::fs::create_dir(&path).unwrap();
+            Self(path)
+        }
+
+        fn get(&self) -> &std::path::PathBuf {
+            &self.0
+        }
+    }
+
+    impl std::ops::Drop for TempDir {
+        fn drop(&mut self) {
+            std::fs::remove_dir_all(&self.0).unwrap();
+        }
+    }
+
+    #[test]
+    fn json_message() {
+        let now = time::macros::datetime!(2023-10-14 12:34:56.789 UTC);
+        let record = log::Record::builder()
+            .level(log::Level::Warn)
+            .target("passer")
+            .args(format_args!("hello"))
+            .build();
+
+        assert_eq!(
+            super::to_json(&record, now).to_string(),
+            r#"{"timestamp":"2023-10-14T12:34:56.789Z","level":"WARN","target":"passer","message":"hello"}"#
+        );
+    }
+
+    #[test]
+    fn json_access() {
+        let now = time::macros::datetime!(2023-10-14 12:34:56.789 UTC);
+        let fields: &[(&str, log::kv::Value<'_>)] = &[
+            ("method", "GET".into()),
+            ("status", 404_u16.into()),
+            ("response_size", log::kv::Value::null()),
+            ("latency_ms", 1.5_f64.into()),
+        ];
+        let record = log::Record::builder()
+            .level(log::Level::Info)
+            .target(super::ACCESS)
+            .args(format_args!("ignored"))
+            .key_values(&fields)
+            .build();
+
+        assert_eq!(
+            super::to_json(&record, now).to_string(),
+            r#"{"timestamp":"2023-10-14T12:34:56.789Z","level":"INFO","method":"GET","status":404,"response_size":null,"latency_ms":1.5}"#
+        );
+    }
+
+    #[test]
+    fn rotate() {
+        use std::io::Write;
+
+        let dir = TempDir::new("rotate");
+        let path = dir.get().join("passer.log");
+        let mut file = RotatingFile::open(path.clone(), 10, 2).unwrap();
+
+        file.write_all(b"first\n").unwrap();
+        file.write_all(b"second\n").unwrap();
+        file.write_all(b"third\n").unwrap();
+        file.write_all(b"fourth\n").unwrap();
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "fourth\n");
+        assert_eq!(
+            std::fs::read_to_string(dir.get().join("passer.log.1")).unwrap(),
+            "third\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(dir.get().join("passer.log.2")).unwrap(),
+            "second\n"
+        );
+        assert!(!dir.get().join("passer.log.3").exists());
+    }
+
+    #[test]
+    fn rotate_between_lines() {
+        use std::io::Write;
+
+        let dir = TempDir::new("rotate_between_lines");
+        let path = dir.get().join("passer.log");
+        let mut file = RotatingFile::open(path.clone(), 10, 1).unwrap();
+
+        file.write_all(b"first").unwrap();
+        file.write_all(b" line\n").unwrap();
+        file.write_all(b"second").unwrap();
+        file.write_all(b" line\n").unwrap();
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "second line\n");
+        assert_eq!(
+            std::fs::read_to_string(dir.get().join("passer.log.1")).unwrap(),
+            "first line\n"
+        );
+    }
+
+    #[test]
+    fn resume_size() {
+        use std::io::Write;
+
+        let dir = TempDir::new("resume_size");
+        let path = dir.get().join("passer.log");
+        std::fs::write(&path, b"previous\n").unwrap();
+
+        let mut file = RotatingFile::open(path.clone(), 10, 1).unwrap();
+        file.write_all(b"next\n").unwrap();
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "next\n");
+        assert_eq!(
+            std::fs::read_to_string(dir.get().join("passer.log.1")).unwrap(),
+            "previous\n"
+        );
+    }
+}
@@ -1,57 +1,152 @@
 #![deny(warnings, clippy::pedantic, clippy::all)]
 #![warn(rust_2018_idioms)]
 
+mod listener;
+mod logger;
 mod options;
 mod server;
 mod store;
+mod systemd;
 
-fn init_logger() {
-    let config = simplelog::ConfigBuilder::new()
-        .set_time_format_custom(time::macros::format_description!(
-            "[year]-[month]-[day]T[hour]:[minute]:[second]Z"
-        ))
-        .build();
-
-    let color_choice = std::env::var("CLICOLOR_FORCE")
-        .ok()
-        .filter(|force| force != "0")
-        .map(|_| simplelog::ColorChoice::Always)
-        .or({
-            std::env::var("CLICOLOR")
-                .ok()
-                .filter(|clicolor| clicolor == "0")
-                .map(|_| simplelog::ColorChoice::Never)
-        })
-        .unwrap_or(simplelog::ColorChoice::Auto);
-
-    simplelog::TermLogger::init(
-        #[cfg(debug_assertions)]
-        simplelog::LevelFilter::Debug,
-        #[cfg(not(debug_assertions))]
-        simplelog::LevelFilter::Info,
-        config,
-        simplelog::TerminalMode::Mixed,
-        color_choice,
-    )
-    .expect("Could not initialize logger");
+async fn shutdown_signal() {
+    let Ok(mut terminate) =
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+    else {
+        log::warn!("Could not listen for termination signals");
+        return std::future::pending().await;
+    };
+
+    tokio::select! {
+        _ = terminate.recv() => {}
+        _ = tokio::signal::ctrl_c() => {}
+    }
 }
 
 fn main() {
     let options = options::parse();
-    init_logger();
+    if let Some(options::Command::VerifyAudit { path }) = &options.command {
+        match logger::audit::verify(path) {
+            Ok(summary) => {
+                println!("{} entries, last hash {}", summary.entries, summary.head);
+                if summary.unfinished {
+                    println!(
+                        "Ignored an unfinished last line, dropped when the log is next opened"
+                    );
+                }
+            }
+            Err(e) => {
+                eprintln!("Audit log is not intact: {e}");
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
 
-    if let Err(e) = if options.threads > 0 {
-        let threads = usize::from(options.threads);
+    let activated = systemd::take_listen_fds();
+    logger::init(&options);
+
+    let mut runtime = tokio::runtime::Builder::new_multi_thread();
+    if options.threads > 0 {
         log::info!("Core threads set to {}", options.threads);
-        gotham::start_with_num_threads(
-            format!("0.0.0.0:{}", options.port),
-            server::route(options),
-            threads,
-        )
+        runtime.worker_threads(usize::from(options.threads));
     } else {
         log::info!("Core threads set to automatic");
-        gotham::start(format!("0.0.0.0:{}", options.port), server::route(options))
-    } {
+    }
+
+    let runtime = match runtime.enable_all().build() {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            log::error!("Error: {e}");
+            return;
+        }
+    };
+
+    // Inherited sockets replace the default address, but not the explicitly requested ones
+    let addresses = if activated.public.is_empty() {
+        options.addresses()
+    } else {
+        log::info!(
+            "Using {} sockets from the service manager",
+            activated.public.len()
+        );
+        options.listen.clone()
+    };
+    if !activated.admin.is_empty() {
+        log::info!(
+            "Using {} admin sockets from the service manager",
+            activated.admin.len()
+        );
+    }
+
+    let admin_addresses = options.admin_listen.clone();
+    let notifier = systemd::Notifier::from_env();
+    let limits = listener::Limits {
+        header_timeout: std::time::Duration::from_secs(options.header_timeout),
+        idle_timeout: std::time::Duration::from_secs(options.idle_timeout),
+    };
+
+    let result = runtime.block_on(async {
+        let admin_activated = !activated.admin.is_empty();
+        let mut listeners = activated
+            .public
+            .into_iter()
+            .map(listener::Listener::from_fd)
+            .collect::<Result<Vec<_>, _>>()?;
+        listeners.extend(listener::bind(&addresses).await?);
+        let mut admin_listeners = activated
+            .admin
+            .into_iter()
+            .map(listener::Listener::from_fd)
+            .collect::<Result<Vec<_>, _>>()?;
+        admin_listeners.extend(listener::bind(&admin_addresses).await?);
+
+        let routers = server::route(options, admin_activated)?;
+        let connections = routers.connections;
+        let closed: listener::Closed =
+            std::sync::Arc::new(move |peer, error, age| connections.closed(peer, error, age));
+        if let Some(router) = routers.admin {
+            tokio::spawn(listener::serve(
+                admin_listeners,
+                router,
+                limits,
+                closed.clone(),
+            ));
+        }
+
+        if let Some(notifier) = &notifier {
+            notifier.ready();
+        }
+
+        tokio::select! {
+            () = listener::serve(listeners, routers.public, limits, closed) => {}
+            () = watchdog(notifier.as_ref()) => {}
+            () = shutdown_signal() => log::info!("Shutting down"),
+        }
+
+        Ok::<_, std::io::Error>(())
+    });
+    if let Err(e) = &result {
         log::error!("Error: {e}");
     }
+
+    if let Some(notifier) = notifier {
+        notifier.stopping();
+    }
+    if result.is_err() {
+        std::process::exit(1);
+    }
+}
+
+async fn watchdog(notifier: Option<&systemd::Notifier>) {
+    let (Some(notifier), Some(interval)) = (notifier, systemd::Notifier::watchdog_interval())
+    else {
+        return std::future::pending().await;
+    };
+
+    log::info!("Sending watchdog heartbeats every {interval:?}");
+    let mut interval = tokio::time::interval(interval);
+    loop {
+        interval.tick().await;
+        notifier.watchdog();
+    }
 }
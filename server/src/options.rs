@@ -2,19 +2,257 @@ pub fn parse() -> Options {
     <Options as clap::Parser>::parse()
 }
 
+impl Options {
+    pub fn addresses(&self) -> Vec<crate::listener::Address> {
+        if self.listen.is_empty() {
+            vec![crate::listener::Address::Tcp(
+                (std::net::Ipv4Addr::UNSPECIFIED, self.port).into(),
+            )]
+        } else {
+            self.listen.clone()
+        }
+    }
+}
+
 #[derive(clap::Parser)]
 pub struct Options {
-    /// Selects the port to serve on
-    #[clap(short, long, default_value = "80")]
+    /// Selects the port to serve on all IPv4 interfaces
+    ///
+    /// Ignored if addresses are given with "--listen"
+    #[clap(short, long, default_value = "80", conflicts_with = "listen")]
     pub port: u16,
 
+    /// Selects the addresses to serve on. Can be repeated
+    ///
+    /// Accepts IPv4 (`127.0.0.1:80`), IPv6 (`[::1]:80`) or Unix domain
+    /// sockets with optional octal permissions (`unix:/run/passer.sock:660`)
+    #[clap(short, long)]
+    pub listen: Vec<crate::listener::Address>,
+
     /// Selects the number of threads to use. Zero for automatic
     #[clap(short, long, default_value = "0")]
     pub threads: u8,
 
-    /// Sets the 'allow-origin' header
-    #[clap(short, long, value_parser = to_cors)]
-    pub cors: Option<gotham::hyper::header::HeaderValue>,
+    /// Seconds a client has to send the headers of a request
+    #[clap(long, default_value = "10")]
+    pub header_timeout: u64,
+
+    /// Seconds an upload may pause before it is aborted
+    #[clap(long, default_value = "10")]
+    pub body_timeout: u64,
+
+    /// Slowest accepted upload, in bytes per second. Zero to accept any rate
+    ///
+    /// Uploads may take as long as their Content-Length needs at this rate, on top
+    /// of "--body-timeout"
+    #[clap(long, default_value = "8192")]
+    pub min_upload_rate: u64,
+
+    /// Seconds a connection may stay idle before it is closed
+    #[clap(long, default_value = "60")]
+    pub idle_timeout: u64,
+
+    /// Serves Prometheus metrics on "/metrics"
+    #[clap(short, long)]
+    pub metrics: bool,
+
+    /// Serves the metrics and health checks on these addresses instead of together
+    /// with the API. Can be repeated
+    ///
+    /// Meant for addresses only reachable by operators, such as the loopback
+    /// interface or a Unix domain socket. Implies "--metrics"
+    #[clap(long, alias = "metrics-listen")]
+    pub admin_listen: Vec<crate::listener::Address>,
+
+    /// Serves the admin API to clients presenting this bearer token
+    ///
+    /// The API is served under "/admin" of the admin addresses, or together with
+    /// the API when there are none. Clients of Unix domain sockets given in
+    /// "--admin-listen" are trusted without a token
+    #[clap(long, env = "PASSER_ADMIN_TOKEN", hide_env_values = true)]
+    pub admin_token: Option<String>,
+
+    /// Requires uploads to carry one of these API keys as a bearer token. Can be repeated
+    ///
+    /// Given as `<label>:<sha256>`, where the hash is the hex SHA-256 of the key,
+    /// such as from `printf %s "$KEY" | sha256sum`. The label names the key in the
+    /// logs. Secrets can be read without a key
+    #[clap(long)]
+    pub api_key: Vec<crate::server::ApiKey>,
+
+    /// Lets uploaders sign in with this `OpenID` Connect provider
+    ///
+    /// API clients send a token issued by the provider as a bearer token. With
+    /// "--oidc-redirect-url", the front-end signs in through the browser instead.
+    /// API keys are still accepted
+    #[clap(long, requires = "oidc_client_id")]
+    pub oidc_issuer: Option<String>,
+
+    /// Identifies the server to the provider
+    #[clap(long, requires = "oidc_issuer")]
+    pub oidc_client_id: Option<String>,
+
+    /// Authenticates the server to the provider when signing in through the browser
+    #[clap(long, env = "PASSER_OIDC_CLIENT_SECRET", hide_env_values = true)]
+    pub oidc_client_secret: Option<String>,
+
+    /// Accepts bearer tokens meant for this audience. Can be repeated. Defaults to the client id
+    #[clap(long, requires = "oidc_issuer")]
+    pub oidc_audience: Vec<String>,
+
+    /// Asks for these scopes when signing in through the browser
+    #[clap(long, value_delimiter = ',', default_value = "openid")]
+    pub oidc_scopes: Vec<String>,
+
+    /// Only lets in members of this group. Can be repeated
+    #[clap(long, requires = "oidc_issuer")]
+    pub oidc_group: Vec<String>,
+
+    /// The claim listing the groups of a user
+    #[clap(long, default_value = "groups")]
+    pub oidc_groups_claim: String,
+
+    /// The URL of "/auth/callback" on this server, as registered with the provider
+    ///
+    /// Enables signing in through the browser, which keeps users signed in with a cookie
+    #[clap(long, requires = "oidc_issuer")]
+    pub oidc_redirect_url: Option<String>,
+
+    /// Signs the session cookies with this secret instead of a random one
+    ///
+    /// Keeps users signed in over restarts and across instances sharing it
+    #[clap(long, env = "PASSER_OIDC_SESSION_SECRET", hide_env_values = true)]
+    pub oidc_session_secret: Option<String>,
+
+    /// Most secrets a client may have stored at once
+    ///
+    /// Clients are told apart by their API key, or by their address when uploading
+    /// without one. Applies to every "--quota-*" option
+    #[clap(long)]
+    pub quota_secrets: Option<usize>,
+
+    /// Most bytes a client may have stored at once
+    #[clap(long)]
+    pub quota_bytes: Option<u64>,
+
+    /// Longest time a client may keep secrets for, in `<amount>[m|h|d]` format
+    #[clap(long, value_parser = crate::server::parse_ttl)]
+    pub quota_ttl: Option<std::time::Duration>,
+
+    /// Most secrets a client may create, in `<count>/<s|m|h|d>` format such as `10/m`
+    ///
+    /// Clients are told apart like for the quotas, and may spend the whole count at once.
+    /// Rejected requests are answered with `429 Too Many Requests` and `Retry-After`
+    #[clap(long)]
+    pub create_rate: Option<crate::server::rate::Rate>,
+
+    /// Most secrets a client may retrieve, in `<count>/<s|m|h|d>` format such as `60/m`
+    #[clap(long)]
+    pub retrieve_rate: Option<crate::server::rate::Rate>,
+
+    /// Bans clients from retrieving secrets after this many lookups of missing ones
+    ///
+    /// The lookups must happen within "--ban-time", which is also how long the ban lasts
+    #[clap(long)]
+    pub ban_after: Option<u32>,
+
+    /// How long clients are banned for, in `<amount>[m|h|d]` format
+    #[clap(long, default_value = "15m", value_parser = crate::server::parse_ttl)]
+    pub ban_time: std::time::Duration,
+
+    /// Asks uploads without an API key to solve a puzzle of this many bits first
+    ///
+    /// Each bit doubles the work. Larger uploads and a fuller store ask for more bits,
+    /// up to 26. Challenges are fetched from "/v2/challenge" and solved in the
+    /// "X-Proof-Of-Work" header, which cross-origin requests are allowed to send
+    #[clap(long, value_parser = clap::value_parser!(u8).range(..=26))]
+    pub proof_of_work: Option<u8>,
+
+    /// Signs the proof of work challenges with this secret instead of a random one
+    ///
+    /// Needed when several instances share the clients, so that any of them can check the
+    /// challenges issued by the others
+    #[clap(long, env = "PASSER_POW_SECRET", hide_env_values = true)]
+    pub proof_of_work_secret: Option<String>,
+
+    /// Hosts that senders may ask to be notified at, as `host` or `host:port`
+    ///
+    /// Uploads may then give a callback URL, which is sent a signed `POST` when the secret
+    /// is read or expires unread. Callbacks must use HTTPS, unless the host is given as
+    /// `http://host`, such as a relay on the local network
+    #[clap(long, value_delimiter = ',', requires = "webhook_secret")]
+    pub webhook_hosts: Vec<crate::server::webhook::Host>,
+
+    /// Signs the webhooks with this secret, which receivers use to check them
+    #[clap(long, env = "PASSER_WEBHOOK_SECRET", hide_env_values = true)]
+    pub webhook_secret: Option<String>,
+
+    /// Times a webhook is sent before giving up, with a growing delay in between
+    #[clap(long, default_value = "5", value_parser = clap::value_parser!(u32).range(1..))]
+    pub webhook_attempts: u32,
+
+    /// Trusts the forwarding headers of proxies in this network. Can be repeated
+    ///
+    /// Given in CIDR notation (`10.0.0.0/8`, `fd00::/8`) or as a single address.
+    /// The client address is taken from `Forwarded` or `X-Forwarded-For` only when
+    /// the request comes from a trusted proxy. Clients on Unix domain sockets
+    /// have the address `0.0.0.0`
+    #[clap(long)]
+    pub trusted_proxy: Vec<crate::server::proxy::Cidr>,
+
+    /// Longest time secrets can be kept for, in `<amount>[m|h|d]` format. Unlimited by default
+    #[clap(long, value_parser = crate::server::parse_ttl)]
+    pub max_ttl: Option<std::time::Duration>,
+
+    /// Allows cross-origin requests from this origin. Can be repeated
+    ///
+    /// The origin of a request is echoed in the 'allow-origin' header when it is
+    /// allowed. `*` allows any origin
+    #[clap(short, long, value_parser = to_header_value)]
+    pub cors: Vec<gotham::hyper::header::HeaderValue>,
+
+    /// Methods allowed in cross-origin requests
+    #[clap(
+        long,
+        value_delimiter = ',',
+        default_value = "GET,POST",
+        requires = "cors"
+    )]
+    pub cors_methods: Vec<gotham::hyper::Method>,
+
+    /// Headers allowed in cross-origin requests
+    #[clap(
+        long,
+        value_delimiter = ',',
+        default_value = "content-type",
+        requires = "cors"
+    )]
+    pub cors_headers: Vec<gotham::hyper::header::HeaderName>,
+
+    /// Response headers exposed to cross-origin requests
+    #[clap(long, value_delimiter = ',', requires = "cors")]
+    pub cors_expose_headers: Vec<gotham::hyper::header::HeaderName>,
+
+    /// Seconds for which browsers may cache the answer to a preflight request
+    #[clap(long, requires = "cors")]
+    pub cors_max_age: Option<u32>,
+
+    /// Sets the Content-Security-Policy of the front-end pages
+    ///
+    /// The default allows the WebAssembly of the front-end to be compiled
+    #[clap(
+        long,
+        value_parser = to_header_value,
+        default_value = "default-src 'self'; script-src 'self' 'wasm-unsafe-eval'; img-src 'self' data:; object-src 'none'; base-uri 'none'; form-action 'self'; frame-ancestors 'none'"
+    )]
+    pub content_security_policy: gotham::hyper::header::HeaderValue,
+
+    /// Sends Strict-Transport-Security with this max-age in seconds
+    ///
+    /// Only enable when clients reach the server over TLS, such as through a
+    /// terminating proxy
+    #[clap(long)]
+    pub hsts: Option<u64>,
 
     /// Sets storage location
     ///
@@ -22,20 +260,148 @@ pub struct Options {
     #[clap(short, long, value_parser = clap::builder::TypedValueParser::try_map(clap::builder::PathBufValueParser::new(), to_dir_path))]
     pub store_path: Option<std::path::PathBuf>,
 
+    /// Selects the log format
+    #[clap(long, value_enum, default_value = "text")]
+    pub log_format: LogFormat,
+
+    /// Selects the log level
+    ///
+    /// Defaults to "debug" on debug builds and to "info" otherwise
+    #[clap(long)]
+    pub log_level: Option<log::LevelFilter>,
+
+    /// Writes the logs to a file instead of the terminal
+    #[clap(long)]
+    pub log_file: Option<std::path::PathBuf>,
+
+    /// Size in bytes after which the log file is rotated
+    #[clap(long, default_value = "10485760", requires = "log_file")]
+    pub log_file_size: u64,
+
+    /// Number of rotated log files to keep
+    #[clap(long, default_value = "5", requires = "log_file")]
+    pub log_file_count: u8,
+
+    /// Appends the lifecycle of every secret to this tamper-evident audit log
+    ///
+    /// Each line holds the hash of the one before it, which "passer verify-audit"
+    /// checks. Ids are hashed, and contents are never written
+    #[clap(long)]
+    pub audit_log: Option<std::path::PathBuf>,
+
+    /// Selects how client addresses are written to the audit log
+    #[clap(long, value_enum, default_value = "truncate", requires = "audit_log")]
+    pub audit_client_ip: IpPolicy,
+
+    /// Selects how secret ids in request paths are logged
+    ///
+    /// Also applies to the values of query parameters other than "ttl", "size" and "scope",
+    /// such as callback URLs
+    #[clap(long, value_enum, default_value = "redact")]
+    pub log_ids: IdPolicy,
+
+    /// Selects how client addresses are logged
+    ///
+    /// Truncating keeps the /24 network for IPv4 and the /48 network for IPv6
+    #[clap(long, value_enum, default_value = "full")]
+    pub log_client_ip: IpPolicy,
+
+    /// Selects the fields logged for a route. Can be repeated
+    ///
+    /// Given as `<route>=<template>`, where the template contains `{field}`
+    /// placeholders. The route `*` applies to routes without a template and an
+    /// empty template disables logging for the route.
+    ///
+    /// Routes: create, retrieve, `create_v2`, `retrieve_v2`, openapi, preflight, index,
+    /// config, challenge, login, `login_callback`, metrics, health, ready, `admin_stats`,
+    /// `admin_revoke`, `admin_purge`, `admin_maintenance`, unmatched, and connection
+    /// for the connections closed for "--header-timeout" or "--idle-timeout"
+    ///
+    /// Fields: `client_ip`, forwarded, key, method, path, route, status, `request_size`,
+    /// `response_size`, latency, error
+    #[clap(long)]
+    pub log_template: Vec<crate::logger::access::Template>,
+
+    /// Serves everything under this path instead of the root, such as `/passer`
+    ///
+    /// The front-end must be built with the same path in `PUBLIC_URL`
+    #[clap(long, value_parser = to_base_path)]
+    pub base_path: Option<String>,
+
     /// The directory of the front-end content
     ///
     /// If set, the front-end will be served on the root path "/"
     /// and the api will be nested under "/api"
     #[clap(short, long, value_parser = clap::builder::TypedValueParser::try_map(clap::builder::PathBufValueParser::new(), to_index_root))]
     pub web_path: Option<(std::path::PathBuf, std::path::PathBuf)>,
+
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Copy, Clone, clap::ValueEnum)]
+pub enum LogFormat {
+    /// Human readable lines
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+#[derive(clap::Subcommand)]
+pub enum Command {
+    /// Checks that an audit log was not edited, and prints its last hash
+    ///
+    /// Keep the last hash somewhere else to also tell when lines were removed from the end
+    VerifyAudit { path: std::path::PathBuf },
 }
 
-fn to_cors(
+#[derive(Copy, Clone, clap::ValueEnum)]
+pub enum IdPolicy {
+    /// Replaces ids with a placeholder
+    Redact,
+    /// Replaces ids with a short hash, so that requests for the same secret can be related
+    Hash,
+    /// Logs ids as they are
+    Full,
+}
+
+#[derive(Copy, Clone, clap::ValueEnum)]
+pub enum IpPolicy {
+    /// Logs addresses as they are
+    Full,
+    /// Logs only the network of the address
+    Truncate,
+    /// Does not log addresses
+    Drop,
+}
+
+fn to_header_value(
     value: &str,
 ) -> Result<gotham::hyper::header::HeaderValue, gotham::hyper::header::InvalidHeaderValue> {
     gotham::hyper::header::HeaderValue::from_str(value)
 }
 
+fn to_base_path(path: &str) -> Result<String, &'static str> {
+    if !path.starts_with('/') {
+        return Err("path must start with a slash");
+    }
+
+    let path = path.trim_end_matches('/');
+    if path.is_empty() {
+        return Err("the root is already the default");
+    }
+
+    if path
+        .split('/')
+        .skip(1)
+        .any(|segment| segment.is_empty() || segment.starts_with([':', '*']))
+    {
+        return Err("path must be made of non-empty literal segments");
+    }
+
+    Ok(String::from(path))
+}
+
 fn to_dir_path(path: std::path::PathBuf) -> Result<std::path::PathBuf, &'static str> {
     if !path.is_dir() {
         return Err("path is not a directory");
@@ -0,0 +1,256 @@
+use gotham::hyper;
+
+#[cfg(feature = "host-frontend")]
+mod files {
+    include!(concat!(env!("OUT_DIR"), "/web.rs"));
+}
+
+// The front-end files, served from memory
+#[derive(Clone)]
+pub struct Assets(std::sync::Arc<std::collections::HashMap<&'static str, Asset>>);
+
+struct Asset {
+    content: &'static [u8],
+    etag: hyper::header::HeaderValue,
+}
+
+impl Assets {
+    #[cfg(feature = "host-frontend")]
+    pub fn embedded() -> Self {
+        Self::new(files::FILES)
+    }
+
+    pub fn new(files: &'static [(&'static str, &'static [u8])]) -> Self {
+        Self(std::sync::Arc::new(
+            files
+                .iter()
+                .map(|(name, content)| {
+                    let etag =
+                        hyper::header::HeaderValue::from_str(&format!("\"{}\"", etag(content)))
+                            .unwrap_or_else(|_| hyper::header::HeaderValue::from_static("\"\""));
+                    (*name, Asset { content, etag })
+                })
+                .collect(),
+        ))
+    }
+
+    // Responds with the file, preferring the precompressed variants accepted by the client
+    pub fn respond(
+        &self,
+        headers: &hyper::HeaderMap,
+        name: &str,
+        cache_control: &'static str,
+    ) -> Option<hyper::Response<hyper::Body>> {
+        use hyper::header;
+
+        let asset = self.0.get(name)?;
+        let (asset, encoding) = [("br", "br"), ("gzip", "gz")]
+            .into_iter()
+            .filter(|(encoding, _)| accepts(headers, encoding))
+            .find_map(|(encoding, extension)| {
+                self.0
+                    .get(format!("{name}.{extension}").as_str())
+                    .map(|asset| (asset, Some(encoding)))
+            })
+            .unwrap_or((asset, None));
+
+        let not_modified = headers
+            .get_all(header::IF_NONE_MATCH)
+            .iter()
+            .any(|etag| etag == asset.etag);
+
+        let mut response = hyper::Response::builder()
+            .header(header::CACHE_CONTROL, cache_control)
+            .header(header::ETAG, asset.etag.clone());
+
+        if not_modified {
+            return response
+                .status(hyper::StatusCode::NOT_MODIFIED)
+                .body(hyper::Body::empty())
+                .ok();
+        }
+
+        if let Some(encoding) = encoding {
+            response = response.header(header::CONTENT_ENCODING, encoding);
+        }
+        response
+            .status(hyper::StatusCode::OK)
+            .header(header::CONTENT_TYPE, content_type(name))
+            .header(header::CONTENT_LENGTH, asset.content.len())
+            .body(hyper::Body::from(asset.content))
+            .ok()
+    }
+}
+
+fn etag(content: &[u8]) -> String {
+    use sha2::Digest;
+    use std::fmt::Write;
+
+    sha2::Sha256::digest(content)[..16]
+        .iter()
+        .fold(String::with_capacity(32), |mut hex, byte| {
+            write!(hex, "{byte:02x}").unwrap();
+            hex
+        })
+}
+
+fn accepts(headers: &hyper::HeaderMap, encoding: &str) -> bool {
+    headers
+        .get_all(hyper::header::ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|item| {
+            let mut params = item.split(';');
+            let name = params.next().unwrap_or_default().trim();
+            let refused = params.any(|param| {
+                param
+                    .trim()
+                    .strip_prefix("q=")
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .is_some_and(|q| q <= 0.0)
+            });
+            (name.eq_ignore_ascii_case(encoding) || name == "*") && !refused
+        })
+}
+
+fn content_type(name: &str) -> &'static str {
+    match std::path::Path::new(name)
+        .extension()
+        .and_then(std::ffi::OsStr::to_str)
+    {
+        Some("html") => "text/html; charset=utf-8",
+        Some("js") => "text/javascript; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("json" | "map") => "application/json",
+        Some("txt") => "text/plain; charset=utf-8",
+        Some("wasm") => "application/wasm",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("ico") => "image/x-icon",
+        Some("woff2") => "font/woff2",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use gotham::hyper;
+    use gotham::test::TestServer;
+
+    static FILES: &[(&str, &[u8])] = &[
+        ("index.html", b"main_page"),
+        ("foo.txt", b"bar"),
+        ("static/main.0123abcd.js", b"hashed"),
+        ("static/main.0123abcd.js.gz", b"gzipped"),
+        ("static/main.0123abcd.js.br", b"brotli"),
+        ("module.wasm", b"\0asm"),
+    ];
+
+    fn server() -> TestServer {
+        let index = super::super::handler::Index::Embedded(super::Assets::new(FILES));
+
+        TestServer::new(gotham::router::builder::build_simple_router(|route| {
+            use gotham::router::builder::DefineSingleRoute;
+            use gotham::router::builder::DrawRoutes;
+
+            route
+                .get("/*")
+                .with_path_extractor::<super::super::handler::AssetPath>()
+                .to_new_handler(index.clone());
+            route.get("/").to_new_handler(index);
+        }))
+        .unwrap()
+    }
+
+    fn get(
+        server: &TestServer,
+        path: &str,
+        encoding: Option<&'static str>,
+    ) -> gotham::test::TestResponse {
+        let client = server.client();
+        let mut request = client.get(format!("http://localhost/{path}"));
+        if let Some(encoding) = encoding {
+            request = request.with_header(
+                hyper::header::ACCEPT_ENCODING,
+                hyper::header::HeaderValue::from_static(encoding),
+            );
+        }
+        request.perform().unwrap()
+    }
+
+    #[test]
+    fn index() {
+        let server = server();
+        for path in ["", "d/", "howitworks"] {
+            let response = get(&server, path, None);
+            assert_eq!(response.status(), hyper::StatusCode::OK);
+            assert_eq!(
+                response.headers()[hyper::header::CONTENT_TYPE],
+                "text/html; charset=utf-8"
+            );
+            assert_eq!(response.headers()[hyper::header::CACHE_CONTROL], "no-cache");
+            assert_eq!(&response.read_body().unwrap()[..], b"main_page");
+        }
+    }
+
+    #[test]
+    fn files() {
+        let server = server();
+
+        let response = get(&server, "foo.txt", None);
+        assert_eq!(response.status(), hyper::StatusCode::OK);
+        assert_eq!(&response.read_body().unwrap()[..], b"bar");
+
+        let response = get(&server, "module.wasm", None);
+        assert_eq!(
+            response.headers()[hyper::header::CONTENT_TYPE],
+            "application/wasm"
+        );
+
+        let response = get(&server, "static/missing.js", None);
+        assert_eq!(response.status(), hyper::StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn precompressed() {
+        let server = server();
+
+        let response = get(&server, "static/main.0123abcd.js", None);
+        assert_eq!(
+            response.headers()[hyper::header::CACHE_CONTROL],
+            "public, max-age=31536000, immutable"
+        );
+        assert!(
+            !response
+                .headers()
+                .contains_key(hyper::header::CONTENT_ENCODING)
+        );
+        assert_eq!(&response.read_body().unwrap()[..], b"hashed");
+
+        let response = get(&server, "static/main.0123abcd.js", Some("gzip, br"));
+        assert_eq!(response.headers()[hyper::header::CONTENT_ENCODING], "br");
+        assert_eq!(response.headers()[hyper::header::VARY], "accept-encoding");
+        assert_eq!(&response.read_body().unwrap()[..], b"brotli");
+
+        let response = get(&server, "static/main.0123abcd.js", Some("gzip, br;q=0"));
+        assert_eq!(response.headers()[hyper::header::CONTENT_ENCODING], "gzip");
+        assert_eq!(&response.read_body().unwrap()[..], b"gzipped");
+    }
+
+    #[test]
+    fn not_modified() {
+        let server = server();
+
+        let response = get(&server, "foo.txt", None);
+        let etag = response.headers()[hyper::header::ETAG].clone();
+
+        let response = server
+            .client()
+            .get("http://localhost/foo.txt")
+            .with_header(hyper::header::IF_NONE_MATCH, etag)
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), hyper::StatusCode::NOT_MODIFIED);
+    }
+}
@@ -10,8 +10,38 @@ pub enum Error {
     ContentLengthMissing,
     #[error("payload too large")]
     PayloadTooLarge,
+    #[error("ttl longer than {}m", .0.as_secs() / 60)]
+    TtlTooLong(std::time::Duration),
     #[error("read timeout")]
     ReadTimeout,
+    #[error("upload slower than {0} bytes/s")]
+    TooSlow(u64),
+    #[error("no request head within {}s", .0.as_secs())]
+    HeaderTimeout(std::time::Duration),
+    #[error("idle for {}s", .0.as_secs())]
+    IdleTimeout(std::time::Duration),
+    #[error("invalid body: {0}")]
+    InvalidBody(String),
+    #[error("uploads are disabled for maintenance")]
+    ReadOnly,
+    #[error("more than {0} outstanding secrets")]
+    SecretQuota(usize),
+    #[error("more than {0} outstanding bytes")]
+    ByteQuota(u64),
+    #[error("ttl longer than the {}m allowed to this client", .0.as_secs() / 60)]
+    TtlQuota(std::time::Duration),
+    #[error("missing or invalid token")]
+    Unauthorized,
+    #[error("could not sign in: {0}")]
+    SignIn(super::oidc::Invalid),
+    #[error("invalid proof of work: {0}")]
+    ProofOfWork(super::pow::Invalid),
+    #[error("invalid callback: {0}")]
+    Callback(super::webhook::Refused),
+    #[error("too many requests, retry in {}s", retry_after(.0))]
+    RateLimited(std::time::Duration),
+    #[error("too many missing secrets, retry in {}s", retry_after(.0))]
+    Banned(std::time::Duration),
     #[error("{0}")]
     Hyper(gotham::hyper::Error),
     #[error("{0}")]
@@ -19,16 +49,32 @@ pub enum Error {
 }
 
 impl Error {
-    fn status_code(&self) -> gotham::hyper::StatusCode {
+    pub fn status_code(&self) -> gotham::hyper::StatusCode {
+        use super::oidc;
         use super::store::Error as StoreError;
         use gotham::hyper::StatusCode;
 
         match self {
             Error::Store(StoreError::SecretNotFound) => StatusCode::NOT_FOUND,
-            Error::Store(StoreError::InvalidId(_)) => StatusCode::BAD_REQUEST,
+            Error::Store(StoreError::InvalidId(_))
+            | Error::TtlTooLong(_)
+            | Error::InvalidBody(_)
+            | Error::Callback(_) => StatusCode::BAD_REQUEST,
             Error::ContentLengthMissing => StatusCode::LENGTH_REQUIRED,
             Error::NothingToInsert => StatusCode::UNPROCESSABLE_ENTITY,
-            Error::ReadTimeout => StatusCode::REQUEST_TIMEOUT,
+            Error::ReadTimeout
+            | Error::TooSlow(_)
+            | Error::HeaderTimeout(_)
+            | Error::IdleTimeout(_) => StatusCode::REQUEST_TIMEOUT,
+            Error::TtlQuota(_) | Error::ProofOfWork(_) | Error::SignIn(oidc::Invalid::Group) => {
+                StatusCode::FORBIDDEN
+            }
+            Error::SignIn(oidc::Invalid::Provider(_)) => StatusCode::BAD_GATEWAY,
+            Error::Unauthorized | Error::SignIn(_) => StatusCode::UNAUTHORIZED,
+            Error::SecretQuota(_)
+            | Error::ByteQuota(_)
+            | Error::RateLimited(_)
+            | Error::Banned(_) => StatusCode::TOO_MANY_REQUESTS,
             Error::Store(StoreError::StoreFull) => StatusCode::CONFLICT,
             Error::Store(StoreError::TooLarge) | Error::PayloadTooLarge => {
                 StatusCode::PAYLOAD_TOO_LARGE
@@ -36,6 +82,52 @@ impl Error {
             Error::Hyper(_)
             | Error::FailedToAcquireStore
             | Error::Store(StoreError::Generic(_)) => StatusCode::INTERNAL_SERVER_ERROR,
+            Error::ReadOnly => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+
+    // Stable identifier of the variant, for metrics and clients
+    pub fn code(&self) -> &'static str {
+        use super::oidc;
+        use super::store::Error as StoreError;
+
+        match self {
+            Error::NothingToInsert => "nothing_to_insert",
+            Error::FailedToAcquireStore => "failed_to_acquire_store",
+            Error::ContentLengthMissing => "content_length_missing",
+            Error::PayloadTooLarge => "payload_too_large",
+            Error::TtlTooLong(_) => "ttl_too_long",
+            Error::ReadTimeout => "read_timeout",
+            Error::TooSlow(_) => "too_slow",
+            Error::HeaderTimeout(_) => "header_timeout",
+            Error::IdleTimeout(_) => "idle_timeout",
+            Error::InvalidBody(_) => "invalid_body",
+            Error::ReadOnly => "read_only",
+            Error::Unauthorized => "unauthorized",
+            Error::SignIn(oidc::Invalid::Group) => "not_in_group",
+            Error::SignIn(oidc::Invalid::Provider(_)) => "provider_unavailable",
+            Error::SignIn(_) => "sign_in_failed",
+            Error::SecretQuota(_) => "secret_quota",
+            Error::ByteQuota(_) => "byte_quota",
+            Error::TtlQuota(_) => "ttl_quota",
+            Error::ProofOfWork(_) => "proof_of_work",
+            Error::Callback(_) => "invalid_callback",
+            Error::RateLimited(_) => "rate_limited",
+            Error::Banned(_) => "banned",
+            Error::Hyper(_) => "transport",
+            Error::Store(StoreError::StoreFull) => "store_full",
+            Error::Store(StoreError::TooLarge) => "secret_too_large",
+            Error::Store(StoreError::SecretNotFound) => "secret_not_found",
+            Error::Store(StoreError::InvalidId(_)) => "invalid_id",
+            Error::Store(StoreError::Generic(_)) => "store",
+        }
+    }
+
+    // How long the client should wait before trying again, if waiting helps
+    fn retry(&self) -> Option<std::time::Duration> {
+        match self {
+            Error::RateLimited(time) | Error::Banned(time) => Some(*time),
+            _ => None,
         }
     }
 
@@ -45,6 +137,83 @@ impl Error {
     }
 }
 
+// Marks requests whose errors are answered with RFC 7807 problem details instead of an empty body
+#[derive(Clone, Copy, gotham_derive::StateData)]
+pub struct Problems;
+
+#[derive(serde::Serialize)]
+struct Problem<'a> {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    title: &'static str,
+    status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<&'a str>,
+}
+
+// Turns an error that reached the edge of the pipeline into the response sent to the client
+pub fn into_response(
+    state: &gotham::state::State,
+    error: gotham::handler::HandlerError,
+) -> gotham::hyper::Response<gotham::hyper::Body> {
+    use gotham::handler::IntoResponse;
+    use gotham::state::FromState;
+
+    let retry = error.downcast_cause_ref::<Error>().and_then(Error::retry);
+    let mut response = if Problems::try_borrow_from(state).is_none() {
+        error.into_response(state)
+    } else {
+        problem(state, &error)
+    };
+    if response.status() == gotham::hyper::StatusCode::UNAUTHORIZED {
+        response.headers_mut().insert(
+            gotham::hyper::header::WWW_AUTHENTICATE,
+            gotham::hyper::header::HeaderValue::from_static("Bearer"),
+        );
+    }
+    if let Some(time) = retry {
+        response.headers_mut().insert(
+            gotham::hyper::header::RETRY_AFTER,
+            gotham::hyper::header::HeaderValue::from(retry_after(&time)),
+        );
+    }
+    response
+}
+
+// Whole seconds to wait, rounded up so that clients do not come back too early
+fn retry_after(time: &std::time::Duration) -> u64 {
+    time.as_secs() + u64::from(time.subsec_nanos() > 0)
+}
+
+fn problem(
+    state: &gotham::state::State,
+    error: &gotham::handler::HandlerError,
+) -> gotham::hyper::Response<gotham::hyper::Body> {
+    let status = error.status();
+    let cause = error.downcast_cause_ref::<Error>();
+    let problem = Problem {
+        kind: "about:blank",
+        title: status.canonical_reason().unwrap_or_default(),
+        status: status.as_u16(),
+        detail: cause.map(ToString::to_string),
+        code: cause.map(Error::code),
+    };
+
+    let mut response = gotham::helpers::http::response::create_response(
+        state,
+        status,
+        gotham::mime::APPLICATION_JSON,
+        serde_json::to_string(&problem).unwrap_or_default(),
+    );
+    response.headers_mut().insert(
+        gotham::hyper::header::CONTENT_TYPE,
+        gotham::hyper::header::HeaderValue::from_static("application/problem+json"),
+    );
+    response
+}
+
 impl From<store::Error> for Error {
     fn from(e: store::Error) -> Self {
         Self::Store(e)
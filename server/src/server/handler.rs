@@ -1,6 +1,13 @@
+#[cfg(any(feature = "host-frontend", test))]
+use super::embedded;
 use super::error::Error;
 use super::middleware;
+use super::pow;
 use super::store;
+use crate::logger::audit;
+
+// The header carrying the solution of a challenge, as `<challenge>:<nonce>`
+pub const PROOF_OF_WORK: &str = "x-proof-of-work";
 
 #[derive(serde::Deserialize, gotham_derive::StateData, gotham_derive::StaticResponseExtender)]
 pub struct IdExtractor {
@@ -36,6 +43,8 @@ where
 pub struct TtlExtractor {
     #[serde(deserialize_with = "duration_deserializer")]
     ttl: std::time::Duration,
+    // Where to tell the sender that the secret was read or expired
+    callback: Option<String>,
 }
 
 fn duration_deserializer<'de, D>(deserializer: D) -> Result<std::time::Duration, D::Error>
@@ -62,7 +71,7 @@ where
     deserializer.deserialize_str(TtlVisitor)
 }
 
-fn convert_str_to_duration(value: &str) -> Result<std::time::Duration, String> {
+pub fn convert_str_to_duration(value: &str) -> Result<std::time::Duration, String> {
     let unit = match value.chars().last() {
         Some('m') => 60,
         Some('h') => 60 * 60,
@@ -81,16 +90,90 @@ fn convert_str_to_duration(value: &str) -> Result<std::time::Duration, String> {
     Ok(std::time::Duration::from_secs(unit * amount))
 }
 
+#[derive(Copy, Clone, gotham_derive::StateData)]
+pub struct Route(&'static str);
+
+impl Route {
+    pub fn name(state: &gotham::state::State) -> &'static str {
+        use gotham::state::FromState;
+
+        Self::try_borrow_from(state).map_or("unmatched", |route| route.0)
+    }
+}
+
+// Labels the requests served by `handler` so that they can be told apart when observing the server
+pub fn named<H, R>(
+    name: &'static str,
+    handler: H,
+) -> impl Fn(gotham::state::State) -> R + Copy + Send + Sync + std::panic::RefUnwindSafe + 'static
+where
+    H: Fn(gotham::state::State) -> R + Copy + Send + Sync + std::panic::RefUnwindSafe + 'static,
+{
+    move |mut state| {
+        state.put(Route(name));
+        handler(state)
+    }
+}
+
+// The path of a front-end file, relative to the front-end root
+#[derive(serde::Deserialize, gotham_derive::StateData, gotham_derive::StaticResponseExtender)]
+pub struct AssetPath {
+    #[serde(rename = "*")]
+    parts: Vec<String>,
+}
+
 #[derive(Clone)]
-pub struct Index(gotham::handler::DirHandler, gotham::handler::FileHandler);
+pub enum Index {
+    Dir {
+        root: std::path::PathBuf,
+        index: gotham::handler::FileHandler,
+    },
+    #[cfg(any(feature = "host-frontend", test))]
+    Embedded(embedded::Assets),
+}
 
 impl Index {
+    // Assets with a content hash in their name never change, so browsers can keep them for good
+    const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+    // Everything else is revalidated so that new releases are picked up right away
+    const REVALIDATE: &str = "no-cache";
+
     pub fn new(root: std::path::PathBuf, index: std::path::PathBuf) -> Self {
-        use gotham::handler;
-        Self(
-            handler::DirHandler::new(handler::FileOptions::from(root)),
-            handler::FileHandler::new(handler::FileOptions::from(index)),
-        )
+        Self::Dir {
+            root,
+            index: gotham::handler::FileHandler::new(Self::options(index, Self::REVALIDATE)),
+        }
+    }
+
+    #[cfg(feature = "host-frontend")]
+    pub fn embedded() -> Self {
+        Self::Embedded(embedded::Assets::embedded())
+    }
+
+    // Precompressed `.br` and `.gz` siblings are served to the clients that accept them
+    fn options(path: std::path::PathBuf, cache_control: &str) -> gotham::handler::FileOptions {
+        gotham::handler::FileOptions::new(path)
+            .with_cache_control(cache_control)
+            .with_gzip(true)
+            .with_brotli(true)
+            .build()
+    }
+
+    // Bundlers name assets like `main.0123abcd.js` or `0123456789abcdef.module.wasm`
+    fn is_hashed(file_name: &str) -> bool {
+        let mut parts = file_name.split('.');
+        parts.next_back();
+        parts.any(|part| part.len() >= 8 && part.bytes().all(|c| c.is_ascii_hexdigit()))
+    }
+
+    fn vary(result: gotham::handler::HandlerResult) -> gotham::handler::HandlerResult {
+        result.map(|(state, mut response)| {
+            response.headers_mut().append(
+                gotham::hyper::header::VARY,
+                gotham::hyper::header::HeaderValue::from_static("accept-encoding"),
+            );
+            (state, response)
+        })
     }
 }
 
@@ -105,20 +188,260 @@ impl gotham::handler::NewHandler for Index {
 impl gotham::handler::Handler for Index {
     fn handle(
         self,
-        state: gotham::state::State,
+        mut state: gotham::state::State,
     ) -> std::pin::Pin<Box<gotham::handler::HandlerFuture>> {
-        Box::pin(async {
-            match self.0.handle(state).await {
-                Ok(response) => Ok(response),
-                Err((state, _)) => self.1.handle(state).await,
+        use gotham::state::FromState;
+
+        state.put(Route("index"));
+
+        // The root path has no file to look for
+        let path = AssetPath::try_borrow_from(&state).map(|path| path.parts.join("/"));
+
+        // Relative links of the front-end only resolve from within its directory
+        if path.is_none() {
+            let uri = gotham::hyper::Uri::borrow_from(&state);
+            if !uri.path().ends_with('/') {
+                let location = uri.query().map_or_else(
+                    || format!("{}/", uri.path()),
+                    |query| format!("{}/?{query}", uri.path()),
+                );
+                let response =
+                    gotham::helpers::http::response::create_permanent_redirect(&state, location);
+                return Box::pin(std::future::ready(Ok((state, response))));
             }
+        }
+
+        let file_name = path
+            .as_deref()
+            .and_then(|path| path.rsplit('/').next())
+            .unwrap_or_default();
+
+        let cache_control = if Self::is_hashed(file_name) {
+            Self::IMMUTABLE
+        } else {
+            Self::REVALIDATE
+        };
+
+        // Only paths without an extension are routes of the front-end, missing files are not
+        let is_file = std::path::Path::new(file_name).extension().is_some();
+
+        match self {
+            Self::Dir { root, index } => {
+                let Some(path) = path else {
+                    return Box::pin(async { Self::vary(index.handle(state).await) });
+                };
+
+                // The directory handler reads gotham's own path extractor, which can only be built
+                // by deserializing it
+                if let Ok(path) = serde_json::from_value::<gotham::handler::FilePathExtractor>(
+                    serde_json::json!({ "*": path.split('/').collect::<Vec<_>>() }),
+                ) {
+                    state.put(path);
+                }
+
+                let assets = gotham::handler::DirHandler::new(Self::options(root, cache_control));
+
+                Box::pin(async move {
+                    let result = match assets.handle(state).await {
+                        Err((state, _)) if !is_file => index.handle(state).await,
+                        result => result,
+                    };
+                    Self::vary(result)
+                })
+            }
+            #[cfg(any(feature = "host-frontend", test))]
+            Self::Embedded(assets) => {
+                let headers = gotham::hyper::HeaderMap::borrow_from(&state);
+                let response = path
+                    .and_then(|path| assets.respond(headers, &path, cache_control))
+                    .or_else(|| {
+                        (!is_file)
+                            .then(|| assets.respond(headers, "index.html", Self::REVALIDATE))
+                            .flatten()
+                    });
+
+                let result = if let Some(response) = response {
+                    Ok((state, response))
+                } else {
+                    let error = gotham::handler::HandlerError::from(std::io::Error::from(
+                        std::io::ErrorKind::NotFound,
+                    ));
+                    Err((
+                        state,
+                        error.with_status(gotham::hyper::StatusCode::NOT_FOUND),
+                    ))
+                };
+                Box::pin(std::future::ready(Self::vary(result)))
+            }
+        }
+    }
+}
+
+// The settings the front-end needs to work with this server
+#[derive(Clone)]
+pub struct Config {
+    body: std::sync::Arc<str>,
+    api_url: std::sync::Arc<str>,
+    limits: Limits,
+    features: std::sync::Arc<[&'static str]>,
+    sign_in: Option<auth::SignIn>,
+}
+
+// How long and how slowly clients may take to store a secret
+#[derive(Copy, Clone)]
+pub struct Limits {
+    pub max_ttl: Option<std::time::Duration>,
+    // Longest pause while reading a body
+    pub body_timeout: std::time::Duration,
+    // Slowest accepted upload, in bytes per second. Zero to accept any rate
+    pub min_upload_rate: u64,
+}
+
+impl Limits {
+    // Uploads are given the time their size takes at the slowest accepted rate, on top of the
+    // longest pause
+    fn upload_deadline(&self, length: u64) -> Option<std::time::Duration> {
+        (self.min_upload_rate > 0).then(|| {
+            self.body_timeout
+                + std::time::Duration::from_secs(length.div_ceil(self.min_upload_rate))
         })
     }
 }
 
+#[derive(serde::Serialize)]
+struct ConfigBody<'a> {
+    api_url: &'a str,
+    // Where to send users to sign in, when they can
+    #[serde(skip_serializing_if = "Option::is_none")]
+    login_url: Option<&'a str>,
+    max_secret_size: u64,
+    ttl: TtlRange,
+    features: &'a [&'a str],
+}
+
+#[derive(serde::Serialize)]
+struct TtlRange {
+    min_seconds: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_seconds: Option<u64>,
+}
+
+impl Config {
+    pub fn new(
+        api_url: &str,
+        sign_in: Option<auth::SignIn>,
+        limits: Limits,
+        features: &[&'static str],
+    ) -> Self {
+        let body = ConfigBody {
+            api_url,
+            login_url: sign_in.as_ref().map(|sign_in| &*sign_in.url),
+            max_secret_size: store::MAX_SECRET_SIZE,
+            ttl: TtlRange {
+                min_seconds: 0,
+                max_seconds: limits.max_ttl.map(|max_ttl| max_ttl.as_secs()),
+            },
+            features,
+        };
+
+        Self {
+            body: serde_json::to_string(&body).unwrap().into(),
+            api_url: api_url.into(),
+            limits,
+            features: features.into(),
+            sign_in,
+        }
+    }
+
+    pub fn api_url(&self) -> &str {
+        &self.api_url
+    }
+
+    pub fn limits(&self) -> Limits {
+        self.limits
+    }
+
+    pub fn features(&self) -> &[&'static str] {
+        &self.features
+    }
+
+    pub fn sign_in(&self) -> Option<&auth::SignIn> {
+        self.sign_in.as_ref()
+    }
+}
+
+impl gotham::handler::NewHandler for Config {
+    type Instance = Self;
+
+    fn new_handler(&self) -> gotham::anyhow::Result<Self::Instance> {
+        Ok(self.clone())
+    }
+}
+
+impl gotham::handler::Handler for Config {
+    fn handle(
+        self,
+        mut state: gotham::state::State,
+    ) -> std::pin::Pin<Box<gotham::handler::HandlerFuture>> {
+        state.put(Route("config"));
+        let response = document(&state, &self.body);
+        Box::pin(std::future::ready(Ok((state, response))))
+    }
+}
+
+// The OpenAPI description of the API, generated once at startup
+#[derive(Clone)]
+pub struct OpenApi(std::sync::Arc<str>);
+
+impl OpenApi {
+    pub fn new(api_url: &str, max_ttl: Option<std::time::Duration>, features: &[&str]) -> Self {
+        Self(
+            serde_json::to_string(&super::openapi::spec(api_url, max_ttl, features))
+                .unwrap()
+                .into(),
+        )
+    }
+}
+
+impl gotham::handler::NewHandler for OpenApi {
+    type Instance = Self;
+
+    fn new_handler(&self) -> gotham::anyhow::Result<Self::Instance> {
+        Ok(self.clone())
+    }
+}
+
+impl gotham::handler::Handler for OpenApi {
+    fn handle(
+        self,
+        mut state: gotham::state::State,
+    ) -> std::pin::Pin<Box<gotham::handler::HandlerFuture>> {
+        state.put(Route("openapi"));
+        let response = document(&state, &self.0);
+        Box::pin(std::future::ready(Ok((state, response))))
+    }
+}
+
+// Documents change with the configuration, so they are revalidated on every use
+fn document(
+    state: &gotham::state::State,
+    body: &str,
+) -> gotham::hyper::Response<gotham::hyper::Body> {
+    let mut response = gotham::helpers::http::response::create_response(
+        state,
+        gotham::hyper::StatusCode::OK,
+        gotham::mime::APPLICATION_JSON,
+        body.to_owned(),
+    );
+    response.headers_mut().insert(
+        gotham::hyper::header::CACHE_CONTROL,
+        gotham::hyper::header::HeaderValue::from_static("no-cache"),
+    );
+    response
+}
+
 pub fn get(mut state: gotham::state::State) -> std::pin::Pin<Box<gotham::handler::HandlerFuture>> {
     Box::pin(async {
-        use gotham::handler::IntoResponse;
         use gotham::state::FromState;
 
         let id = IdExtractor::take_from(&mut state).id;
@@ -126,7 +449,34 @@ pub fn get(mut state: gotham::state::State) -> std::pin::Pin<Box<gotham::handler
 
         match store.get(&id) {
             Ok(r) => {
-                let response = r.into_response(&state);
+                use gotham::hyper::header;
+
+                middleware::Metrics::borrow_from(&state).retrieved(r.len());
+                middleware::Audit::record(
+                    &state,
+                    audit::Event::Retrieved,
+                    &id,
+                    Some(r.len() as u64),
+                    None,
+                );
+                middleware::Webhooks::retrieved(&state, &id);
+                let mut response = gotham::helpers::http::response::create_response(
+                    &state,
+                    gotham::hyper::StatusCode::OK,
+                    gotham::mime::APPLICATION_OCTET_STREAM,
+                    r,
+                );
+
+                // A secret can only be read once, so no copy of it may be kept along the way
+                let headers = response.headers_mut();
+                headers.insert(
+                    header::CACHE_CONTROL,
+                    header::HeaderValue::from_static("no-store"),
+                );
+                headers.insert(
+                    header::X_CONTENT_TYPE_OPTIONS,
+                    header::HeaderValue::from_static("nosniff"),
+                );
                 Ok((state, response))
             }
             Err(e) => Err((state, e.into_handler_error())),
@@ -134,61 +484,787 @@ pub fn get(mut state: gotham::state::State) -> std::pin::Pin<Box<gotham::handler
     })
 }
 
-pub fn post(mut state: gotham::state::State) -> std::pin::Pin<Box<gotham::handler::HandlerFuture>> {
+pub fn post(
+    mut state: gotham::state::State,
+    limits: Limits,
+) -> std::pin::Pin<Box<gotham::handler::HandlerFuture>> {
     // TODO: Todo one try-block has landed
     async fn internal(
         state: &mut gotham::state::State,
+        limits: Limits,
     ) -> Result<gotham::hyper::Response<gotham::hyper::Body>, Error> {
         use gotham::handler::IntoResponse;
         use gotham::state::FromState;
-        use std::convert::TryFrom;
-
-        let request_length = gotham::hyper::HeaderMap::borrow_from(state)
-            .get(gotham::hyper::header::CONTENT_LENGTH)
-            .and_then(|len| len.to_str().ok())
-            .and_then(|len| len.parse::<usize>().ok())
-            .ok_or(Error::ContentLengthMissing)?;
-
-        if request_length == 0 {
-            return Err(Error::NothingToInsert);
-        } else if u64::try_from(request_length).map_err(|_| Error::PayloadTooLarge)?
-            > store::MAX_SECRET_SIZE
-        {
-            return Err(Error::PayloadTooLarge);
-        }
 
-        // Hyper reads up to Content-Length. No need for chunk-wise verification
-        // TODO: Is this needed behind nginx?
-        let body = tokio::time::timeout(
-            std::time::Duration::from_secs(10),
-            gotham::hyper::body::to_bytes(gotham::hyper::Body::borrow_mut_from(state)),
-        )
-        .await
-        .map_err(|_| Error::ReadTimeout)?
-        .map_err(Error::Hyper)?;
-
-        let ttl = TtlExtractor::take_from(state).ttl;
-        let expiry = std::time::SystemTime::now() + ttl;
-
-        let store = middleware::Store::borrow_mut_from(state);
-        store.put(body.to_vec(), expiry).map(|key| {
-            let mut response = key.encode().into_response(state);
-            *response.status_mut() = gotham::hyper::StatusCode::CREATED;
-            response
-        })
+        check_work(state)?;
+        let body = read_body(state, store::MAX_SECRET_SIZE, limits).await?;
+        let query = TtlExtractor::take_from(state);
+        let (key, _) = store_secret(
+            state,
+            body,
+            query.ttl,
+            limits.max_ttl,
+            query.callback.as_deref(),
+        )?;
+
+        let mut response = key.encode().into_response(state);
+        *response.status_mut() = gotham::hyper::StatusCode::CREATED;
+        response.headers_mut().insert(
+            gotham::hyper::header::CACHE_CONTROL,
+            gotham::hyper::header::HeaderValue::from_static("no-store"),
+        );
+        Ok(response)
     }
 
-    Box::pin(async {
-        match internal(&mut state).await {
+    Box::pin(async move {
+        match internal(&mut state, limits).await {
             Ok(r) => Ok((state, r)),
             Err(e) => Err((state, e.into_handler_error())),
         }
     })
 }
 
+// Reads a body announced by its Content-Length, up to `limit` bytes
+async fn read_body(
+    state: &mut gotham::state::State,
+    limit: u64,
+    limits: Limits,
+) -> Result<Vec<u8>, Error> {
+    use gotham::hyper::body::HttpBody;
+    use gotham::state::FromState;
+    use std::convert::TryFrom;
+
+    let request_length = gotham::hyper::HeaderMap::borrow_from(state)
+        .get(gotham::hyper::header::CONTENT_LENGTH)
+        .and_then(|len| len.to_str().ok())
+        .and_then(|len| len.parse::<usize>().ok())
+        .ok_or(Error::ContentLengthMissing)?;
+
+    let length = u64::try_from(request_length).map_err(|_| Error::PayloadTooLarge)?;
+    if length == 0 {
+        return Err(Error::NothingToInsert);
+    } else if length > limit {
+        return Err(Error::PayloadTooLarge);
+    }
+
+    // Hyper reads up to Content-Length. No need for chunk-wise verification
+    let body = gotham::hyper::Body::borrow_mut_from(state);
+    let read = async {
+        let mut data = Vec::with_capacity(request_length);
+        loop {
+            match tokio::time::timeout(limits.body_timeout, body.data()).await {
+                Ok(Some(chunk)) => data.extend_from_slice(&chunk.map_err(Error::Hyper)?),
+                Ok(None) => return Ok(data),
+                Err(_) => return Err(Error::ReadTimeout),
+            }
+        }
+    };
+
+    match limits.upload_deadline(length) {
+        Some(deadline) => tokio::time::timeout(deadline, read)
+            .await
+            .map_err(|_| Error::TooSlow(limits.min_upload_rate))?,
+        None => read.await,
+    }
+}
+
+// Asks anonymous uploads for a proof of work covering their Content-Length, before their body
+// is read
+fn check_work(state: &gotham::state::State) -> Result<(), Error> {
+    use gotham::state::FromState;
+
+    let Some(work) = middleware::ProofOfWork::try_borrow_from(state) else {
+        return Ok(());
+    };
+    if !work.puzzles().enabled() || middleware::ApiKey::try_borrow_from(state).is_some() {
+        return Ok(());
+    }
+
+    let headers = gotham::hyper::HeaderMap::borrow_from(state);
+    // A missing length is reported when reading the body
+    let Some(length) = headers
+        .get(gotham::hyper::header::CONTENT_LENGTH)
+        .and_then(|len| len.to_str().ok())
+        .and_then(|len| len.parse::<u64>().ok())
+    else {
+        return Ok(());
+    };
+    let proof = headers
+        .get(PROOF_OF_WORK)
+        .ok_or(Error::ProofOfWork(pow::Invalid::Missing))?
+        .to_str()
+        .map_err(|_| Error::ProofOfWork(pow::Invalid::Malformed))?;
+
+    work.puzzles()
+        .verify(proof, length, std::time::SystemTime::now())
+        .map_err(Error::ProofOfWork)
+}
+
+fn store_secret(
+    state: &mut gotham::state::State,
+    data: Vec<u8>,
+    ttl: std::time::Duration,
+    max_ttl: Option<std::time::Duration>,
+    callback: Option<&str>,
+) -> Result<(store::Id, std::time::SystemTime), Error> {
+    use gotham::state::FromState;
+
+    if let Some(max_ttl) = max_ttl
+        && ttl > max_ttl
+    {
+        return Err(Error::TtlTooLong(max_ttl));
+    }
+    let expiry = std::time::SystemTime::now() + ttl;
+    let callback = callback
+        .map(|url| middleware::Webhooks::check(state, url))
+        .transpose()?;
+
+    let size = data.len();
+    let owner = middleware::Owner::of(state);
+    let store = middleware::Store::borrow_mut_from(state);
+    let key = store.put(data, expiry, owner, callback.as_ref())?;
+    middleware::Metrics::borrow_from(state).created(size);
+    middleware::Audit::record(
+        state,
+        audit::Event::Created,
+        &key,
+        Some(size as u64),
+        Some(expiry),
+    );
+    if let Some(url) = callback {
+        middleware::Webhooks::borrow_from(state)
+            .hooks()
+            .subscribe(key, url, expiry);
+    }
+
+    Ok((key, expiry))
+}
+
+pub mod v2 {
+    use super::Error;
+    use super::middleware;
+    use super::store;
+
+    use gotham::hyper;
+
+    #[derive(serde::Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct CreateRequest {
+        // The encrypted secret, in standard base64
+        data: String,
+        ttl_seconds: u64,
+        // Where to tell the sender that the secret was read or expired
+        callback_url: Option<String>,
+    }
+
+    #[derive(serde::Serialize)]
+    struct Created {
+        id: String,
+        // Seconds since the Unix epoch
+        expires_at: u64,
+        size: usize,
+    }
+
+    #[derive(serde::Serialize)]
+    struct Secret {
+        id: String,
+        data: String,
+        size: usize,
+    }
+
+    // Base64 grows the secret by a third, on top of which the rest of the request must fit
+    const MAX_REQUEST_SIZE: u64 = store::MAX_SECRET_SIZE / 3 * 4 + 4 * 1024;
+
+    fn base64() -> base64::engine::GeneralPurpose {
+        base64::engine::general_purpose::STANDARD
+    }
+
+    pub(super) fn json<T: serde::Serialize>(
+        state: &gotham::state::State,
+        status: hyper::StatusCode,
+        body: &T,
+    ) -> hyper::Response<hyper::Body> {
+        let mut response = gotham::helpers::http::response::create_response(
+            state,
+            status,
+            gotham::mime::APPLICATION_JSON,
+            serde_json::to_string(body).unwrap_or_default(),
+        );
+        response.headers_mut().insert(
+            hyper::header::CACHE_CONTROL,
+            hyper::header::HeaderValue::from_static("no-store"),
+        );
+        response
+    }
+
+    pub fn post(
+        mut state: gotham::state::State,
+        limits: super::Limits,
+    ) -> std::pin::Pin<Box<gotham::handler::HandlerFuture>> {
+        async fn internal(
+            state: &mut gotham::state::State,
+            limits: super::Limits,
+        ) -> Result<hyper::Response<hyper::Body>, Error> {
+            super::check_work(state)?;
+            let body = super::read_body(state, MAX_REQUEST_SIZE, limits).await?;
+            let request = serde_json::from_slice::<CreateRequest>(&body)
+                .map_err(|e| Error::InvalidBody(e.to_string()))?;
+
+            let data = base64::engine::Engine::decode(&base64(), request.data)
+                .map_err(|e| Error::InvalidBody(format!("data: {e}")))?;
+            if data.is_empty() {
+                return Err(Error::NothingToInsert);
+            } else if data.len() as u64 > store::MAX_SECRET_SIZE {
+                return Err(Error::PayloadTooLarge);
+            }
+
+            let size = data.len();
+            let (key, expiry) = super::store_secret(
+                state,
+                data,
+                std::time::Duration::from_secs(request.ttl_seconds),
+                limits.max_ttl,
+                request.callback_url.as_deref(),
+            )?;
+
+            let created = Created {
+                id: key.encode(),
+                expires_at: expiry
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .map_or(0, |since| since.as_secs()),
+                size,
+            };
+            Ok(json(state, hyper::StatusCode::CREATED, &created))
+        }
+
+        state.put(super::super::error::Problems);
+        Box::pin(async move {
+            match internal(&mut state, limits).await {
+                Ok(r) => Ok((state, r)),
+                Err(e) => Err((state, e.into_handler_error())),
+            }
+        })
+    }
+
+    // The size of the request body a challenge is for
+    #[derive(
+        serde::Deserialize, gotham_derive::StateData, gotham_derive::StaticResponseExtender,
+    )]
+    pub struct ChallengeExtractor {
+        size: u64,
+    }
+
+    #[derive(serde::Serialize)]
+    struct Puzzle {
+        challenge: String,
+        difficulty: u8,
+        expires_at: u64,
+    }
+
+    pub fn challenge(
+        mut state: gotham::state::State,
+    ) -> std::pin::Pin<Box<gotham::handler::HandlerFuture>> {
+        use gotham::state::FromState;
+
+        state.put(super::super::error::Problems);
+        let size = ChallengeExtractor::take_from(&mut state).size;
+        // The fuller the store, the more work is asked
+        let result = middleware::Store::borrow_mut_from(&mut state)
+            .stats()
+            .map(|stats| (stats.size, stats.capacity));
+
+        Box::pin(std::future::ready(match result {
+            Ok((used, capacity)) => {
+                let puzzles = middleware::ProofOfWork::borrow_from(&state).puzzles();
+                let difficulty = puzzles.difficulty(size, used, capacity);
+                let issued = puzzles.issue(size, difficulty, std::time::SystemTime::now());
+                let puzzle = Puzzle {
+                    challenge: issued.challenge,
+                    difficulty: issued.difficulty,
+                    expires_at: issued
+                        .expires
+                        .duration_since(std::time::UNIX_EPOCH)
+                        .map_or(0, |since| since.as_secs()),
+                };
+                let response = json(&state, hyper::StatusCode::OK, &puzzle);
+                Ok((state, response))
+            }
+            Err(e) => Err((state, e.into_handler_error())),
+        }))
+    }
+
+    pub fn get(
+        mut state: gotham::state::State,
+    ) -> std::pin::Pin<Box<gotham::handler::HandlerFuture>> {
+        use gotham::state::FromState;
+
+        state.put(super::super::error::Problems);
+        let id = super::IdExtractor::take_from(&mut state).id;
+        let result = middleware::Store::borrow_mut_from(&mut state).get(&id);
+
+        Box::pin(std::future::ready(match result {
+            Ok(data) => {
+                middleware::Metrics::borrow_from(&state).retrieved(data.len());
+                middleware::Audit::record(
+                    &state,
+                    super::audit::Event::Retrieved,
+                    &id,
+                    Some(data.len() as u64),
+                    None,
+                );
+                middleware::Webhooks::retrieved(&state, &id);
+                let secret = Secret {
+                    id: id.encode(),
+                    size: data.len(),
+                    data: base64::engine::Engine::encode(&base64(), data),
+                };
+                let response = json(&state, hyper::StatusCode::OK, &secret);
+                Ok((state, response))
+            }
+            Err(e) => Err((state, e.into_handler_error())),
+        }))
+    }
+}
+
+pub mod admin {
+    use super::Error;
+    use super::audit;
+    use super::middleware;
+    use super::store;
+
+    use gotham::hyper;
+
+    // Who may use the admin API
+    #[derive(Copy, Clone)]
+    pub struct Access {
+        // Hash of the bearer token, so that it can be compared in constant time
+        token: Option<[u8; 32]>,
+        // Whether clients of Unix domain sockets are trusted without a token
+        unix: bool,
+    }
+
+    impl Access {
+        pub fn new(token: Option<&str>, unix: bool) -> Self {
+            Self {
+                token: token.map(hash),
+                unix,
+            }
+        }
+
+        fn authorize(self, state: &gotham::state::State) -> Result<(), Error> {
+            use gotham::state::FromState;
+
+            if self.unix && crate::listener::UnixPeer::try_borrow_from(state).is_some() {
+                return Ok(());
+            }
+
+            match (self.token, middleware::bearer(state)) {
+                (Some(token), Some(given)) if middleware::same_digest(&token, &given) => Ok(()),
+                _ => Err(Error::Unauthorized),
+            }
+        }
+    }
+
+    fn hash(token: &str) -> [u8; 32] {
+        use sha2::Digest;
+
+        sha2::Sha256::digest(token.trim()).into()
+    }
+
+    #[derive(
+        serde::Deserialize, gotham_derive::StateData, gotham_derive::StaticResponseExtender,
+    )]
+    pub struct PurgeExtractor {
+        scope: store::Purge,
+    }
+
+    #[derive(serde::Serialize)]
+    struct Stats {
+        secrets: usize,
+        size: u64,
+        capacity: u64,
+        expired: u64,
+        available: u64,
+        maintenance: bool,
+    }
+
+    #[derive(serde::Serialize)]
+    struct Purged {
+        purged: usize,
+    }
+
+    #[derive(serde::Serialize)]
+    struct Maintenance {
+        enabled: bool,
+    }
+
+    // Authorizes the request before running the operation, answering errors with problem details
+    fn respond<F>(
+        mut state: gotham::state::State,
+        access: Access,
+        operation: F,
+    ) -> std::pin::Pin<Box<gotham::handler::HandlerFuture>>
+    where
+        F: FnOnce(&mut gotham::state::State) -> Result<hyper::Response<hyper::Body>, Error>,
+    {
+        state.put(super::super::error::Problems);
+        let result = access
+            .authorize(&state)
+            .and_then(|()| operation(&mut state));
+
+        Box::pin(std::future::ready(match result {
+            Ok(response) => Ok((state, response)),
+            Err(e) => Err((state, e.into_handler_error())),
+        }))
+    }
+
+    pub fn stats(
+        state: gotham::state::State,
+        access: Access,
+    ) -> std::pin::Pin<Box<gotham::handler::HandlerFuture>> {
+        use gotham::state::FromState;
+
+        respond(state, access, |state| {
+            let store = middleware::Store::borrow_mut_from(state);
+            let usage = store.stats()?;
+            let body = Stats {
+                secrets: usage.secrets,
+                size: usage.size,
+                capacity: usage.capacity,
+                expired: usage.expired,
+                available: store.available()?,
+                maintenance: store.read_only(),
+            };
+            Ok(super::v2::json(state, hyper::StatusCode::OK, &body))
+        })
+    }
+
+    pub fn revoke(
+        state: gotham::state::State,
+        access: Access,
+    ) -> std::pin::Pin<Box<gotham::handler::HandlerFuture>> {
+        use gotham::state::FromState;
+
+        respond(state, access, |state| {
+            let id = super::IdExtractor::take_from(state).id;
+            middleware::Store::borrow_mut_from(state).revoke(&id)?;
+            middleware::Audit::record(state, audit::Event::Revoked, &id, None, None);
+            middleware::Webhooks::cancel(state, Some(&id));
+            log::info!("Revoked a secret");
+            Ok(gotham::helpers::http::response::create_empty_response(
+                state,
+                hyper::StatusCode::NO_CONTENT,
+            ))
+        })
+    }
+
+    pub fn purge(
+        state: gotham::state::State,
+        access: Access,
+    ) -> std::pin::Pin<Box<gotham::handler::HandlerFuture>> {
+        use gotham::state::FromState;
+
+        respond(state, access, |state| {
+            let scope = PurgeExtractor::take_from(state).scope;
+            let purged = middleware::Store::borrow_mut_from(state).purge(scope)?;
+            match scope {
+                store::Purge::All => {
+                    middleware::Audit::purge(state);
+                    middleware::Webhooks::cancel(state, None);
+                }
+                store::Purge::Expired => middleware::Audit::expire(state),
+            }
+            log::info!("Purged {purged} secrets ({scope:?})");
+            Ok(super::v2::json(
+                state,
+                hyper::StatusCode::OK,
+                &Purged { purged },
+            ))
+        })
+    }
+
+    pub fn maintenance(
+        state: gotham::state::State,
+        access: Access,
+    ) -> std::pin::Pin<Box<gotham::handler::HandlerFuture>> {
+        use gotham::state::FromState;
+
+        respond(state, access, |state| {
+            let enabled = middleware::Store::borrow_from(state).read_only();
+            Ok(super::v2::json(
+                state,
+                hyper::StatusCode::OK,
+                &Maintenance { enabled },
+            ))
+        })
+    }
+
+    // Switches the maintenance mode on for `PUT` and off for `DELETE`
+    pub fn set_maintenance(
+        state: gotham::state::State,
+        access: Access,
+    ) -> std::pin::Pin<Box<gotham::handler::HandlerFuture>> {
+        use gotham::state::FromState;
+
+        respond(state, access, |state| {
+            let enabled = hyper::Method::borrow_from(state) == hyper::Method::PUT;
+            middleware::Store::borrow_from(state).set_read_only(enabled);
+            if enabled {
+                log::warn!("Maintenance mode enabled: uploads are rejected");
+            } else {
+                log::warn!("Maintenance mode disabled");
+            }
+            Ok(super::v2::json(
+                state,
+                hyper::StatusCode::OK,
+                &Maintenance { enabled },
+            ))
+        })
+    }
+}
+
+// Signs users of the front-end in with the OpenID Connect provider
+pub mod auth {
+    use super::super::oidc;
+    use super::Error;
+    use super::Route;
+
+    use gotham::hyper;
+
+    #[derive(
+        serde::Deserialize, gotham_derive::StateData, gotham_derive::StaticResponseExtender,
+    )]
+    pub struct LoginExtractor {
+        // The path of the page to come back to once signed in
+        #[serde(rename = "return")]
+        return_to: Option<String>,
+    }
+
+    // What the provider sends the browser back with, either a code or an error
+    #[derive(
+        serde::Deserialize, gotham_derive::StateData, gotham_derive::StaticResponseExtender,
+    )]
+    pub struct CallbackExtractor {
+        code: Option<String>,
+        state: Option<String>,
+        error: Option<String>,
+        error_description: Option<String>,
+    }
+
+    // How users of the front-end sign in
+    #[derive(Clone)]
+    pub struct SignIn {
+        // Where the front-end sends them
+        pub url: String,
+        pub provider: std::sync::Arc<oidc::Provider>,
+    }
+
+    // Sends the browser to the provider
+    #[derive(Clone)]
+    pub struct Login(std::sync::Arc<oidc::Provider>);
+
+    impl Login {
+        pub fn new(provider: std::sync::Arc<oidc::Provider>) -> Self {
+            Self(provider)
+        }
+    }
+
+    impl gotham::handler::NewHandler for Login {
+        type Instance = Self;
+
+        fn new_handler(&self) -> gotham::anyhow::Result<Self::Instance> {
+            Ok(self.clone())
+        }
+    }
+
+    impl gotham::handler::Handler for Login {
+        fn handle(
+            self,
+            mut state: gotham::state::State,
+        ) -> std::pin::Pin<Box<gotham::handler::HandlerFuture>> {
+            use gotham::state::FromState;
+
+            state.put(Route("login"));
+            let query = LoginExtractor::take_from(&mut state);
+            Box::pin(async move {
+                let result = self.0.login(query.return_to.as_deref()).await;
+                respond(state, result)
+            })
+        }
+    }
+
+    // Starts the session once the provider sends the browser back
+    #[derive(Clone)]
+    pub struct Callback(std::sync::Arc<oidc::Provider>);
+
+    impl Callback {
+        pub fn new(provider: std::sync::Arc<oidc::Provider>) -> Self {
+            Self(provider)
+        }
+    }
+
+    impl gotham::handler::NewHandler for Callback {
+        type Instance = Self;
+
+        fn new_handler(&self) -> gotham::anyhow::Result<Self::Instance> {
+            Ok(self.clone())
+        }
+    }
+
+    impl gotham::handler::Handler for Callback {
+        fn handle(
+            self,
+            mut state: gotham::state::State,
+        ) -> std::pin::Pin<Box<gotham::handler::HandlerFuture>> {
+            use gotham::state::FromState;
+
+            state.put(Route("login_callback"));
+            let query = CallbackExtractor::take_from(&mut state);
+            let headers = hyper::HeaderMap::borrow_from(&state).clone();
+            Box::pin(async move {
+                let result = match query {
+                    CallbackExtractor {
+                        error: Some(error),
+                        error_description,
+                        ..
+                    } => Err(oidc::Invalid::Refused(
+                        error_description.map_or(error.clone(), |description| {
+                            format!("{error}: {description}")
+                        }),
+                    )),
+                    CallbackExtractor {
+                        code: Some(code),
+                        state: Some(login),
+                        ..
+                    } => self.0.callback(&code, &login, &headers).await,
+                    _ => Err(oidc::Invalid::Session),
+                };
+                respond(state, result)
+            })
+        }
+    }
+
+    fn respond(
+        state: gotham::state::State,
+        result: Result<oidc::Redirect, oidc::Invalid>,
+    ) -> gotham::handler::HandlerResult {
+        let redirect = match result {
+            Ok(redirect) => redirect,
+            Err(e) => return Err((state, Error::SignIn(e).into_handler_error())),
+        };
+
+        let mut response = gotham::helpers::http::response::create_empty_response(
+            &state,
+            hyper::StatusCode::FOUND,
+        );
+        let headers = response.headers_mut();
+        headers.insert(
+            hyper::header::CACHE_CONTROL,
+            hyper::header::HeaderValue::from_static("no-store"),
+        );
+        for cookie in redirect.cookies {
+            if let Ok(cookie) = hyper::header::HeaderValue::try_from(cookie) {
+                headers.append(hyper::header::SET_COOKIE, cookie);
+            }
+        }
+        match hyper::header::HeaderValue::try_from(redirect.location) {
+            Ok(location) => {
+                headers.insert(hyper::header::LOCATION, location);
+                Ok((state, response))
+            }
+            Err(_) => Err((
+                state,
+                Error::SignIn(oidc::Invalid::Session).into_handler_error(),
+            )),
+        }
+    }
+}
+
+pub fn metrics(
+    mut state: gotham::state::State,
+) -> (
+    gotham::state::State,
+    gotham::hyper::Response<gotham::hyper::Body>,
+) {
+    use gotham::state::FromState;
+
+    let usage = middleware::Store::borrow_mut_from(&mut state).stats().ok();
+    let body = middleware::Metrics::borrow_from(&state).render(usage.as_ref());
+
+    let mut response = gotham::helpers::http::response::create_response(
+        &state,
+        gotham::hyper::StatusCode::OK,
+        gotham::mime::TEXT_PLAIN_UTF_8,
+        body,
+    );
+    response.headers_mut().insert(
+        gotham::hyper::header::CONTENT_TYPE,
+        gotham::hyper::header::HeaderValue::from_static("text/plain; version=0.0.4; charset=utf-8"),
+    );
+
+    (state, response)
+}
+
+// The CORS headers are added by the middleware, so there is nothing left to answer
+pub fn preflight(
+    state: gotham::state::State,
+) -> (
+    gotham::state::State,
+    gotham::hyper::Response<gotham::hyper::Body>,
+) {
+    let response = gotham::helpers::http::response::create_empty_response(
+        &state,
+        gotham::hyper::StatusCode::NO_CONTENT,
+    );
+    (state, response)
+}
+
+pub fn health(state: gotham::state::State) -> (gotham::state::State, &'static str) {
+    (state, "ok")
+}
+
+// Ready as long as the store can accept at least one more upload
+pub fn ready(
+    mut state: gotham::state::State,
+) -> (
+    gotham::state::State,
+    gotham::hyper::Response<gotham::hyper::Body>,
+) {
+    use gotham::hyper::StatusCode;
+    use gotham::state::FromState;
+
+    let (status, body) = match middleware::Store::borrow_mut_from(&mut state).available() {
+        Ok(0) => {
+            log::warn!("Not ready: store full");
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                String::from("not ready: store full"),
+            )
+        }
+        Ok(available) => (
+            StatusCode::OK,
+            format!("ready: {available} bytes available"),
+        ),
+        Err(e) => {
+            log::warn!("Not ready: {e}");
+            (StatusCode::SERVICE_UNAVAILABLE, format!("not ready: {e}"))
+        }
+    };
+
+    let response = gotham::helpers::http::response::create_response(
+        &state,
+        status,
+        gotham::mime::TEXT_PLAIN_UTF_8,
+        body,
+    );
+    (state, response)
+}
+
 #[cfg(test)]
 mod tests {
     #[test]
+    // Allowed because the lint is newer than this code
+    #[allow(clippy::duration_suboptimal_units)]
     fn can_deserialize_ttl() {
         use super::convert_str_to_duration;
 
@@ -0,0 +1,236 @@
+use super::store;
+
+// Upper bounds of the latency histogram buckets, in seconds
+const BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+#[derive(Default)]
+pub struct Metrics {
+    requests: std::sync::Mutex<std::collections::BTreeMap<(&'static str, u16), Histogram>>,
+    errors: std::sync::Mutex<std::collections::BTreeMap<&'static str, u64>>,
+    uploaded: std::sync::atomic::AtomicU64,
+    downloaded: std::sync::atomic::AtomicU64,
+    created: std::sync::atomic::AtomicU64,
+    retrieved: std::sync::atomic::AtomicU64,
+}
+
+#[derive(Default)]
+struct Histogram {
+    buckets: [u64; BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        for (bucket, bound) in self.buckets.iter_mut().zip(BUCKETS) {
+            if value <= bound {
+                *bucket += 1;
+            }
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+
+    fn render(&self, output: &mut String, name: &str, labels: &str) {
+        use std::fmt::Write;
+
+        for (bucket, bound) in self.buckets.iter().zip(BUCKETS) {
+            writeln!(output, "{name}_bucket{{{labels},le=\"{bound}\"}} {bucket}").unwrap();
+        }
+        writeln!(
+            output,
+            "{name}_bucket{{{labels},le=\"+Inf\"}} {}",
+            self.count
+        )
+        .unwrap();
+        writeln!(output, "{name}_sum{{{labels}}} {}", self.sum).unwrap();
+        writeln!(output, "{name}_count{{{labels}}} {}", self.count).unwrap();
+    }
+}
+
+impl Metrics {
+    pub fn request(&self, route: &'static str, status: u16, elapsed: std::time::Duration) {
+        lock(&self.requests)
+            .entry((route, status))
+            .or_default()
+            .observe(elapsed.as_secs_f64());
+    }
+
+    pub fn error(&self, code: &'static str) {
+        *lock(&self.errors).entry(code).or_default() += 1;
+    }
+
+    pub fn created(&self, size: usize) {
+        use std::sync::atomic::Ordering;
+
+        self.created.fetch_add(1, Ordering::Relaxed);
+        self.uploaded.fetch_add(size as u64, Ordering::Relaxed);
+    }
+
+    pub fn retrieved(&self, size: usize) {
+        use std::sync::atomic::Ordering;
+
+        self.retrieved.fetch_add(1, Ordering::Relaxed);
+        self.downloaded.fetch_add(size as u64, Ordering::Relaxed);
+    }
+
+    pub fn render(&self, stats: Option<&store::Stats>) -> String {
+        use std::fmt::Write;
+        use std::sync::atomic::Ordering;
+
+        let mut output = String::new();
+
+        header(
+            &mut output,
+            "passer_http_request_duration_seconds",
+            "histogram",
+            "Count and latency of requests by route and status",
+        );
+        for ((route, status), histogram) in lock(&self.requests).iter() {
+            histogram.render(
+                &mut output,
+                "passer_http_request_duration_seconds",
+                &format!("route=\"{route}\",status=\"{status}\""),
+            );
+        }
+
+        header(
+            &mut output,
+            "passer_errors_total",
+            "counter",
+            "Errors by variant",
+        );
+        for (code, count) in lock(&self.errors).iter() {
+            writeln!(output, "passer_errors_total{{error=\"{code}\"}} {count}").unwrap();
+        }
+
+        for (name, help, value) in [
+            (
+                "passer_uploaded_bytes_total",
+                "Bytes received in secrets",
+                &self.uploaded,
+            ),
+            (
+                "passer_downloaded_bytes_total",
+                "Bytes sent in secrets",
+                &self.downloaded,
+            ),
+            (
+                "passer_secrets_created_total",
+                "Secrets created",
+                &self.created,
+            ),
+            (
+                "passer_secrets_retrieved_total",
+                "Secrets retrieved",
+                &self.retrieved,
+            ),
+        ] {
+            header(&mut output, name, "counter", help);
+            writeln!(output, "{name} {}", value.load(Ordering::Relaxed)).unwrap();
+        }
+
+        if let Some(stats) = stats {
+            header(
+                &mut output,
+                "passer_secrets_expired_total",
+                "counter",
+                "Secrets expired before being retrieved",
+            );
+            writeln!(output, "passer_secrets_expired_total {}", stats.expired).unwrap();
+
+            for (name, help, value) in [
+                (
+                    "passer_store_secrets",
+                    "Secrets currently in the store",
+                    stats.secrets as u64,
+                ),
+                (
+                    "passer_store_bytes",
+                    "Bytes currently used by the store",
+                    stats.size,
+                ),
+                (
+                    "passer_store_capacity_bytes",
+                    "Maximum bytes the store can hold",
+                    stats.capacity,
+                ),
+            ] {
+                header(&mut output, name, "gauge", help);
+                writeln!(output, "{name} {value}").unwrap();
+            }
+        }
+
+        output
+    }
+}
+
+fn header(output: &mut String, name: &str, kind: &str, help: &str) {
+    use std::fmt::Write;
+
+    writeln!(output, "# HELP {name} {help}").unwrap();
+    writeln!(output, "# TYPE {name} {kind}").unwrap();
+}
+
+fn lock<T>(mutex: &std::sync::Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    // A panic while recording cannot leave the counters in an invalid state
+    mutex
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Metrics;
+
+    #[test]
+    fn histogram() {
+        let metrics = Metrics::default();
+        metrics.request("create", 201, std::time::Duration::from_millis(20));
+        metrics.request("create", 201, std::time::Duration::from_secs(20));
+
+        let output = metrics.render(None);
+        assert!(output.contains(
+            "passer_http_request_duration_seconds_bucket{route=\"create\",status=\"201\",le=\"0.01\"} 0\n"
+        ));
+        assert!(output.contains(
+            "passer_http_request_duration_seconds_bucket{route=\"create\",status=\"201\",le=\"0.025\"} 1\n"
+        ));
+        assert!(output.contains(
+            "passer_http_request_duration_seconds_bucket{route=\"create\",status=\"201\",le=\"10\"} 1\n"
+        ));
+        assert!(output.contains(
+            "passer_http_request_duration_seconds_bucket{route=\"create\",status=\"201\",le=\"+Inf\"} 2\n"
+        ));
+        assert!(output.contains(
+            "passer_http_request_duration_seconds_count{route=\"create\",status=\"201\"} 2\n"
+        ));
+    }
+
+    #[test]
+    fn counters() {
+        let metrics = Metrics::default();
+        metrics.created(10);
+        metrics.created(5);
+        metrics.retrieved(10);
+        metrics.error("store_full");
+
+        let output = metrics.render(Some(&crate::store::Stats {
+            secrets: 1,
+            size: 5,
+            capacity: 100,
+            expired: 3,
+        }));
+        assert!(output.contains("passer_uploaded_bytes_total 15\n"));
+        assert!(output.contains("passer_downloaded_bytes_total 10\n"));
+        assert!(output.contains("passer_secrets_created_total 2\n"));
+        assert!(output.contains("passer_secrets_retrieved_total 1\n"));
+        assert!(output.contains("passer_secrets_expired_total 3\n"));
+        assert!(output.contains("passer_errors_total{error=\"store_full\"} 1\n"));
+        assert!(output.contains("passer_store_secrets 1\n"));
+        assert!(output.contains("passer_store_bytes 5\n"));
+        assert!(output.contains("passer_store_capacity_bytes 100\n"));
+    }
+}
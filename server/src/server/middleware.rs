@@ -1,14 +1,97 @@
+use super::error;
 use super::error::Error;
+use super::handler;
+use super::metrics;
+use super::oidc;
+use super::pow;
+use super::proxy;
+use super::rate;
 use super::store;
+use super::webhook;
+use crate::logger::access;
+use crate::logger::audit;
+use crate::options;
 
 use gotham::hyper;
 
 #[derive(Clone, gotham_derive::NewMiddleware)]
-pub struct Cors(hyper::header::HeaderValue);
+pub struct Cors(std::sync::Arc<CorsOptions>);
+
+struct CorsOptions {
+    origins: Vec<hyper::header::HeaderValue>,
+    methods: Option<hyper::header::HeaderValue>,
+    headers: Option<hyper::header::HeaderValue>,
+    expose_headers: Option<hyper::header::HeaderValue>,
+    max_age: Option<hyper::header::HeaderValue>,
+}
 
 impl Cors {
-    pub fn new(cors: hyper::header::HeaderValue) -> Self {
-        Self(cors)
+    pub fn new(
+        origins: Vec<hyper::header::HeaderValue>,
+        methods: &[hyper::Method],
+        headers: &[hyper::header::HeaderName],
+        expose_headers: &[hyper::header::HeaderName],
+        max_age: Option<u32>,
+    ) -> Self {
+        Self(std::sync::Arc::new(CorsOptions {
+            origins,
+            methods: Self::list(methods.iter().map(hyper::Method::as_str)),
+            headers: Self::list(headers.iter().map(hyper::header::HeaderName::as_str)),
+            expose_headers: Self::list(
+                expose_headers.iter().map(hyper::header::HeaderName::as_str),
+            ),
+            max_age: max_age.map(hyper::header::HeaderValue::from),
+        }))
+    }
+
+    fn list<'a>(items: impl Iterator<Item = &'a str>) -> Option<hyper::header::HeaderValue> {
+        let list = items.collect::<Vec<_>>().join(", ");
+        if list.is_empty() {
+            return None;
+        }
+        hyper::header::HeaderValue::from_str(&list).ok()
+    }
+
+    fn apply(
+        &self,
+        origin: Option<&hyper::header::HeaderValue>,
+        preflight: bool,
+        headers: &mut hyper::HeaderMap,
+    ) {
+        use hyper::header;
+
+        let options = &self.0;
+        let any_origin = options.origins.iter().any(|allowed| allowed == "*");
+
+        let allow_origin = if any_origin {
+            header::HeaderValue::from_static("*")
+        } else {
+            // The answer depends on the origin, so caches must not share it between origins
+            headers.append(header::VARY, header::HeaderValue::from_static("origin"));
+
+            match origin.filter(|origin| options.origins.contains(origin)) {
+                Some(origin) => origin.clone(),
+                None => return,
+            }
+        };
+        headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin);
+
+        if preflight {
+            for (name, value) in [
+                (header::ACCESS_CONTROL_ALLOW_METHODS, &options.methods),
+                (header::ACCESS_CONTROL_ALLOW_HEADERS, &options.headers),
+                (header::ACCESS_CONTROL_MAX_AGE, &options.max_age),
+            ] {
+                if let Some(value) = value {
+                    headers.insert(name, value.clone());
+                }
+            }
+        } else if let Some(expose_headers) = &options.expose_headers {
+            headers.insert(
+                header::ACCESS_CONTROL_EXPOSE_HEADERS,
+                expose_headers.clone(),
+            );
+        }
     }
 }
 
@@ -23,24 +106,452 @@ impl gotham::middleware::Middleware for Cors {
             + Send
             + 'static,
     {
-        Box::pin(async {
+        use gotham::state::FromState;
+
+        let headers = hyper::HeaderMap::borrow_from(&state);
+        let origin = headers.get(hyper::header::ORIGIN).cloned();
+        let preflight = hyper::Method::borrow_from(&state) == hyper::Method::OPTIONS
+            && headers.contains_key(hyper::header::ACCESS_CONTROL_REQUEST_METHOD);
+
+        Box::pin(async move {
             chain(state)
                 .await
                 .or_else(|(state, err)| {
-                    use gotham::handler::IntoResponse;
-
-                    let response = err.into_response(&state);
+                    let response = error::into_response(&state, err);
                     Ok((state, response))
                 })
                 .map(move |(state, mut response)| {
-                    let header = response.headers_mut();
-                    header.insert(hyper::header::ACCESS_CONTROL_ALLOW_ORIGIN, self.0);
+                    self.apply(origin.as_ref(), preflight, response.headers_mut());
                     (state, response)
                 })
         })
     }
 }
 
+// A key allowed to upload, known by the SHA-256 of its token
+#[derive(Clone, gotham_derive::StateData)]
+pub struct ApiKey {
+    // Tells the uploads of each key apart in the logs
+    label: std::sync::Arc<str>,
+    digest: [u8; 32],
+}
+
+impl ApiKey {
+    // Stands for a user signed in with the provider, who is no key. Labels of keys cannot hold a
+    // colon, so they are not mistaken for one another
+    fn user(subject: &str) -> Self {
+        Self {
+            label: format!("oidc:{subject}").into(),
+            digest: [0; 32],
+        }
+    }
+
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+}
+
+// Parses `<label>:<sha256 in hex>`
+impl std::str::FromStr for ApiKey {
+    type Err = &'static str;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (label, hex) = value
+            .split_once(':')
+            .ok_or("expected <label>:<sha256 in hex>")?;
+        if label.is_empty() {
+            return Err("the label is empty");
+        }
+        if hex.len() != 64 {
+            return Err("the hash must be 64 hexadecimal characters");
+        }
+
+        let mut digest = [0; 32];
+        for (byte, pair) in digest.iter_mut().zip(hex.as_bytes().chunks(2)) {
+            *byte = std::str::from_utf8(pair)
+                .ok()
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                .ok_or("the hash must be 64 hexadecimal characters")?;
+        }
+
+        Ok(Self {
+            label: label.into(),
+            digest,
+        })
+    }
+}
+
+// The SHA-256 of the bearer token of the request, if any
+pub fn bearer(state: &gotham::state::State) -> Option<[u8; 32]> {
+    use gotham::state::FromState;
+    use sha2::Digest;
+
+    hyper::HeaderMap::borrow_from(state)
+        .get(hyper::header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|token| sha2::Sha256::digest(token.trim()).into())
+}
+
+// Compares digests in constant time, so that the time taken does not tell how much of it matched
+pub fn same_digest(a: &[u8; 32], b: &[u8; 32]) -> bool {
+    a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+// Rejects the requests without one of the keys or a user signed in with the provider, unless
+// there are neither
+#[derive(Clone, gotham_derive::NewMiddleware)]
+pub struct ApiKeys {
+    keys: std::sync::Arc<Vec<ApiKey>>,
+    provider: Option<std::sync::Arc<oidc::Provider>>,
+}
+
+impl ApiKeys {
+    pub fn new(keys: Vec<ApiKey>, provider: Option<oidc::Provider>) -> Self {
+        Self {
+            keys: std::sync::Arc::new(keys),
+            provider: provider.map(std::sync::Arc::new),
+        }
+    }
+
+    pub fn required(&self) -> bool {
+        !self.keys.is_empty() || self.provider.is_some()
+    }
+
+    pub fn provider(&self) -> Option<&std::sync::Arc<oidc::Provider>> {
+        self.provider.as_ref()
+    }
+}
+
+impl gotham::middleware::Middleware for ApiKeys {
+    fn call<Chain>(
+        self,
+        mut state: gotham::state::State,
+        chain: Chain,
+    ) -> std::pin::Pin<Box<gotham::handler::HandlerFuture>>
+    where
+        Chain: FnOnce(gotham::state::State) -> std::pin::Pin<Box<gotham::handler::HandlerFuture>>
+            + Send
+            + 'static,
+    {
+        if !self.required() {
+            return chain(state);
+        }
+
+        // Every key is compared, so that the time taken does not tell which one came close
+        let key = bearer(&state).and_then(|given| {
+            self.keys
+                .iter()
+                .filter(|key| same_digest(&key.digest, &given))
+                .fold(None, |_, key| Some(key.clone()))
+        });
+
+        if let Some(key) = key {
+            state.put(key);
+            return chain(state);
+        }
+
+        let credentials = self.provider.as_ref().and_then(|_| {
+            use gotham::state::FromState;
+
+            oidc::Credentials::from_headers(hyper::HeaderMap::borrow_from(&state))
+        });
+        let (Some(provider), Some(credentials)) = (self.provider, credentials) else {
+            return Box::pin(std::future::ready(Err((
+                state,
+                Error::Unauthorized.into_handler_error(),
+            ))));
+        };
+
+        Box::pin(async move {
+            match provider.authenticate(credentials).await {
+                Ok(subject) => {
+                    state.put(ApiKey::user(&subject));
+                    chain(state).await
+                }
+                Err(e) => Err((state, Error::SignIn(e).into_handler_error())),
+            }
+        })
+    }
+}
+
+// Records the lifecycle of the secrets to the audit log, when there is one
+#[derive(Clone, gotham_derive::StateData, gotham_derive::NewMiddleware)]
+pub struct Audit(Option<std::sync::Arc<std::sync::Mutex<audit::Log>>>);
+
+impl Audit {
+    pub fn new(log: Option<audit::Log>) -> Self {
+        Self(log.map(|log| std::sync::Arc::new(std::sync::Mutex::new(log))))
+    }
+
+    // Records `event` of the secret `id`, caused by the client of the request
+    pub fn record(
+        state: &gotham::state::State,
+        event: audit::Event,
+        id: &store::Id,
+        size: Option<u64>,
+        expires: Option<std::time::SystemTime>,
+    ) {
+        Self::write(state, |log, client, now| {
+            log.record(event, &id.encode(), size, expires, client, now)
+        });
+    }
+
+    // Records every outstanding secret as purged by the client of the request
+    pub fn purge(state: &gotham::state::State) {
+        Self::write(state, audit::Log::purge);
+    }
+
+    // Records the secrets that expired, without waiting for the next event
+    pub fn expire(state: &gotham::state::State) {
+        Self::write(state, |log, _, now| log.expire(now));
+    }
+
+    fn write(
+        state: &gotham::state::State,
+        write: impl FnOnce(
+            &mut audit::Log,
+            audit::Client<'_>,
+            std::time::SystemTime,
+        ) -> Result<(), audit::Error>,
+    ) {
+        use gotham::state::FromState;
+
+        let Some(log) = Self::try_borrow_from(state).and_then(|audit| audit.0.as_ref()) else {
+            return;
+        };
+        let client = audit::Client {
+            key: ApiKey::try_borrow_from(state).map(ApiKey::label),
+            ip: ClientIp::try_borrow_from(state).map(|client| client.ip),
+        };
+
+        let mut log = log
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        // Requests are not failed for it, but the chain shows what is missing
+        if let Err(e) = write(&mut log, client, std::time::SystemTime::now()) {
+            log::error!("Could not write to the audit log: {e}");
+        }
+    }
+}
+
+impl gotham::middleware::Middleware for Audit {
+    fn call<Chain>(
+        self,
+        mut state: gotham::state::State,
+        chain: Chain,
+    ) -> std::pin::Pin<Box<gotham::handler::HandlerFuture>>
+    where
+        Chain: FnOnce(gotham::state::State) -> std::pin::Pin<Box<gotham::handler::HandlerFuture>>,
+    {
+        state.put(self);
+        chain(state)
+    }
+}
+
+// Notifies the senders that asked for it when their secrets are read or expire
+#[derive(Clone, gotham_derive::StateData, gotham_derive::NewMiddleware)]
+pub struct Webhooks(std::sync::Arc<webhook::Hooks>);
+
+impl Webhooks {
+    pub fn new(hosts: Vec<webhook::Host>, secret: Option<&str>, attempts: u32) -> Self {
+        Self(std::sync::Arc::new(webhook::Hooks::new(
+            hosts, secret, attempts,
+        )))
+    }
+
+    pub fn hooks(&self) -> &webhook::Hooks {
+        &self.0
+    }
+
+    // Checks the callback URL of an upload, before the secret is stored
+    pub fn check(state: &gotham::state::State, url: &str) -> Result<hyper::Uri, Error> {
+        use gotham::state::FromState;
+
+        Self::try_borrow_from(state)
+            .map_or(Err(webhook::Refused::Disabled), |webhooks| {
+                webhooks.0.check(url)
+            })
+            .map_err(Error::Callback)
+    }
+
+    pub fn retrieved(state: &gotham::state::State, id: &store::Id) {
+        use gotham::state::FromState;
+
+        if let Some(webhooks) = Self::try_borrow_from(state) {
+            webhooks.0.retrieved(id);
+        }
+    }
+
+    // Forgets the callbacks of secrets removed by an operator, who are not reported
+    pub fn cancel(state: &gotham::state::State, id: Option<&store::Id>) {
+        use gotham::state::FromState;
+
+        match (Self::try_borrow_from(state), id) {
+            (Some(webhooks), Some(id)) => webhooks.0.cancel(id),
+            (Some(webhooks), None) => webhooks.0.clear(),
+            (None, _) => {}
+        }
+    }
+}
+
+impl gotham::middleware::Middleware for Webhooks {
+    fn call<Chain>(
+        self,
+        mut state: gotham::state::State,
+        chain: Chain,
+    ) -> std::pin::Pin<Box<gotham::handler::HandlerFuture>>
+    where
+        Chain: FnOnce(gotham::state::State) -> std::pin::Pin<Box<gotham::handler::HandlerFuture>>,
+    {
+        state.put(self);
+        chain(state)
+    }
+}
+
+// Issues and checks the puzzles anonymous clients solve before uploading
+#[derive(Clone, gotham_derive::StateData, gotham_derive::NewMiddleware)]
+pub struct ProofOfWork(std::sync::Arc<pow::Puzzles>);
+
+impl ProofOfWork {
+    pub fn new(difficulty: Option<u8>, secret: Option<&str>) -> Self {
+        Self(std::sync::Arc::new(pow::Puzzles::new(difficulty, secret)))
+    }
+
+    pub fn puzzles(&self) -> &pow::Puzzles {
+        &self.0
+    }
+}
+
+impl gotham::middleware::Middleware for ProofOfWork {
+    fn call<Chain>(
+        self,
+        mut state: gotham::state::State,
+        chain: Chain,
+    ) -> std::pin::Pin<Box<gotham::handler::HandlerFuture>>
+    where
+        Chain: FnOnce(gotham::state::State) -> std::pin::Pin<Box<gotham::handler::HandlerFuture>>,
+    {
+        state.put(self);
+        chain(state)
+    }
+}
+
+// Answers the errors of the routes behind it with problem details, including those of the
+// middlewares that follow
+#[derive(Clone, gotham_derive::NewMiddleware)]
+pub struct ProblemDetails;
+
+impl gotham::middleware::Middleware for ProblemDetails {
+    fn call<Chain>(
+        self,
+        mut state: gotham::state::State,
+        chain: Chain,
+    ) -> std::pin::Pin<Box<gotham::handler::HandlerFuture>>
+    where
+        Chain: FnOnce(gotham::state::State) -> std::pin::Pin<Box<gotham::handler::HandlerFuture>>,
+    {
+        state.put(error::Problems);
+        chain(state)
+    }
+}
+
+// Limits how often each client may call the routes behind it, and bans those that look for
+// missing secrets too often
+#[derive(Clone, gotham_derive::NewMiddleware)]
+pub struct RateLimit(std::sync::Arc<rate::Limiter<Owner>>);
+
+impl RateLimit {
+    pub fn new(rate: Option<rate::Rate>, ban: Option<rate::Ban>) -> Self {
+        Self(std::sync::Arc::new(rate::Limiter::new(rate, ban)))
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.0.enabled()
+    }
+}
+
+impl gotham::middleware::Middleware for RateLimit {
+    fn call<Chain>(
+        self,
+        state: gotham::state::State,
+        chain: Chain,
+    ) -> std::pin::Pin<Box<gotham::handler::HandlerFuture>>
+    where
+        Chain: FnOnce(gotham::state::State) -> std::pin::Pin<Box<gotham::handler::HandlerFuture>>
+            + Send
+            + 'static,
+    {
+        // Clients without an address cannot be told apart, so they are left alone
+        let Some(owner) = Owner::of(&state) else {
+            return chain(state);
+        };
+
+        if let Err(denied) = self.0.acquire(&owner, std::time::Instant::now()) {
+            let error = match denied {
+                rate::Denied::Limited(time) => Error::RateLimited(time),
+                rate::Denied::Banned(time) => Error::Banned(time),
+            };
+            return Box::pin(std::future::ready(Err((state, error.into_handler_error()))));
+        }
+
+        if !self.0.bans() {
+            return chain(state);
+        }
+
+        Box::pin(async move {
+            let result = chain(state).await;
+            let status = match &result {
+                Ok((_, response)) => response.status(),
+                Err((_, error)) => error.status(),
+            };
+            if status == hyper::StatusCode::NOT_FOUND
+                && self.0.miss(&owner, std::time::Instant::now())
+            {
+                log::warn!("Banned a client after too many lookups of missing secrets");
+            }
+            result
+        })
+    }
+}
+
+// The address of the client, resolved through the trusted proxies
+#[derive(Clone, Copy, gotham_derive::StateData)]
+pub struct ClientIp {
+    pub ip: std::net::IpAddr,
+    // Whether the address was taken from the forwarding headers
+    pub forwarded: bool,
+}
+
+#[derive(Clone, gotham_derive::NewMiddleware)]
+pub struct ClientAddress(std::sync::Arc<Vec<proxy::Cidr>>);
+
+impl ClientAddress {
+    pub fn new(trusted: Vec<proxy::Cidr>) -> Self {
+        Self(std::sync::Arc::new(trusted))
+    }
+}
+
+impl gotham::middleware::Middleware for ClientAddress {
+    fn call<Chain>(
+        self,
+        mut state: gotham::state::State,
+        chain: Chain,
+    ) -> std::pin::Pin<Box<gotham::handler::HandlerFuture>>
+    where
+        Chain: FnOnce(gotham::state::State) -> std::pin::Pin<Box<gotham::handler::HandlerFuture>>,
+    {
+        use gotham::state::FromState;
+
+        if let Some(peer) = gotham::state::client_addr(&state) {
+            let (ip, forwarded) =
+                proxy::resolve(peer.ip(), hyper::HeaderMap::borrow_from(&state), &self.0);
+            state.put(ClientIp { ip, forwarded });
+        }
+        chain(state)
+    }
+}
+
 impl Log {
     #[inline]
     fn log_level(error: &Error) -> log::Level {
@@ -49,15 +560,31 @@ impl Log {
         match error {
             Error::NothingToInsert
             | Error::ContentLengthMissing
+            | Error::TtlTooLong(_)
+            | Error::InvalidBody(_)
+            | Error::ReadOnly
+            | Error::TtlQuota(_)
+            | Error::ProofOfWork(_)
+            | Error::Callback(_)
+            | Error::RateLimited(_)
+            | Error::Banned(_)
             | Error::Store(
                 StoreError::TooLarge | StoreError::SecretNotFound | StoreError::InvalidId(_),
             ) => log::Level::Info,
-            Error::Store(StoreError::Generic(_)) | Error::PayloadTooLarge | Error::ReadTimeout => {
-                log::Level::Warn
-            }
-            Error::Store(StoreError::StoreFull) | Error::Hyper(_) | Error::FailedToAcquireStore => {
-                log::Level::Error
-            }
+            Error::Store(StoreError::StoreFull)
+            | Error::Hyper(_)
+            | Error::FailedToAcquireStore
+            | Error::SignIn(oidc::Invalid::Provider(_)) => log::Level::Error,
+            Error::Store(StoreError::Generic(_))
+            | Error::PayloadTooLarge
+            | Error::ReadTimeout
+            | Error::TooSlow(_)
+            | Error::HeaderTimeout(_)
+            | Error::IdleTimeout(_)
+            | Error::Unauthorized
+            | Error::SignIn(_)
+            | Error::SecretQuota(_)
+            | Error::ByteQuota(_) => log::Level::Warn,
         }
     }
 
@@ -72,67 +599,109 @@ impl Log {
         }
     }
 
-    #[inline]
-    fn status_to_color(status: u16) -> colored::ColoredString {
-        use colored::Colorize;
-        if status < 200 {
-            status.to_string().blue()
-        } else if status < 400 {
-            status.to_string().green()
-        } else if status < 500 {
-            status.to_string().yellow()
-        } else if status < 600 {
-            status.to_string().red()
-        } else {
-            status.to_string().white()
-        }
+    fn template(&self, route: &str) -> Option<&access::Template> {
+        self.0
+            .templates
+            .iter()
+            .find(|template| template.route == route)
+            .or_else(|| {
+                self.0
+                    .templates
+                    .iter()
+                    .find(|template| template.route == "*")
+            })
+    }
+
+    fn client_ip(&self, state: &gotham::state::State) -> (Option<String>, bool) {
+        use gotham::state::FromState;
+
+        ClientIp::try_borrow_from(state).map_or((Some(String::from("??")), false), |client| {
+            (
+                access::mask_ip(client.ip, self.0.client_ip).map(|ip| ip.to_string()),
+                client.forwarded,
+            )
+        })
     }
 
     fn log(
+        &self,
         state: &gotham::state::State,
         level: log::Level,
         status: u16,
-        tail: &impl std::fmt::Display,
+        outcome: Result<Option<u64>, Option<&Error>>,
         start: std::time::Instant,
     ) {
-        use colored::Colorize;
         use gotham::state::FromState;
 
-        let ip = hyper::HeaderMap::borrow_from(state)
-            .get("x-forwarded-for")
-            .and_then(|fwd| fwd.to_str().ok())
-            .map_or_else(
-                || {
-                    gotham::state::client_addr(state)
-                        .map_or_else(|| String::from("??"), |addr| addr.ip().to_string())
-                },
-                |fwd| format!("{fwd} [p]"),
-            );
+        let (client_ip, forwarded) = self.client_ip(state);
+        let route = handler::Route::name(state);
 
-        let method = hyper::Method::borrow_from(state);
-        let path = hyper::Uri::borrow_from(state).to_string().white();
-        let request_length = hyper::HeaderMap::borrow_from(state)
-            .get(hyper::header::CONTENT_LENGTH)
-            .and_then(|len| len.to_str().ok())
-            .map_or_else(String::new, |len| format!(" {len}b"));
-
-        // Log out
-        log::log!(
-            level,
-            "{} {} {}{} - {}{} - {:?}",
-            ip,
-            method,
-            path,
-            request_length,
-            Self::status_to_color(status),
-            tail,
-            start.elapsed()
-        );
+        let access = access::Access {
+            client_ip,
+            forwarded,
+            key: ApiKey::try_borrow_from(state).map(ApiKey::label),
+            method: hyper::Method::borrow_from(state).as_str(),
+            path: access::sanitize_path(&hyper::Uri::borrow_from(state).to_string(), self.0.ids),
+            route,
+            status,
+            request_size: hyper::HeaderMap::borrow_from(state)
+                .get(hyper::header::CONTENT_LENGTH)
+                .and_then(|len| len.to_str().ok())
+                .and_then(|len| len.parse::<u64>().ok()),
+            response_size: outcome.ok().flatten(),
+            latency: start.elapsed(),
+            error: outcome.err().map(|error| {
+                error.map_or(("unknown", String::from("Unknown error")), |e| {
+                    (e.code(), e.to_string())
+                })
+            }),
+        };
+
+        access.log(level, self.template(route));
+    }
+
+    // Logs a connection that the listener closed on its own, under the `connection` route
+    pub fn closed(&self, peer: std::net::SocketAddr, error: &Error, age: std::time::Duration) {
+        let access = access::Access {
+            client_ip: access::mask_ip(peer.ip(), self.0.client_ip).map(|ip| ip.to_string()),
+            forwarded: false,
+            key: None,
+            method: "-",
+            path: String::from("-"),
+            route: "connection",
+            status: error.status_code().as_u16(),
+            request_size: None,
+            response_size: None,
+            latency: age,
+            error: Some((error.code(), error.to_string())),
+        };
+
+        access.log(Self::log_level(error), self.template("connection"));
     }
 }
 
 #[derive(Clone, gotham_derive::NewMiddleware)]
-pub struct Log;
+pub struct Log(std::sync::Arc<LogOptions>);
+
+struct LogOptions {
+    ids: options::IdPolicy,
+    client_ip: options::IpPolicy,
+    templates: Vec<access::Template>,
+}
+
+impl Log {
+    pub fn new(
+        ids: options::IdPolicy,
+        client_ip: options::IpPolicy,
+        templates: Vec<access::Template>,
+    ) -> Self {
+        Self(std::sync::Arc::new(LogOptions {
+            ids,
+            client_ip,
+            templates,
+        }))
+    }
+}
 
 impl gotham::middleware::Middleware for Log {
     fn call<C>(
@@ -145,58 +714,452 @@ impl gotham::middleware::Middleware for Log {
             + Send
             + 'static,
     {
-        Box::pin(async {
+        Box::pin(async move {
             let start = std::time::Instant::now();
-            chain(state)
-                .await
-                .map(move |(state, response)| {
+            match chain(state).await {
+                Ok((state, response)) => {
                     let status = response.status().as_u16();
                     let length = gotham::hyper::body::HttpBody::size_hint(response.body())
                         .exact()
-                        .filter(|len| *len > 0)
-                        .map_or_else(String::new, |len| format!(" {len}b"));
+                        .filter(|len| *len > 0);
 
-                    Self::log(&state, log::Level::Info, status, &length, start);
+                    self.log(&state, log::Level::Info, status, Ok(length), start);
 
-                    (state, response)
-                })
-                .map_err(|(state, error)| {
+                    Ok((state, response))
+                }
+                Err((state, error)) => {
                     let status = error.status().as_u16();
-                    let (level, error_message) = error.downcast_cause_ref::<Error>().map_or_else(
-                        || (Self::log_level_for(status), " [Unknown error]".to_owned()),
-                        |e| (Self::log_level(e), format!(" [{e}]")),
-                    );
+                    let cause = error.downcast_cause_ref::<Error>();
+                    let level = cause.map_or_else(|| Self::log_level_for(status), Self::log_level);
+
+                    self.log(&state, level, status, Err(cause), start);
+
+                    Err((state, error))
+                }
+            }
+        })
+    }
+}
+
+#[derive(Clone, gotham_derive::StateData, gotham_derive::NewMiddleware)]
+pub struct Metrics(std::sync::Arc<metrics::Metrics>);
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self(std::sync::Arc::new(metrics::Metrics::default()))
+    }
+
+    pub fn created(&self, size: usize) {
+        self.0.created(size);
+    }
+
+    pub fn retrieved(&self, size: usize) {
+        self.0.retrieved(size);
+    }
+
+    pub fn error(&self, error: &Error) {
+        self.0.error(error.code());
+    }
+
+    pub fn render(&self, stats: Option<&store::Stats>) -> String {
+        self.0.render(stats)
+    }
+}
+
+impl gotham::middleware::Middleware for Metrics {
+    fn call<C>(
+        self,
+        mut state: gotham::state::State,
+        chain: C,
+    ) -> std::pin::Pin<Box<gotham::handler::HandlerFuture>>
+    where
+        C: FnOnce(gotham::state::State) -> std::pin::Pin<Box<gotham::handler::HandlerFuture>>
+            + Send
+            + 'static,
+    {
+        let metrics = self.0.clone();
+        state.put(self);
+
+        Box::pin(async move {
+            let start = std::time::Instant::now();
+            let result = chain(state).await;
+
+            let (state, status) = match &result {
+                Ok((state, response)) => (state, response.status()),
+                Err((state, error)) => {
+                    if let Some(error) = error.downcast_cause_ref::<Error>() {
+                        metrics.error(error.code());
+                    }
+                    (state, error.status())
+                }
+            };
+            metrics.request(
+                handler::Route::name(state),
+                status.as_u16(),
+                start.elapsed(),
+            );
+
+            result
+        })
+    }
+}
+
+#[derive(Clone, gotham_derive::NewMiddleware)]
+pub struct SecurityHeaders(std::sync::Arc<SecurityOptions>);
+
+struct SecurityOptions {
+    content_security_policy: hyper::header::HeaderValue,
+    strict_transport_security: Option<hyper::header::HeaderValue>,
+}
+
+impl SecurityHeaders {
+    // Responses other than pages load nothing and must not be rendered as documents
+    const API_CONTENT_SECURITY_POLICY: &str = "default-src 'none'; frame-ancestors 'none'";
+
+    pub fn new(content_security_policy: hyper::header::HeaderValue, hsts: Option<u64>) -> Self {
+        Self(std::sync::Arc::new(SecurityOptions {
+            content_security_policy,
+            strict_transport_security: hsts.and_then(|max_age| {
+                hyper::header::HeaderValue::from_str(&format!("max-age={max_age}")).ok()
+            }),
+        }))
+    }
+
+    fn apply(&self, route: &str, headers: &mut hyper::HeaderMap) {
+        use hyper::header;
+
+        let content_security_policy = if route == "index" {
+            self.0.content_security_policy.clone()
+        } else {
+            header::HeaderValue::from_static(Self::API_CONTENT_SECURITY_POLICY)
+        };
 
-                    Self::log(&state, level, status, &error_message, start);
+        headers.insert(header::CONTENT_SECURITY_POLICY, content_security_policy);
+        headers.insert(
+            header::X_CONTENT_TYPE_OPTIONS,
+            header::HeaderValue::from_static("nosniff"),
+        );
+        headers.insert(
+            header::REFERRER_POLICY,
+            header::HeaderValue::from_static("no-referrer"),
+        );
+        headers.insert(
+            header::X_FRAME_OPTIONS,
+            header::HeaderValue::from_static("DENY"),
+        );
+        if let Some(hsts) = &self.0.strict_transport_security {
+            headers.insert(header::STRICT_TRANSPORT_SECURITY, hsts.clone());
+        }
+    }
+}
 
-                    (state, error)
+impl gotham::middleware::Middleware for SecurityHeaders {
+    fn call<C>(
+        self,
+        state: gotham::state::State,
+        chain: C,
+    ) -> std::pin::Pin<Box<gotham::handler::HandlerFuture>>
+    where
+        C: FnOnce(gotham::state::State) -> std::pin::Pin<Box<gotham::handler::HandlerFuture>>
+            + Send
+            + 'static,
+    {
+        Box::pin(async move {
+            chain(state)
+                .await
+                .or_else(|(state, err)| {
+                    let response = error::into_response(&state, err);
+                    Ok((state, response))
+                })
+                .map(move |(state, mut response)| {
+                    self.apply(handler::Route::name(&state), response.headers_mut());
+                    (state, response)
                 })
         })
     }
 }
 
+// Who a secret is accounted to: the key it was uploaded with, or else the address of the client.
+// IPv6 clients are told apart by their /64, which is usually given whole to a single client
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Owner {
+    Key(std::sync::Arc<str>),
+    Ip(std::net::IpAddr),
+}
+
+impl Owner {
+    pub fn of(state: &gotham::state::State) -> Option<Self> {
+        use gotham::state::FromState;
+
+        ApiKey::try_borrow_from(state)
+            .map(|key| Self::Key(key.label.clone()))
+            .or_else(|| ClientIp::try_borrow_from(state).map(|client| Self::Ip(network(client.ip))))
+    }
+
+    // Names the owner in the store, hashed so that client addresses are not kept in the clear
+    fn tag(&self) -> String {
+        use sha2::Digest;
+
+        let name = match self {
+            Self::Key(label) => format!("key:{label}"),
+            Self::Ip(ip) => format!("ip:{ip}"),
+        };
+        base64::Engine::encode(
+            &base64::engine::general_purpose::URL_SAFE_NO_PAD,
+            sha2::Sha256::digest(name),
+        )
+    }
+}
+
+fn network(ip: std::net::IpAddr) -> std::net::IpAddr {
+    match ip {
+        std::net::IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => std::net::IpAddr::V4(ip),
+            None => std::net::IpAddr::V6((ip.to_bits() & !u128::from(u64::MAX)).into()),
+        },
+        ip @ std::net::IpAddr::V4(_) => ip,
+    }
+}
+
+// What a single owner may keep in the store at once. Unset limits are not enforced
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Quotas {
+    pub secrets: Option<usize>,
+    pub bytes: Option<u64>,
+    pub ttl: Option<std::time::Duration>,
+}
+
+impl Quotas {
+    pub fn enabled(&self) -> bool {
+        self.secrets.is_some() || self.bytes.is_some() || self.ttl.is_some()
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+struct Usage {
+    secrets: usize,
+    bytes: u64,
+}
+
+// The outstanding secrets of each owner, by tag, released once they are read, revoked or expire
+#[derive(Default)]
+struct Ledger {
+    secrets: std::collections::HashMap<store::Id, (String, u64, std::time::SystemTime)>,
+    usage: std::collections::HashMap<String, Usage>,
+}
+
+impl Ledger {
+    // Accounts the secrets the store kept from a previous run to their owners
+    fn rebuild(store: &dyn store::Store) -> Self {
+        let mut ledger = Self::default();
+        for (id, owned) in store.owned() {
+            ledger.insert(id, owned.owner, owned.size, owned.expiry);
+        }
+        if !ledger.secrets.is_empty() {
+            log::info!(
+                "Accounted {} stored secrets to {} owners",
+                ledger.secrets.len(),
+                ledger.usage.len()
+            );
+        }
+        ledger
+    }
+
+    fn refresh(&mut self) {
+        let now = std::time::SystemTime::now();
+        let expired = self
+            .secrets
+            .iter()
+            .filter(|(_, (_, _, expiry))| *expiry <= now)
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        for id in &expired {
+            self.release(id);
+        }
+    }
+
+    fn usage(&self, owner: &str) -> Usage {
+        self.usage.get(owner).copied().unwrap_or_default()
+    }
+
+    fn insert(&mut self, id: store::Id, owner: String, size: u64, expiry: std::time::SystemTime) {
+        let usage = self.usage.entry(owner.clone()).or_default();
+        usage.secrets += 1;
+        usage.bytes += size;
+        self.secrets.insert(id, (owner, size, expiry));
+    }
+
+    fn release(&mut self, id: &store::Id) {
+        let Some((owner, size, _)) = self.secrets.remove(id) else {
+            return;
+        };
+        if let Some(usage) = self.usage.get_mut(&owner) {
+            usage.secrets -= 1;
+            usage.bytes -= size;
+            if usage.secrets == 0 {
+                self.usage.remove(&owner);
+            }
+        }
+    }
+
+    fn clear(&mut self) {
+        self.secrets.clear();
+        self.usage.clear();
+    }
+}
+
 #[derive(Clone, gotham_derive::StateData, gotham_derive::NewMiddleware)]
-pub struct Store(std::sync::Arc<std::sync::Mutex<dyn 'static + store::Store + Send>>);
+pub struct Store {
+    inner: std::sync::Arc<std::sync::Mutex<dyn 'static + store::Store + Send>>,
+    // Rejects uploads while still serving downloads
+    read_only: std::sync::Arc<std::sync::atomic::AtomicBool>,
+    quotas: Quotas,
+    // Only kept when quotas are enabled. Locked after `inner` when both are needed
+    ledger: std::sync::Arc<std::sync::Mutex<Ledger>>,
+}
 
 impl Store {
-    pub fn new(store: impl 'static + store::Store + Send) -> Self {
-        Self(std::sync::Arc::new(std::sync::Mutex::new(store)))
+    pub fn new(store: impl 'static + store::Store + Send, quotas: Quotas) -> Self {
+        let ledger = if quotas.enabled() {
+            Ledger::rebuild(&store)
+        } else {
+            Ledger::default()
+        };
+
+        Self {
+            inner: std::sync::Arc::new(std::sync::Mutex::new(store)),
+            read_only: std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false)),
+            quotas,
+            ledger: std::sync::Arc::new(std::sync::Mutex::new(ledger)),
+        }
+    }
+
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, dyn 'static + store::Store + Send>, Error> {
+        self.refreshed().map(|(store, _)| store)
+    }
+
+    // Locks the store once it dropped the secrets that expired, telling how many there were
+    fn refreshed(
+        &self,
+    ) -> Result<
+        (
+            std::sync::MutexGuard<'_, dyn 'static + store::Store + Send>,
+            usize,
+        ),
+        Error,
+    > {
+        let mut store = self.inner.lock().map_err(|_| Error::FailedToAcquireStore)?;
+        let expired = store.purge(store::Purge::Expired);
+        Ok((store, expired))
+    }
+
+    fn ledger(&self) -> Result<Option<std::sync::MutexGuard<'_, Ledger>>, Error> {
+        if !self.quotas.enabled() {
+            return Ok(None);
+        }
+        let mut ledger = self
+            .ledger
+            .lock()
+            .map_err(|_| Error::FailedToAcquireStore)?;
+        ledger.refresh();
+        Ok(Some(ledger))
     }
 
     pub fn put(
         &mut self,
         data: Vec<u8>,
         expiry: std::time::SystemTime,
+        owner: Option<Owner>,
+        callback: Option<&hyper::Uri>,
     ) -> Result<store::Id, Error> {
-        let mut store = self.0.lock().map_err(|_| Error::FailedToAcquireStore)?;
-        store.refresh();
-        store.put(expiry, data).map_err(Error::Store)
+        if self.read_only() {
+            return Err(Error::ReadOnly);
+        }
+
+        let mut store = self.lock()?;
+        let mut ledger = self.ledger()?;
+        let size = data.len() as u64;
+        // Owners are only recorded when there are quotas to hold them to
+        let owner = owner.filter(|_| ledger.is_some()).map(|owner| owner.tag());
+
+        if let (Some(ledger), Some(owner)) = (&ledger, &owner) {
+            self.check(ledger.usage(owner), size, expiry)?;
+        }
+
+        let callback = callback.map(ToString::to_string);
+        let id = store
+            .put(expiry, owner.as_deref(), callback.as_deref(), data)
+            .map_err(Error::Store)?;
+        if let (Some(ledger), Some(owner)) = (&mut ledger, owner) {
+            ledger.insert(id, owner, size, expiry);
+        }
+        Ok(id)
+    }
+
+    fn check(&self, usage: Usage, size: u64, expiry: std::time::SystemTime) -> Result<(), Error> {
+        if let Some(ttl) = self.quotas.ttl
+            && expiry > std::time::SystemTime::now() + ttl
+        {
+            return Err(Error::TtlQuota(ttl));
+        }
+        if let Some(secrets) = self.quotas.secrets
+            && usage.secrets >= secrets
+        {
+            return Err(Error::SecretQuota(secrets));
+        }
+        if let Some(bytes) = self.quotas.bytes
+            && usage.bytes + size > bytes
+        {
+            return Err(Error::ByteQuota(bytes));
+        }
+        Ok(())
     }
 
     pub fn get(&mut self, key: &store::Id) -> Result<Vec<u8>, Error> {
-        let mut store = self.0.lock().map_err(|_| Error::FailedToAcquireStore)?;
-        store.refresh();
-        store.get(key).map_err(Error::Store)
+        let data = self.lock()?.get(key).map_err(Error::Store)?;
+        if let Some(mut ledger) = self.ledger()? {
+            ledger.release(key);
+        }
+        Ok(data)
+    }
+
+    pub fn revoke(&mut self, key: &store::Id) -> Result<(), Error> {
+        self.lock()?.revoke(key).map_err(Error::Store)?;
+        if let Some(mut ledger) = self.ledger()? {
+            ledger.release(key);
+        }
+        Ok(())
+    }
+
+    pub fn purge(&mut self, scope: store::Purge) -> Result<usize, Error> {
+        let (mut store, expired) = self.refreshed()?;
+        let purged = match scope {
+            store::Purge::Expired => expired,
+            store::Purge::All => expired + store.purge(scope),
+        };
+        if scope == store::Purge::All
+            && let Some(mut ledger) = self.ledger()?
+        {
+            ledger.clear();
+        }
+        Ok(purged)
+    }
+
+    pub fn stats(&mut self) -> Result<store::Stats, Error> {
+        Ok(self.lock()?.stats())
+    }
+
+    pub fn available(&mut self) -> Result<u64, Error> {
+        self.lock()?.available().map_err(Error::Store)
+    }
+
+    pub fn read_only(&self) -> bool {
+        self.read_only.load(std::sync::atomic::Ordering::Relaxed)
+    }
+
+    pub fn set_read_only(&self, read_only: bool) {
+        self.read_only
+            .store(read_only, std::sync::atomic::Ordering::Relaxed);
     }
 }
 
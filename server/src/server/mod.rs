@@ -1,90 +1,702 @@
+#[cfg(any(feature = "host-frontend", test))]
+mod embedded;
 mod error;
 mod handler;
+mod metrics;
 mod middleware;
+mod oidc;
+mod openapi;
+mod pow;
+pub mod proxy;
+pub mod rate;
+pub mod webhook;
+
+pub use error::Error;
+pub use handler::convert_str_to_duration as parse_ttl;
+pub use middleware::ApiKey;
 
 use super::options::Options;
 use super::store;
 
+pub struct Routers {
+    pub public: gotham::router::Router,
+    pub admin: Option<gotham::router::Router>,
+    pub connections: Connections,
+}
+
+// Told about the connections that the listener closes on its own, which no route ever sees
+#[derive(Clone)]
+pub struct Connections {
+    metrics: middleware::Metrics,
+    log: middleware::Log,
+}
+
+impl Connections {
+    pub fn closed(&self, peer: std::net::SocketAddr, error: &Error, age: std::time::Duration) {
+        self.metrics.error(error);
+        self.log.closed(peer, error, age);
+    }
+}
+
 // Allowed because you can't create closures that share the same captures
+//
+// `admin_activated` tells whether the service manager passed sockets for the admin API
+//
+// Fails when the audit log cannot be opened
 #[allow(clippy::option_if_let_else)]
-pub fn route(options: Options) -> gotham::router::Router {
+pub fn route(options: Options, admin_activated: bool) -> std::io::Result<Routers> {
     use gotham::pipeline;
-    use gotham::router::builder;
-
-    let web_path = options.web_path;
 
-    let store = options.store_path.map_or_else(
-        || middleware::Store::new(store::in_memory()),
-        |path| middleware::Store::new(store::in_file(path)),
+    let guards = Guards::new(&options);
+    let quotas = middleware::Quotas {
+        secrets: options.quota_secrets,
+        bytes: options.quota_bytes,
+        ttl: options.quota_ttl,
+    };
+    let shared = Shared::new(&options, quotas)?;
+    let connections = shared.connections();
+    let index = index(options.web_path);
+    let base_path = options.base_path.unwrap_or_default();
+    let api_url = if index.is_some() {
+        format!("{base_path}/api/")
+    } else {
+        format!("{base_path}/")
+    };
+    let limits = handler::Limits {
+        max_ttl: options.max_ttl,
+        body_timeout: std::time::Duration::from_secs(options.body_timeout),
+        min_upload_rate: options.min_upload_rate,
+    };
+    let work = middleware::ProofOfWork::new(
+        options.proof_of_work,
+        options.proof_of_work_secret.as_deref(),
+    );
+    let sign_in = sign_in(&guards.api_keys, &base_path);
+    let config = handler::Config::new(
+        &api_url,
+        sign_in,
+        limits,
+        &features(&guards, quotas, &work, &shared.webhooks),
     );
 
-    if let Some(cors) = options.cors {
+    // Metrics, health checks and the admin API are either served together with the API or on
+    // their own listener
+    let admin_token = options.admin_token.as_deref();
+    let (admin, admin_router) = if options.admin_listen.is_empty() && !admin_activated {
+        (
+            Admin::Public {
+                metrics: options.metrics,
+                access: admin_token.map(|token| handler::admin::Access::new(Some(token), false)),
+            },
+            None,
+        )
+    } else {
+        (
+            Admin::Separate,
+            Some(admin_route(
+                shared.clone(),
+                handler::admin::Access::new(admin_token, true),
+            )),
+        )
+    };
+
+    let public = if options.cors.is_empty() {
         let pipeline = pipeline::new_pipeline()
-            .add(store)
-            .add(middleware::Cors::new(cors))
-            .add(middleware::Log)
+            .add(shared.client_address)
+            .add(shared.store)
+            .add(shared.audit)
+            .add(shared.webhooks)
+            .add(work)
+            .add(shared.security_headers)
+            .add(shared.metrics)
+            .add(shared.log)
             .build();
 
-        let (chain, pipelines) = pipeline::single_pipeline(pipeline);
-
-        builder::build_router(chain, pipelines, |route| {
-            wrap_routes(route, web_path, true);
-        })
+        public_route(pipeline, &guards, &base_path, index, &config, false, admin)
     } else {
+        // Front-ends on other origins cannot send their proofs of work unless the header is allowed
+        let mut cors_headers = options.cors_headers;
+        let proof_of_work = gotham::hyper::header::HeaderName::from_static(handler::PROOF_OF_WORK);
+        if work.puzzles().enabled() && !cors_headers.contains(&proof_of_work) {
+            cors_headers.push(proof_of_work);
+        }
+        let cors = middleware::Cors::new(
+            options.cors,
+            &options.cors_methods,
+            &cors_headers,
+            &options.cors_expose_headers,
+            options.cors_max_age,
+        );
         let pipeline = pipeline::new_pipeline()
-            .add(store)
-            .add(middleware::Log)
+            .add(shared.client_address)
+            .add(shared.store)
+            .add(shared.audit)
+            .add(shared.webhooks)
+            .add(work)
+            .add(shared.security_headers)
+            .add(cors)
+            .add(shared.metrics)
+            .add(shared.log)
             .build();
 
-        let (chain, pipelines) = pipeline::single_pipeline(pipeline);
+        public_route(pipeline, &guards, &base_path, index, &config, true, admin)
+    };
+
+    Ok(Routers {
+        public,
+        admin: admin_router,
+        connections,
+    })
+}
+
+// The front-end served with the API, from `web_path` or else embedded when built with it
+fn index(web_path: Option<(std::path::PathBuf, std::path::PathBuf)>) -> Option<handler::Index> {
+    let index = web_path.map(|(root, index)| {
+        log::info!("Serving front-end at {}", root.display());
+        handler::Index::new(root, index)
+    });
+    #[cfg(feature = "host-frontend")]
+    let index = index.or_else(|| {
+        log::info!("Serving embedded front-end");
+        Some(handler::Index::embedded())
+    });
+    index
+}
+
+// Opens the audit log at `path`, if any. Secrets kept in memory do not outlive the previous
+// run, so the ones it left outstanding are recorded as purged
+fn audit_log(
+    path: Option<&std::path::Path>,
+    client_ip: super::options::IpPolicy,
+    in_memory: bool,
+) -> std::io::Result<Option<crate::logger::audit::Log>> {
+    let Some(path) = path else {
+        return Ok(None);
+    };
+    let failed =
+        |e| std::io::Error::other(format!("Could not open audit log {}: {e}", path.display()));
+    let mut log = crate::logger::audit::Log::open(path, client_ip).map_err(failed)?;
+    if in_memory {
+        log.purge(
+            crate::logger::audit::Client::default(),
+            std::time::SystemTime::now(),
+        )
+        .map_err(failed)?;
+    }
+    log::info!("Recording the lifecycle of secrets to {}", path.display());
+    Ok(Some(log))
+}
+
+// Follows the callbacks of the secrets the store kept from a previous run, taken before it drops
+// those that expired meanwhile so that their senders are still told
+fn open_store(
+    store: impl 'static + store::Store + Send,
+    quotas: middleware::Quotas,
+    webhooks: &middleware::Webhooks,
+) -> middleware::Store {
+    webhooks.hooks().rebuild(&store);
+    middleware::Store::new(store, quotas)
+}
+
+// The middlewares of both the API and the admin API, which share their state
+#[derive(Clone)]
+struct Shared {
+    client_address: middleware::ClientAddress,
+    store: middleware::Store,
+    audit: middleware::Audit,
+    webhooks: middleware::Webhooks,
+    security_headers: middleware::SecurityHeaders,
+    metrics: middleware::Metrics,
+    log: middleware::Log,
+}
+
+impl Shared {
+    fn new(options: &Options, quotas: middleware::Quotas) -> std::io::Result<Self> {
+        let webhooks = middleware::Webhooks::new(
+            options.webhook_hosts.clone(),
+            options.webhook_secret.as_deref(),
+            options.webhook_attempts,
+        );
+        let store = options.store_path.clone().map_or_else(
+            || open_store(store::in_memory(), quotas, &webhooks),
+            |path| open_store(store::in_file(path), quotas, &webhooks),
+        );
+
+        Ok(Self {
+            client_address: middleware::ClientAddress::new(options.trusted_proxy.clone()),
+            store,
+            audit: middleware::Audit::new(audit_log(
+                options.audit_log.as_deref(),
+                options.audit_client_ip,
+                options.store_path.is_none(),
+            )?),
+            webhooks,
+            security_headers: middleware::SecurityHeaders::new(
+                options.content_security_policy.clone(),
+                options.hsts,
+            ),
+            metrics: middleware::Metrics::new(),
+            log: middleware::Log::new(
+                options.log_ids,
+                options.log_client_ip,
+                options.log_template.clone(),
+            ),
+        })
+    }
+
+    fn connections(&self) -> Connections {
+        Connections {
+            metrics: self.metrics.clone(),
+            log: self.log.clone(),
+        }
+    }
+}
+
+// The checks made before storing or retrieving secrets
+struct Guards {
+    api_keys: middleware::ApiKeys,
+    create: middleware::RateLimit,
+    retrieve: middleware::RateLimit,
+}
+
+impl Guards {
+    fn new(options: &Options) -> Self {
+        // Lookups of missing secrets count towards bans only on the retrieve routes
+        let ban = options.ban_after.map(|misses| rate::Ban {
+            misses,
+            time: options.ban_time,
+        });
+
+        Self {
+            api_keys: middleware::ApiKeys::new(options.api_key.clone(), provider(options)),
+            create: middleware::RateLimit::new(options.create_rate, None),
+            retrieve: middleware::RateLimit::new(options.retrieve_rate, ban),
+        }
+    }
+}
+
+// The OpenID Connect provider users sign in with, if any
+fn provider(options: &Options) -> Option<oidc::Provider> {
+    let (issuer, client_id) = options
+        .oidc_issuer
+        .clone()
+        .zip(options.oidc_client_id.clone())?;
+    log::info!("Signing users in with {issuer}");
+
+    Some(oidc::Provider::new(oidc::Settings {
+        issuer,
+        audiences: if options.oidc_audience.is_empty() {
+            vec![client_id.clone()]
+        } else {
+            options.oidc_audience.clone()
+        },
+        client_id,
+        client_secret: options.oidc_client_secret.clone(),
+        scopes: options.oidc_scopes.clone(),
+        groups: options.oidc_group.clone(),
+        groups_claim: options.oidc_groups_claim.clone(),
+        redirect_url: options.oidc_redirect_url.clone(),
+        session_secret: options.oidc_session_secret.clone(),
+    }))
+}
 
-        builder::build_router(chain, pipelines, |route| {
-            wrap_routes(route, web_path, false);
+// Signing in through the browser, when the provider allows it
+fn sign_in(api_keys: &middleware::ApiKeys, base_path: &str) -> Option<handler::auth::SignIn> {
+    api_keys
+        .provider()
+        .filter(|provider| provider.signs_in())
+        .map(|provider| handler::auth::SignIn {
+            url: format!("{base_path}/auth/login"),
+            provider: provider.clone(),
         })
+}
+
+// The optional features that change how clients must call the API, which the front-end and the
+// OpenAPI description are told about
+fn features(
+    guards: &Guards,
+    quotas: middleware::Quotas,
+    work: &middleware::ProofOfWork,
+    webhooks: &middleware::Webhooks,
+) -> Vec<&'static str> {
+    [
+        (guards.api_keys.required(), "api_key"),
+        (guards.api_keys.provider().is_some(), "oidc"),
+        (
+            guards
+                .api_keys
+                .provider()
+                .is_some_and(|provider| provider.signs_in()),
+            "sign_in",
+        ),
+        (quotas.enabled(), "quotas"),
+        (
+            guards.create.enabled() || guards.retrieve.enabled(),
+            "rate_limit",
+        ),
+        (work.puzzles().enabled(), "proof_of_work"),
+        (webhooks.hooks().enabled(), "webhooks"),
+    ]
+    .into_iter()
+    .filter_map(|(enabled, feature)| enabled.then_some(feature))
+    .collect()
+}
+
+// Uploads and lookups go through their own pipelines after `pipeline`, so that only they are
+// checked for a key and rate limited
+fn public_route<T>(
+    pipeline: gotham::pipeline::Pipeline<T>,
+    guards: &Guards,
+    base_path: &str,
+    index: Option<handler::Index>,
+    config: &handler::Config,
+    with_cors: bool,
+    admin: Admin,
+) -> gotham::router::Router
+where
+    T: gotham::middleware::chain::NewMiddlewareChain
+        + std::panic::RefUnwindSafe
+        + Send
+        + Sync
+        + 'static,
+    T::Instance: Send + 'static,
+{
+    use gotham::pipeline;
+
+    let upload = pipeline::new_pipeline()
+        .add(guards.api_keys.clone())
+        .add(guards.create.clone())
+        .build();
+    let upload_v2 = pipeline::new_pipeline()
+        .add(middleware::ProblemDetails)
+        .add(guards.api_keys.clone())
+        .add(guards.create.clone())
+        .build();
+    let lookup = pipeline::new_pipeline()
+        .add(guards.retrieve.clone())
+        .build();
+    let lookup_v2 = pipeline::new_pipeline()
+        .add(middleware::ProblemDetails)
+        .add(guards.retrieve.clone())
+        .build();
+
+    let (pipelines, default) = pipeline::new_pipeline_set().add(pipeline);
+    let (pipelines, upload) = pipelines.add(upload);
+    let (pipelines, upload_v2) = pipelines.add(upload_v2);
+    let (pipelines, lookup) = pipelines.add(lookup);
+    let (pipelines, lookup_v2) = pipelines.add(lookup_v2);
+    let pipelines = pipeline::finalize_pipeline_set(pipelines);
+    let chain = (default, ());
+    let chains = Chains {
+        upload: (upload, chain),
+        upload_v2: (upload_v2, chain),
+        lookup: (lookup, chain),
+        lookup_v2: (lookup_v2, chain),
+    };
+
+    gotham::router::builder::build_router(chain, pipelines, |route| {
+        wrap_routes(route, base_path, index, config, chains, with_cors, admin);
+    })
+}
+
+// The pipeline chains of the routes that store and retrieve secrets, for the raw body and the
+// JSON routes
+#[derive(Clone, Copy)]
+struct Chains<U, V, R, S> {
+    upload: U,
+    upload_v2: V,
+    lookup: R,
+    lookup_v2: S,
+}
+
+// Where the metrics, health checks and admin API are served
+#[derive(Clone, Copy)]
+enum Admin {
+    Public {
+        metrics: bool,
+        // The admin API is only served together with the API when a token is required
+        access: Option<handler::admin::Access>,
+    },
+    Separate,
+}
+
+fn admin_route(shared: Shared, access: handler::admin::Access) -> gotham::router::Router {
+    use gotham::pipeline;
+    use gotham::router::builder;
+
+    let pipeline = pipeline::new_pipeline()
+        .add(shared.client_address)
+        .add(shared.store)
+        .add(shared.audit)
+        .add(shared.webhooks)
+        .add(shared.security_headers)
+        .add(shared.metrics)
+        .add(shared.log)
+        .build();
+
+    let (chain, pipelines) = pipeline::single_pipeline(pipeline);
+
+    builder::build_router(chain, pipelines, |route| {
+        use gotham::router::builder::DefineSingleRoute;
+        use gotham::router::builder::DrawRoutes;
+
+        route
+            .get("/metrics")
+            .to(handler::named("metrics", handler::metrics));
+        route
+            .get("/healthz")
+            .to(handler::named("health", handler::health));
+        route
+            .get("/readyz")
+            .to(handler::named("ready", handler::ready));
+        route.scope("/admin", |route| add_admin_routes(route, access));
+    })
+}
+
+fn wrap_routes<C, P, U, V, R, S>(
+    route: &mut impl gotham::router::builder::DrawRoutes<C, P>,
+    base_path: &str,
+    index: Option<handler::Index>,
+    config: &handler::Config,
+    chains: Chains<U, V, R, S>,
+    with_cors: bool,
+    admin: Admin,
+) where
+    C: gotham::pipeline::PipelineHandleChain<P> + Copy + Send + Sync + 'static,
+    P: std::panic::RefUnwindSafe + Send + Sync + 'static,
+    U: gotham::pipeline::PipelineHandleChain<P> + Copy + Send + Sync + 'static,
+    V: gotham::pipeline::PipelineHandleChain<P> + Copy + Send + Sync + 'static,
+    R: gotham::pipeline::PipelineHandleChain<P> + Copy + Send + Sync + 'static,
+    S: gotham::pipeline::PipelineHandleChain<P> + Copy + Send + Sync + 'static,
+{
+    if base_path.is_empty() {
+        draw_routes(route, index, config, chains, with_cors, admin);
+    } else {
+        route.scope(base_path, |route| {
+            draw_routes(route, index, config, chains, with_cors, admin);
+        });
     }
 }
 
-fn wrap_routes<C, P>(
+fn draw_routes<C, P, U, V, R, S>(
     route: &mut impl gotham::router::builder::DrawRoutes<C, P>,
-    web_path: Option<(std::path::PathBuf, std::path::PathBuf)>,
+    index: Option<handler::Index>,
+    config: &handler::Config,
+    chains: Chains<U, V, R, S>,
     with_cors: bool,
+    admin: Admin,
 ) where
     C: gotham::pipeline::PipelineHandleChain<P> + Copy + Send + Sync + 'static,
     P: std::panic::RefUnwindSafe + Send + Sync + 'static,
+    U: gotham::pipeline::PipelineHandleChain<P> + Copy + Send + Sync + 'static,
+    V: gotham::pipeline::PipelineHandleChain<P> + Copy + Send + Sync + 'static,
+    R: gotham::pipeline::PipelineHandleChain<P> + Copy + Send + Sync + 'static,
+    S: gotham::pipeline::PipelineHandleChain<P> + Copy + Send + Sync + 'static,
 {
     use gotham::router::builder::DefineSingleRoute;
 
-    if let Some(web_path) = web_path {
-        log::info!("Serving front-end at {}", web_path.0.display());
+    if let Admin::Public { metrics: true, .. } = admin {
+        route
+            .get("/metrics")
+            .to(handler::named("metrics", handler::metrics));
+        if with_cors {
+            preflight(route, &["/metrics"]);
+        }
+    }
+    if let Admin::Public { .. } = admin {
+        route
+            .get("/healthz")
+            .to(handler::named("health", handler::health));
+        route
+            .get("/readyz")
+            .to(handler::named("ready", handler::ready));
+        if with_cors {
+            preflight(route, &["/healthz", "/readyz"]);
+        }
+    }
+    if let Admin::Public {
+        access: Some(access),
+        ..
+    } = admin
+    {
+        route.scope("/admin", |route| add_admin_routes(route, access));
+    }
+    route.get("/config.json").to_new_handler(config.clone());
+    if with_cors {
+        preflight(route, &["/config.json"]);
+    }
+    if let Some(sign_in) = config.sign_in() {
+        route
+            .get("/auth/login")
+            .with_query_string_extractor::<handler::auth::LoginExtractor>()
+            .to_new_handler(handler::auth::Login::new(sign_in.provider.clone()));
+        route
+            .get("/auth/callback")
+            .with_query_string_extractor::<handler::auth::CallbackExtractor>()
+            .to_new_handler(handler::auth::Callback::new(sign_in.provider.clone()));
+    }
+
+    if let Some(index) = index {
         route
             .get("/*")
-            .with_path_extractor::<gotham::handler::FilePathExtractor>()
-            .to_new_handler(handler::Index::new(web_path.0, web_path.1.clone()));
-        route.get("/").to_file(web_path.1);
-        route.scope("/api", |route| add_routes(route, with_cors));
+            .with_path_extractor::<handler::AssetPath>()
+            .to_new_handler(index.clone());
+        route.get("/").to_new_handler(index);
+        route.scope("/api", |route| {
+            add_routes(route, config, chains, with_cors);
+        });
     } else {
-        add_routes(route, with_cors);
+        add_routes(route, config, chains, with_cors);
     }
 }
 
-fn add_routes<C, P>(route: &mut impl gotham::router::builder::DrawRoutes<C, P>, with_cors: bool)
-where
+fn add_routes<C, P, U, V, R, S>(
+    route: &mut impl gotham::router::builder::DrawRoutes<C, P>,
+    config: &handler::Config,
+    chains: Chains<U, V, R, S>,
+    with_cors: bool,
+) where
+    C: gotham::pipeline::PipelineHandleChain<P> + Copy + Send + Sync + 'static,
+    P: std::panic::RefUnwindSafe + Send + Sync + 'static,
+    U: gotham::pipeline::PipelineHandleChain<P> + Copy + Send + Sync + 'static,
+    V: gotham::pipeline::PipelineHandleChain<P> + Copy + Send + Sync + 'static,
+    R: gotham::pipeline::PipelineHandleChain<P> + Copy + Send + Sync + 'static,
+    S: gotham::pipeline::PipelineHandleChain<P> + Copy + Send + Sync + 'static,
+{
+    use gotham::router::builder::DefineSingleRoute;
+    use gotham::router::builder::DrawRoutes;
+
+    const ID: &str = "/:id:[a-zA-Z0-9_\\-]{43}";
+
+    let limits = config.limits();
+
+    route.with_pipeline_chain(chains.upload, |route| {
+        route
+            .post("/")
+            .with_query_string_extractor::<handler::TtlExtractor>()
+            .to(handler::named("create", move |state| {
+                handler::post(state, limits)
+            }));
+    });
+    route.with_pipeline_chain(chains.lookup, |route| {
+        route
+            .get(ID)
+            .with_path_extractor::<handler::IdExtractor>()
+            .to(handler::named("retrieve", handler::get));
+    });
+
+    route.scope("/v2", |route| {
+        add_v2_routes(route, limits, chains.upload_v2, chains.lookup_v2, with_cors);
+    });
+    route
+        .get("/openapi.json")
+        .to_new_handler(handler::OpenApi::new(
+            config.api_url(),
+            limits.max_ttl,
+            config.features(),
+        ));
+
+    if with_cors {
+        preflight(route, &["/", ID, "/openapi.json"]);
+    }
+}
+
+// JSON bodies in both directions, and problem details for errors
+fn add_v2_routes<C, P, V, S>(
+    route: &mut impl gotham::router::builder::DrawRoutes<C, P>,
+    limits: handler::Limits,
+    upload: V,
+    lookup: S,
+    with_cors: bool,
+) where
     C: gotham::pipeline::PipelineHandleChain<P> + Copy + Send + Sync + 'static,
     P: std::panic::RefUnwindSafe + Send + Sync + 'static,
+    V: gotham::pipeline::PipelineHandleChain<P> + Copy + Send + Sync + 'static,
+    S: gotham::pipeline::PipelineHandleChain<P> + Copy + Send + Sync + 'static,
 {
     use gotham::router::builder::DefineSingleRoute;
+    use gotham::router::builder::DrawRoutes;
+
+    const SECRET: &str = "/secrets/:id:[a-zA-Z0-9_\\-]{43}";
+
+    route.with_pipeline_chain(upload, |route| {
+        route
+            .post("/secrets")
+            .to(handler::named("create_v2", move |state| {
+                handler::v2::post(state, limits)
+            }));
+    });
+    route
+        .get("/challenge")
+        .with_query_string_extractor::<handler::v2::ChallengeExtractor>()
+        .to(handler::named("challenge", handler::v2::challenge));
+    route.with_pipeline_chain(lookup, |route| {
+        route
+            .get(SECRET)
+            .with_path_extractor::<handler::IdExtractor>()
+            .to(handler::named("retrieve_v2", handler::v2::get));
+    });
 
     if with_cors {
-        route.options("/").to(|state| (state, ""));
+        preflight(route, &["/secrets", SECRET, "/challenge"]);
+    }
+}
+
+// Answers the preflight requests browsers send before cross-origin requests to these paths
+fn preflight<C, P>(route: &mut impl gotham::router::builder::DrawRoutes<C, P>, paths: &[&str])
+where
+    C: gotham::pipeline::PipelineHandleChain<P> + Copy + Send + Sync + 'static,
+    P: std::panic::RefUnwindSafe + Send + Sync + 'static,
+{
+    use gotham::router::builder::DefineSingleRoute;
+
+    for path in paths {
+        route
+            .options(path)
+            .to(handler::named("preflight", handler::preflight));
     }
+}
+
+// Inspects and manages the running instance, for the clients allowed by `access`
+fn add_admin_routes<C, P>(
+    route: &mut impl gotham::router::builder::DrawRoutes<C, P>,
+    access: handler::admin::Access,
+) where
+    C: gotham::pipeline::PipelineHandleChain<P> + Copy + Send + Sync + 'static,
+    P: std::panic::RefUnwindSafe + Send + Sync + 'static,
+{
+    use gotham::router::builder::DefineSingleRoute;
+
     route
-        .post("/")
-        .with_query_string_extractor::<handler::TtlExtractor>()
-        .to(handler::post);
+        .get("/stats")
+        .to(handler::named("admin_stats", move |state| {
+            handler::admin::stats(state, access)
+        }));
     route
-        .get("/:id:[a-zA-Z0-9_\\-]{43}")
+        .delete("/secrets/:id:[a-zA-Z0-9_\\-]{43}")
         .with_path_extractor::<handler::IdExtractor>()
-        .to(handler::get);
+        .to(handler::named("admin_revoke", move |state| {
+            handler::admin::revoke(state, access)
+        }));
+    route
+        .post("/purge")
+        .with_query_string_extractor::<handler::admin::PurgeExtractor>()
+        .to(handler::named("admin_purge", move |state| {
+            handler::admin::purge(state, access)
+        }));
+    route
+        .get("/maintenance")
+        .to(handler::named("admin_maintenance", move |state| {
+            handler::admin::maintenance(state, access)
+        }));
+    route
+        .request(
+            vec![gotham::hyper::Method::PUT, gotham::hyper::Method::DELETE],
+            "/maintenance",
+        )
+        .to(handler::named("admin_maintenance", move |state| {
+            handler::admin::set_maintenance(state, access)
+        }));
 }
 
 #[cfg(test)]
@@ -93,7 +705,10 @@ mod tests {
     use gotham::test::TestServer;
 
     use super::super::options;
-    use super::route;
+
+    fn route(options: options::Options) -> gotham::router::Router {
+        super::route(options, false).unwrap().public
+    }
 
     macro_rules! host_path {
         ($($path:literal)?) => {
@@ -102,23 +717,13 @@ mod tests {
     }
 
     fn options() -> options::Options {
-        options::Options {
-            port: 0,
-            threads: 0,
-            cors: None,
-            store_path: None,
-            web_path: None,
-        }
+        <options::Options as clap::Parser>::parse_from(["passer"])
     }
 
     fn options_with_path() -> options::Options {
-        options::Options {
-            port: 0,
-            threads: 0,
-            cors: None,
-            store_path: None,
-            web_path: Some(("res/test".into(), "res/test/index".into())),
-        }
+        let mut options = options();
+        options.web_path = Some(("res/test".into(), "res/test/index".into()));
+        options
     }
 
     #[test]
@@ -165,6 +770,14 @@ mod tests {
 
         assert_eq!(response.status(), hyper::StatusCode::OK);
 
+        let headers = response.headers();
+        assert_eq!(headers[hyper::header::CACHE_CONTROL], "no-store");
+        assert_eq!(headers[hyper::header::X_CONTENT_TYPE_OPTIONS], "nosniff");
+        assert_eq!(
+            headers[hyper::header::CONTENT_TYPE],
+            "application/octet-stream"
+        );
+
         let body = response.read_body().unwrap();
         assert_eq!(&body[..], b"foo");
     }
@@ -240,6 +853,41 @@ mod tests {
         assert!(body.is_empty());
     }
 
+    #[test]
+    fn unlimited_ttl_by_default() {
+        let test_server = TestServer::new(route(options())).unwrap();
+        let response = test_server
+            .client()
+            .post(concat!(host_path!(), "?ttl=30d"), "foo", mime::TEXT_PLAIN)
+            .perform()
+            .unwrap();
+
+        assert_eq!(response.status(), hyper::StatusCode::CREATED);
+    }
+
+    #[test]
+    fn cannot_exceed_max_ttl() {
+        let mut options = options();
+        options.max_ttl = Some(std::time::Duration::from_hours(1));
+
+        let test_server = TestServer::new(route(options)).unwrap();
+        let response = test_server
+            .client()
+            .post(concat!(host_path!(), "?ttl=2h"), "foo", mime::TEXT_PLAIN)
+            .perform()
+            .unwrap();
+
+        assert_eq!(response.status(), hyper::StatusCode::BAD_REQUEST);
+
+        let response = test_server
+            .client()
+            .post(concat!(host_path!(), "?ttl=1h"), "foo", mime::TEXT_PLAIN)
+            .perform()
+            .unwrap();
+
+        assert_eq!(response.status(), hyper::StatusCode::CREATED);
+    }
+
     #[test]
     fn secrets_expire() {
         let test_server = TestServer::new(route(options())).unwrap();
@@ -345,44 +993,177 @@ mod tests {
         assert!(cors.is_none());
     }
 
+    fn options_with_cors(args: &[&str]) -> options::Options {
+        <options::Options as clap::Parser>::parse_from(
+            ["passer", "--cors", "http://foo", "--cors", "http://bar"]
+                .iter()
+                .chain(args),
+        )
+    }
+
     #[test]
     fn with_cors() {
-        let mut options = options();
-        options.cors = Some(hyper::header::HeaderValue::from_static("bar"));
-
-        let test_server = TestServer::new(route(options)).unwrap();
+        let test_server = TestServer::new(route(options_with_cors(&[
+            "--cors-expose-headers",
+            "content-length",
+        ])))
+        .unwrap();
         let response = test_server
             .client()
             .get(host_path!("0___________________foo___________________0"))
+            .with_header(
+                hyper::header::ORIGIN,
+                hyper::header::HeaderValue::from_static("http://bar"),
+            )
             .perform()
             .unwrap();
 
-        let cors = response
-            .headers()
-            .get(hyper::header::ACCESS_CONTROL_ALLOW_ORIGIN)
+        let headers = response.headers();
+        assert_eq!(
+            headers[hyper::header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "http://bar"
+        );
+        assert_eq!(headers[hyper::header::VARY], "origin");
+        assert_eq!(
+            headers[hyper::header::ACCESS_CONTROL_EXPOSE_HEADERS],
+            "content-length"
+        );
+        assert!(!headers.contains_key(hyper::header::ACCESS_CONTROL_ALLOW_METHODS));
+    }
+
+    #[test]
+    fn cors_other_origin() {
+        let test_server = TestServer::new(route(options_with_cors(&[]))).unwrap();
+        let response = test_server
+            .client()
+            .get(host_path!("healthz"))
+            .with_header(
+                hyper::header::ORIGIN,
+                hyper::header::HeaderValue::from_static("http://baz"),
+            )
+            .perform()
             .unwrap();
 
-        assert_eq!(cors, "bar");
+        let headers = response.headers();
+        assert!(!headers.contains_key(hyper::header::ACCESS_CONTROL_ALLOW_ORIGIN));
+        assert_eq!(headers[hyper::header::VARY], "origin");
     }
 
     #[test]
-    fn preflight() {
+    fn cors_any_origin() {
         let mut options = options();
-        options.cors = Some(hyper::header::HeaderValue::from_static("bar"));
+        options.cors = vec![hyper::header::HeaderValue::from_static("*")];
 
         let test_server = TestServer::new(route(options)).unwrap();
         let response = test_server
             .client()
-            .options(host_path!())
+            .get(host_path!("healthz"))
+            .with_header(
+                hyper::header::ORIGIN,
+                hyper::header::HeaderValue::from_static("http://baz"),
+            )
             .perform()
             .unwrap();
 
-        let cors = response
-            .headers()
-            .get(hyper::header::ACCESS_CONTROL_ALLOW_ORIGIN)
+        let headers = response.headers();
+        assert_eq!(headers[hyper::header::ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+        assert!(!headers.contains_key(hyper::header::VARY));
+    }
+
+    #[test]
+    fn preflight() {
+        let test_server = TestServer::new(route(options_with_cors(&[
+            "--cors-methods",
+            "GET,POST,DELETE",
+            "--cors-max-age",
+            "600",
+        ])))
+        .unwrap();
+
+        for path in [
+            host_path!(),
+            host_path!("0___________________foo___________________0"),
+            host_path!("healthz"),
+            host_path!("readyz"),
+        ] {
+            let response = test_server
+                .client()
+                .options(path)
+                .with_header(
+                    hyper::header::ORIGIN,
+                    hyper::header::HeaderValue::from_static("http://foo"),
+                )
+                .with_header(
+                    hyper::header::ACCESS_CONTROL_REQUEST_METHOD,
+                    hyper::header::HeaderValue::from_static("POST"),
+                )
+                .perform()
+                .unwrap();
+
+            assert_eq!(response.status(), hyper::StatusCode::NO_CONTENT);
+
+            let headers = response.headers();
+            assert_eq!(
+                headers[hyper::header::ACCESS_CONTROL_ALLOW_ORIGIN],
+                "http://foo"
+            );
+            assert_eq!(
+                headers[hyper::header::ACCESS_CONTROL_ALLOW_METHODS],
+                "GET, POST, DELETE"
+            );
+            assert_eq!(
+                headers[hyper::header::ACCESS_CONTROL_ALLOW_HEADERS],
+                "content-type"
+            );
+            assert_eq!(headers[hyper::header::ACCESS_CONTROL_MAX_AGE], "600");
+        }
+    }
+
+    #[test]
+    fn preflight_allows_proof_of_work() {
+        for args in [
+            &["--proof-of-work", "8"][..],
+            &["--proof-of-work", "8", "--cors-headers", "x-proof-of-work"],
+        ] {
+            let test_server = TestServer::new(route(options_with_cors(args))).unwrap();
+            let response = test_server
+                .client()
+                .options(host_path!("v2/secrets"))
+                .with_header(
+                    hyper::header::ORIGIN,
+                    hyper::header::HeaderValue::from_static("http://foo"),
+                )
+                .with_header(
+                    hyper::header::ACCESS_CONTROL_REQUEST_METHOD,
+                    hyper::header::HeaderValue::from_static("POST"),
+                )
+                .with_header(
+                    hyper::header::ACCESS_CONTROL_REQUEST_HEADERS,
+                    hyper::header::HeaderValue::from_static("x-proof-of-work"),
+                )
+                .perform()
+                .unwrap();
+
+            let allowed = response.headers()[hyper::header::ACCESS_CONTROL_ALLOW_HEADERS]
+                .to_str()
+                .unwrap()
+                .split(", ")
+                .filter(|header| *header == "x-proof-of-work")
+                .count();
+            assert_eq!(allowed, 1, "{args:?}");
+        }
+    }
+
+    #[test]
+    fn no_preflight_without_cors() {
+        let test_server = TestServer::new(route(options())).unwrap();
+        let response = test_server
+            .client()
+            .options(host_path!())
+            .perform()
             .unwrap();
 
-        assert_eq!(cors, "bar");
+        assert_eq!(response.status(), hyper::StatusCode::METHOD_NOT_ALLOWED);
     }
 
     #[test]
@@ -434,16 +1215,2359 @@ mod tests {
     }
 
     #[test]
-    fn api_still_gets_served() {
+    fn missing_asset() {
         let test_server = TestServer::new(route(options_with_path())).unwrap();
         let response = test_server
             .client()
-            .get(host_path!(
-                "api/0___________________foo___________________0"
-            ))
+            .get(host_path!("static/main.deadbeef.js"))
             .perform()
             .unwrap();
 
         assert_eq!(response.status(), hyper::StatusCode::NOT_FOUND);
     }
+
+    #[test]
+    fn asset_caching() {
+        let test_server = TestServer::new(route(options_with_path())).unwrap();
+
+        let response = test_server
+            .client()
+            .get(host_path!("static/main.0123abcd.js"))
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), hyper::StatusCode::OK);
+        assert_eq!(
+            response.headers()[hyper::header::CACHE_CONTROL],
+            "public, max-age=31536000, immutable"
+        );
+        assert_eq!(response.headers()[hyper::header::VARY], "accept-encoding");
+        let etag = response.headers()[hyper::header::ETAG].clone();
+
+        let response = test_server
+            .client()
+            .get(host_path!("static/main.0123abcd.js"))
+            .with_header(hyper::header::IF_NONE_MATCH, etag)
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), hyper::StatusCode::NOT_MODIFIED);
+
+        for path in [host_path!(), host_path!("foo"), host_path!("d/")] {
+            let response = test_server.client().get(path).perform().unwrap();
+            assert_eq!(response.status(), hyper::StatusCode::OK);
+            assert_eq!(response.headers()[hyper::header::CACHE_CONTROL], "no-cache");
+        }
+    }
+
+    #[test]
+    fn precompressed_assets() {
+        let test_server = TestServer::new(route(options_with_path())).unwrap();
+
+        let response = test_server
+            .client()
+            .get(host_path!("static/main.0123abcd.js"))
+            .with_header(
+                hyper::header::ACCEPT_ENCODING,
+                hyper::header::HeaderValue::from_static("br, gzip"),
+            )
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), hyper::StatusCode::OK);
+        assert_eq!(response.headers()[hyper::header::CONTENT_ENCODING], "gzip");
+        assert_eq!(
+            response.read_body().unwrap(),
+            std::fs::read("res/test/static/main.0123abcd.js.gz").unwrap()
+        );
+
+        let response = test_server
+            .client()
+            .get(host_path!("static/main.0123abcd.js"))
+            .perform()
+            .unwrap();
+        assert!(
+            !response
+                .headers()
+                .contains_key(hyper::header::CONTENT_ENCODING)
+        );
+        assert_eq!(&response.read_body().unwrap()[..], b"hashed\n");
+    }
+
+    #[test]
+    fn wasm_mime_type() {
+        let test_server = TestServer::new(route(options_with_path())).unwrap();
+        let response = test_server
+            .client()
+            .get(host_path!("module.wasm"))
+            .perform()
+            .unwrap();
+
+        assert_eq!(response.status(), hyper::StatusCode::OK);
+        assert_eq!(
+            response.headers()[hyper::header::CONTENT_TYPE],
+            "application/wasm"
+        );
+    }
+
+    #[test]
+    fn api_still_gets_served() {
+        let test_server = TestServer::new(route(options_with_path())).unwrap();
+        let response = test_server
+            .client()
+            .get(host_path!(
+                "api/0___________________foo___________________0"
+            ))
+            .perform()
+            .unwrap();
+
+        assert_eq!(response.status(), hyper::StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn metrics() {
+        let mut options = options();
+        options.metrics = true;
+
+        let test_server = TestServer::new(route(options)).unwrap();
+        let response = test_server
+            .client()
+            .post(concat!(host_path!(), "?ttl=1m"), "foo", mime::TEXT_PLAIN)
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), hyper::StatusCode::CREATED);
+
+        let response = test_server
+            .client()
+            .get(host_path!("0___________________foo___________________0"))
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), hyper::StatusCode::NOT_FOUND);
+
+        let response = test_server
+            .client()
+            .get(host_path!("metrics"))
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), hyper::StatusCode::OK);
+
+        let body = String::from_utf8(response.read_body().unwrap()).unwrap();
+        assert!(body.contains(
+            "passer_http_request_duration_seconds_count{route=\"create\",status=\"201\"} 1\n"
+        ));
+        assert!(body.contains(
+            "passer_http_request_duration_seconds_count{route=\"retrieve\",status=\"404\"} 1\n"
+        ));
+        assert!(body.contains("passer_errors_total{error=\"secret_not_found\"} 1\n"));
+        assert!(body.contains("passer_uploaded_bytes_total 3\n"));
+        assert!(body.contains("passer_secrets_created_total 1\n"));
+        assert!(body.contains("passer_store_secrets 1\n"));
+        assert!(body.contains("passer_store_bytes 3\n"));
+    }
+
+    #[test]
+    fn no_metrics() {
+        let test_server = TestServer::new(route(options())).unwrap();
+        let response = test_server
+            .client()
+            .get(host_path!("metrics"))
+            .perform()
+            .unwrap();
+
+        assert_eq!(response.status(), hyper::StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn admin_on_dedicated_listener() {
+        let mut options = options();
+        options.admin_listen = vec!["127.0.0.1:0".parse().unwrap()];
+
+        let routers = super::route(options, false).unwrap();
+        let public = TestServer::new(routers.public).unwrap();
+        let metrics = TestServer::new(routers.admin.unwrap()).unwrap();
+
+        let response = public
+            .client()
+            .post(concat!(host_path!(), "?ttl=1m"), "foo", mime::TEXT_PLAIN)
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), hyper::StatusCode::CREATED);
+
+        let response = public
+            .client()
+            .get(host_path!("metrics"))
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), hyper::StatusCode::NOT_FOUND);
+
+        let response = metrics
+            .client()
+            .get(host_path!("metrics"))
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), hyper::StatusCode::OK);
+
+        let body = String::from_utf8(response.read_body().unwrap()).unwrap();
+        assert!(body.contains("passer_secrets_created_total 1\n"));
+
+        for path in [host_path!("healthz"), host_path!("readyz")] {
+            let response = public.client().get(path).perform().unwrap();
+            assert_eq!(response.status(), hyper::StatusCode::NOT_FOUND);
+
+            let response = metrics.client().get(path).perform().unwrap();
+            assert_eq!(response.status(), hyper::StatusCode::OK);
+        }
+    }
+
+    #[test]
+    fn admin_on_activated_socket() {
+        let routers = super::route(options(), true).unwrap();
+        let public = TestServer::new(routers.public).unwrap();
+        let admin = TestServer::new(routers.admin.unwrap()).unwrap();
+
+        let response = public
+            .client()
+            .get(host_path!("healthz"))
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), hyper::StatusCode::NOT_FOUND);
+
+        let response = admin.client().get(host_path!("healthz")).perform().unwrap();
+        assert_eq!(response.status(), hyper::StatusCode::OK);
+    }
+
+    #[test]
+    fn admin_listen_options() {
+        let options = <options::Options as clap::Parser>::parse_from([
+            "passer",
+            "--admin-listen",
+            "127.0.0.1:9090",
+            "--metrics-listen",
+            "unix:/run/passer-admin.sock",
+        ]);
+        assert_eq!(options.admin_listen.len(), 2);
+    }
+
+    #[test]
+    fn metrics_with_front_end() {
+        let mut options = options_with_path();
+        options.metrics = true;
+
+        let test_server = TestServer::new(route(options)).unwrap();
+        let response = test_server
+            .client()
+            .get(host_path!("metrics"))
+            .perform()
+            .unwrap();
+
+        assert_eq!(response.status(), hyper::StatusCode::OK);
+        let body = String::from_utf8(response.read_body().unwrap()).unwrap();
+        assert!(body.contains("passer_store_capacity_bytes"));
+    }
+
+    #[test]
+    fn health() {
+        let test_server = TestServer::new(route(options_with_path())).unwrap();
+        let response = test_server
+            .client()
+            .get(host_path!("healthz"))
+            .perform()
+            .unwrap();
+
+        assert_eq!(response.status(), hyper::StatusCode::OK);
+        let body = response.read_body().unwrap();
+        assert_eq!(&body[..], b"ok");
+    }
+
+    #[test]
+    fn ready() {
+        let test_server = TestServer::new(route(options())).unwrap();
+        let response = test_server
+            .client()
+            .get(host_path!("readyz"))
+            .perform()
+            .unwrap();
+
+        assert_eq!(response.status(), hyper::StatusCode::OK);
+        let body = String::from_utf8(response.read_body().unwrap()).unwrap();
+        assert!(body.starts_with("ready: "));
+        assert!(body.ends_with(" bytes available"));
+    }
+
+    #[test]
+    fn not_ready() {
+        let path = std::env::temp_dir().join("passer_test_not_ready");
+
+        let mut options = options();
+        options.store_path = Some(path.clone());
+
+        let test_server = TestServer::new(route(options)).unwrap();
+        std::fs::remove_dir_all(&path).unwrap();
+
+        let response = test_server
+            .client()
+            .get(host_path!("readyz"))
+            .perform()
+            .unwrap();
+
+        assert_eq!(response.status(), hyper::StatusCode::SERVICE_UNAVAILABLE);
+        let body = String::from_utf8(response.read_body().unwrap()).unwrap();
+        assert!(body.starts_with("not ready: "));
+    }
+
+    #[test]
+    fn audit_log() {
+        let path = std::env::temp_dir().join("passer_test_audit_log.jsonl");
+        let _ = std::fs::remove_file(&path);
+
+        let mut options = options_with_admin_token();
+        options.audit_log = Some(path.clone());
+        let test_server = TestServer::new(route(options)).unwrap();
+
+        let body = r#"{"data":"Zm9v","ttl_seconds":60}"#;
+        let read = read_json(post_v2(&test_server, body))["id"]
+            .as_str()
+            .unwrap()
+            .to_owned();
+        let revoked = read_json(post_v2(&test_server, body))["id"]
+            .as_str()
+            .unwrap()
+            .to_owned();
+
+        let response = test_server
+            .client()
+            .get(format!(concat!(host_path!("v2/secrets/"), "{}"), read))
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), hyper::StatusCode::OK);
+        let uri = format!(concat!(host_path!("admin/secrets/"), "{}"), revoked);
+        let response = admin(
+            &test_server,
+            hyper::Method::DELETE,
+            &uri,
+            Some("Bearer hunter2"),
+        );
+        assert_eq!(response.status(), hyper::StatusCode::NO_CONTENT);
+        post_v2(&test_server, body);
+        let response = admin(
+            &test_server,
+            hyper::Method::POST,
+            host_path!("admin/purge?scope=all"),
+            Some("Bearer hunter2"),
+        );
+        assert_eq!(response.status(), hyper::StatusCode::OK);
+
+        let log = std::fs::read_to_string(&path).unwrap();
+        let entries = log
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            entries
+                .iter()
+                .map(|entry| entry["event"].as_str().unwrap())
+                .collect::<Vec<_>>(),
+            [
+                "created",
+                "created",
+                "retrieved",
+                "revoked",
+                "created",
+                "purged"
+            ]
+        );
+        assert_eq!(entries[2]["size"], 3);
+        assert_eq!(entries[2]["secret"], entries[0]["secret"]);
+        assert!(!log.contains(&read) && !log.contains(&revoked));
+        assert_eq!(crate::logger::audit::verify(&path).unwrap().entries, 6);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn broken_audit_log() {
+        let path = std::env::temp_dir().join("passer_test_broken_audit_log.jsonl");
+        std::fs::write(&path, "{}\n").unwrap();
+
+        let mut options = options();
+        options.audit_log = Some(path.clone());
+        let error = super::route(options, false).err().unwrap();
+        assert!(error.to_string().starts_with("Could not open audit log"));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    fn assert_security_headers(response: &gotham::test::TestResponse, csp: &str) {
+        let headers = response.headers();
+        assert_eq!(headers[hyper::header::CONTENT_SECURITY_POLICY], csp);
+        assert_eq!(headers[hyper::header::X_CONTENT_TYPE_OPTIONS], "nosniff");
+        assert_eq!(headers[hyper::header::REFERRER_POLICY], "no-referrer");
+        assert_eq!(headers[hyper::header::X_FRAME_OPTIONS], "DENY");
+        assert!(!headers.contains_key(hyper::header::STRICT_TRANSPORT_SECURITY));
+    }
+
+    #[test]
+    fn security_headers() {
+        const API_CSP: &str = "default-src 'none'; frame-ancestors 'none'";
+
+        let mut options = options_with_path();
+        options.metrics = true;
+        options.cors = vec![hyper::header::HeaderValue::from_static("*")];
+        let page_csp = options.content_security_policy.clone();
+
+        let test_server = TestServer::new(route(options)).unwrap();
+        let client = test_server.client();
+
+        let response = client
+            .post(
+                concat!(host_path!("api/"), "?ttl=1m"),
+                "foo",
+                mime::TEXT_PLAIN,
+            )
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), hyper::StatusCode::CREATED);
+        assert_eq!(response.headers()[hyper::header::CACHE_CONTROL], "no-store");
+        assert_security_headers(&response, API_CSP);
+        let key = String::from_utf8(response.read_body().unwrap()).unwrap();
+
+        let response = client
+            .get(format!(concat!(host_path!("api/"), "{}"), key))
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), hyper::StatusCode::OK);
+        assert_security_headers(&response, API_CSP);
+
+        for path in [host_path!(), host_path!("foo"), host_path!("d/")] {
+            let response = client.get(path).perform().unwrap();
+            assert_eq!(response.status(), hyper::StatusCode::OK);
+            assert_security_headers(&response, page_csp.to_str().unwrap());
+        }
+
+        for path in [
+            host_path!("healthz"),
+            host_path!("readyz"),
+            host_path!("metrics"),
+            host_path!("api/0___________________foo___________________0"),
+        ] {
+            let response = client.get(path).perform().unwrap();
+            assert_security_headers(&response, API_CSP);
+        }
+
+        let response = client.options(host_path!("api/")).perform().unwrap();
+        assert_eq!(response.status(), hyper::StatusCode::NO_CONTENT);
+        assert_security_headers(&response, API_CSP);
+    }
+
+    #[test]
+    fn hsts() {
+        let mut options = options();
+        options.hsts = Some(31_536_000);
+
+        let test_server = TestServer::new(route(options)).unwrap();
+        let response = test_server
+            .client()
+            .get(host_path!("healthz"))
+            .perform()
+            .unwrap();
+
+        assert_eq!(
+            response.headers()[hyper::header::STRICT_TRANSPORT_SECURITY],
+            "max-age=31536000"
+        );
+    }
+
+    #[test]
+    fn config() {
+        let test_server = TestServer::new(route(options_with_path())).unwrap();
+        let response = test_server
+            .client()
+            .get(host_path!("config.json"))
+            .perform()
+            .unwrap();
+
+        assert_eq!(response.status(), hyper::StatusCode::OK);
+        assert_eq!(
+            response.headers()[hyper::header::CONTENT_TYPE],
+            "application/json"
+        );
+        let body = String::from_utf8(response.read_body().unwrap()).unwrap();
+        assert_eq!(
+            body,
+            r#"{"api_url":"/api/","max_secret_size":115343360,"ttl":{"min_seconds":0},"features":[]}"#
+        );
+    }
+
+    #[test]
+    fn config_without_front_end() {
+        let mut options = options();
+        options.max_ttl = Some(std::time::Duration::from_hours(1));
+
+        let test_server = TestServer::new(route(options)).unwrap();
+        let response = test_server
+            .client()
+            .get(host_path!("config.json"))
+            .perform()
+            .unwrap();
+
+        let body = String::from_utf8(response.read_body().unwrap()).unwrap();
+        assert!(body.starts_with(r#"{"api_url":"/","#));
+        assert!(body.contains(r#""max_seconds":3600"#));
+    }
+
+    fn options_with_base_path() -> options::Options {
+        let mut options = options_with_path();
+        options.base_path = Some("/passer".into());
+        options
+    }
+
+    #[test]
+    fn base_path() {
+        let test_server = TestServer::new(route(options_with_base_path())).unwrap();
+        let client = test_server.client();
+
+        let response = client
+            .post(
+                host_path!("passer/api/?ttl=1m"),
+                "foo",
+                mime::APPLICATION_OCTET_STREAM,
+            )
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), hyper::StatusCode::CREATED);
+        let id = String::from_utf8(response.read_body().unwrap()).unwrap();
+
+        let response = client
+            .get(format!("{}{id}", host_path!("passer/api/")))
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), hyper::StatusCode::OK);
+
+        for path in [host_path!("passer/"), host_path!("passer/d/")] {
+            let response = client.get(path).perform().unwrap();
+            assert_eq!(response.status(), hyper::StatusCode::OK);
+            assert_eq!(&response.read_body().unwrap()[..], b"main_page\n");
+        }
+
+        let response = client.get(host_path!("passer/foo")).perform().unwrap();
+        assert_eq!(&response.read_body().unwrap()[..], b"bar\n");
+
+        let response = client
+            .get(host_path!("passer/missing.js"))
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), hyper::StatusCode::NOT_FOUND);
+
+        let response = client
+            .get(host_path!("passer/config.json"))
+            .perform()
+            .unwrap();
+        let body = String::from_utf8(response.read_body().unwrap()).unwrap();
+        assert!(body.starts_with(r#"{"api_url":"/passer/api/","#));
+
+        for path in [host_path!(), host_path!("foo"), host_path!("api/")] {
+            let response = client.get(path).perform().unwrap();
+            assert_eq!(response.status(), hyper::StatusCode::NOT_FOUND);
+        }
+    }
+
+    #[test]
+    fn base_path_redirect() {
+        let test_server = TestServer::new(route(options_with_base_path())).unwrap();
+        let response = test_server
+            .client()
+            .get(host_path!("passer?foo=bar"))
+            .perform()
+            .unwrap();
+
+        assert_eq!(response.status(), hyper::StatusCode::PERMANENT_REDIRECT);
+        assert_eq!(
+            response.headers()[hyper::header::LOCATION],
+            "/passer/?foo=bar"
+        );
+    }
+
+    #[test]
+    fn base_path_without_front_end() {
+        let mut options = options();
+        options.base_path = Some("/passer".into());
+
+        let test_server = TestServer::new(route(options)).unwrap();
+        let response = test_server
+            .client()
+            .get(host_path!("passer/config.json"))
+            .perform()
+            .unwrap();
+
+        let body = String::from_utf8(response.read_body().unwrap()).unwrap();
+        assert!(body.starts_with(r#"{"api_url":"/passer/","#));
+    }
+
+    #[test]
+    fn invalid_base_path() {
+        let parse = |path| {
+            <options::Options as clap::Parser>::try_parse_from(["passer", "--base-path", path])
+                .map(|options| options.base_path)
+        };
+
+        assert_eq!(parse("/passer/").unwrap().as_deref(), Some("/passer"));
+        assert_eq!(
+            parse("/tools/passer").unwrap().as_deref(),
+            Some("/tools/passer")
+        );
+        for path in ["/", "", "passer", "/a//b", "/:id", "/*"] {
+            assert!(parse(path).is_err(), "{path}");
+        }
+    }
+
+    fn post_v2(test_server: &TestServer, body: &str) -> gotham::test::TestResponse {
+        test_server
+            .client()
+            .post(
+                host_path!("v2/secrets"),
+                body.to_owned(),
+                mime::APPLICATION_JSON,
+            )
+            .perform()
+            .unwrap()
+    }
+
+    fn read_json(response: gotham::test::TestResponse) -> serde_json::Value {
+        serde_json::from_slice(&response.read_body().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn v2_roundtrip() {
+        let test_server = TestServer::new(route(options())).unwrap();
+
+        let response = post_v2(&test_server, r#"{"data":"Zm9v","ttl_seconds":60}"#);
+        assert_eq!(response.status(), hyper::StatusCode::CREATED);
+        assert_eq!(
+            response.headers()[hyper::header::CONTENT_TYPE],
+            "application/json"
+        );
+        assert_eq!(response.headers()[hyper::header::CACHE_CONTROL], "no-store");
+        let created = read_json(response);
+        assert_eq!(created["size"], 3);
+        let id = created["id"].as_str().unwrap().to_owned();
+
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let expires_at = created["expires_at"].as_u64().unwrap();
+        assert!((now + 50..=now + 70).contains(&expires_at));
+
+        let response = test_server
+            .client()
+            .get(format!("{}{id}", host_path!("v2/secrets/")))
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), hyper::StatusCode::OK);
+        assert_eq!(
+            read_json(response),
+            serde_json::json!({ "id": id, "data": "Zm9v", "size": 3 })
+        );
+
+        let response = test_server
+            .client()
+            .get(format!("{}{id}", host_path!("v2/secrets/")))
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), hyper::StatusCode::NOT_FOUND);
+        assert_eq!(
+            response.headers()[hyper::header::CONTENT_TYPE],
+            "application/problem+json"
+        );
+        assert_eq!(
+            read_json(response),
+            serde_json::json!({
+                "type": "about:blank",
+                "title": "Not Found",
+                "status": 404,
+                "detail": "secret not found",
+                "code": "secret_not_found",
+            })
+        );
+    }
+
+    #[test]
+    fn v2_errors() {
+        let mut options = options();
+        options.max_ttl = Some(std::time::Duration::from_hours(24));
+
+        let test_server = TestServer::new(route(options)).unwrap();
+
+        for (body, status, code) in [
+            ("{", hyper::StatusCode::BAD_REQUEST, "invalid_body"),
+            (
+                r#"{"data":"Zm9v"}"#,
+                hyper::StatusCode::BAD_REQUEST,
+                "invalid_body",
+            ),
+            (
+                r#"{"data":"not base64!","ttl_seconds":60}"#,
+                hyper::StatusCode::BAD_REQUEST,
+                "invalid_body",
+            ),
+            (
+                r#"{"data":"","ttl_seconds":60}"#,
+                hyper::StatusCode::UNPROCESSABLE_ENTITY,
+                "nothing_to_insert",
+            ),
+            (
+                r#"{"data":"Zm9v","ttl_seconds":31536000}"#,
+                hyper::StatusCode::BAD_REQUEST,
+                "ttl_too_long",
+            ),
+        ] {
+            let response = post_v2(&test_server, body);
+            assert_eq!(response.status(), status, "{body}");
+            assert_eq!(
+                response.headers()[hyper::header::CONTENT_TYPE],
+                "application/problem+json"
+            );
+            let problem = read_json(response);
+            assert_eq!(problem["code"], code, "{body}");
+            assert_eq!(problem["status"], status.as_u16());
+        }
+    }
+
+    #[test]
+    fn v1_errors_stay_empty() {
+        let test_server = TestServer::new(route(options())).unwrap();
+        let response = test_server
+            .client()
+            .get(host_path!("0___________________foo___________________0"))
+            .perform()
+            .unwrap();
+
+        assert_eq!(response.status(), hyper::StatusCode::NOT_FOUND);
+        assert!(response.read_body().unwrap().is_empty());
+    }
+
+    #[test]
+    fn v2_with_front_end() {
+        let test_server = TestServer::new(route(options_with_path())).unwrap();
+        let response = test_server
+            .client()
+            .post(
+                host_path!("api/v2/secrets"),
+                r#"{"data":"Zm9v","ttl_seconds":60}"#,
+                mime::APPLICATION_JSON,
+            )
+            .perform()
+            .unwrap();
+
+        assert_eq!(response.status(), hyper::StatusCode::CREATED);
+    }
+
+    #[test]
+    fn openapi_matches_routes() {
+        let mut options = options();
+        options.metrics = true;
+
+        let test_server = TestServer::new(route(options)).unwrap();
+        let client = test_server.client();
+
+        let response = client.get(host_path!("openapi.json")).perform().unwrap();
+        assert_eq!(response.status(), hyper::StatusCode::OK);
+        let spec = read_json(response);
+        assert_eq!(spec["servers"][0]["url"], "/");
+        assert_eq!(
+            spec["paths"]["/v2/secrets"]["post"]["responses"]["413"]["description"],
+            "payload_too_large, secret_too_large"
+        );
+
+        // Every documented operation reaches the handler of the same name and only answers with
+        // documented statuses
+        let mut operations = std::collections::BTreeSet::new();
+        for (path, methods) in spec["paths"].as_object().unwrap() {
+            let path = path.replace("{id}", "0___________________foo___________________0");
+            for (method, operation) in methods.as_object().unwrap() {
+                operations.insert(operation["operationId"].as_str().unwrap().to_owned());
+
+                let query = operation["parameters"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter(|parameter| parameter["in"] == "query")
+                    .map(|parameter| {
+                        let value = if parameter["schema"]["type"] == "integer" {
+                            "1"
+                        } else {
+                            "1m"
+                        };
+                        format!("{}={value}", parameter["name"].as_str().unwrap())
+                    })
+                    .collect::<Vec<_>>();
+                let uri = if query.is_empty() {
+                    format!("http://localhost{path}")
+                } else {
+                    format!("http://localhost{path}?{}", query.join("&"))
+                };
+
+                let content = &operation["requestBody"]["content"];
+                let response = match method.as_str() {
+                    "get" => client.get(uri).perform().unwrap(),
+                    "post" if content.get("application/json").is_some() => client
+                        .post(
+                            uri,
+                            r#"{"data":"Zm9v","ttl_seconds":60}"#,
+                            mime::APPLICATION_JSON,
+                        )
+                        .perform()
+                        .unwrap(),
+                    "post" => client
+                        .post(uri, "foo", mime::APPLICATION_OCTET_STREAM)
+                        .perform()
+                        .unwrap(),
+                    method => panic!("untested method {method}"),
+                };
+
+                let status = response.status().as_u16().to_string();
+                assert!(
+                    operation["responses"].get(&status).is_some(),
+                    "{method} {path} answered with undocumented {status}"
+                );
+            }
+        }
+
+        let response = client.get(host_path!("metrics")).perform().unwrap();
+        let metrics = String::from_utf8(response.read_body().unwrap()).unwrap();
+        for operation in &operations {
+            assert!(
+                metrics.contains(&format!("route=\"{operation}\"")),
+                "{operation} is documented but not routed"
+            );
+        }
+
+        // Every API route is documented
+        let source = include_str!("mod.rs");
+        let start = source.find("fn add_routes").unwrap();
+        let end = source.find("// Answers the preflight").unwrap();
+        let routed = source[start..end]
+            .split("handler::named(\"")
+            .skip(1)
+            .map(|name| name.split('"').next().unwrap().to_owned())
+            .chain(std::iter::once(String::from("openapi")))
+            .collect::<std::collections::BTreeSet<_>>();
+        assert_eq!(routed, operations);
+    }
+
+    #[test]
+    fn openapi_with_front_end() {
+        let test_server = TestServer::new(route(options_with_path())).unwrap();
+        let response = test_server
+            .client()
+            .get(host_path!("api/openapi.json"))
+            .perform()
+            .unwrap();
+
+        assert_eq!(response.status(), hyper::StatusCode::OK);
+        assert_eq!(read_json(response)["servers"][0]["url"], "/api/");
+    }
+
+    // Announces `size` bytes but only uploads `sent`, in chunks of `chunk` bytes every `interval`
+    fn trickle(
+        test_server: &TestServer,
+        size: usize,
+        sent: usize,
+        chunk: usize,
+        interval: std::time::Duration,
+    ) -> gotham::test::TestResponse {
+        let (mut sender, body) = hyper::Body::channel();
+        let upload = std::thread::spawn(move || {
+            tokio::runtime::Runtime::new().unwrap().block_on(async {
+                for _ in 0..sent / chunk {
+                    tokio::time::sleep(interval).await;
+                    if sender.send_data(vec![0; chunk].into()).await.is_err() {
+                        return;
+                    }
+                }
+                // Keeps the body open, as a stalled client would
+                tokio::time::sleep(std::time::Duration::from_secs(3)).await;
+            });
+        });
+
+        let response = test_server
+            .client()
+            .post(concat!(host_path!(), "?ttl=1m"), body, mime::TEXT_PLAIN)
+            .with_header(hyper::header::CONTENT_LENGTH, size.into())
+            .perform()
+            .unwrap();
+        drop(upload);
+        response
+    }
+
+    #[test]
+    fn body_timeout() {
+        let mut options = options();
+        options.body_timeout = 1;
+        options.min_upload_rate = 0;
+
+        let test_server = TestServer::new(route(options)).unwrap();
+        let start = std::time::Instant::now();
+        let response = trickle(&test_server, 10, 5, 1, std::time::Duration::from_millis(10));
+
+        assert_eq!(response.status(), hyper::StatusCode::REQUEST_TIMEOUT);
+        assert!(start.elapsed() < std::time::Duration::from_secs(3));
+    }
+
+    #[test]
+    fn too_slow() {
+        let mut options = options();
+        options.body_timeout = 1;
+        options.min_upload_rate = 8192;
+
+        let test_server = TestServer::new(route(options)).unwrap();
+        let start = std::time::Instant::now();
+        // Never pauses for long, but would take over 3s in total
+        let response = trickle(
+            &test_server,
+            8192,
+            8192,
+            512,
+            std::time::Duration::from_millis(200),
+        );
+
+        assert_eq!(response.status(), hyper::StatusCode::REQUEST_TIMEOUT);
+        let elapsed = start.elapsed();
+        assert!(elapsed >= std::time::Duration::from_secs(2), "{elapsed:?}");
+        assert!(elapsed < std::time::Duration::from_secs(3), "{elapsed:?}");
+    }
+
+    #[test]
+    fn slow_upload_within_rate() {
+        let mut options = options();
+        options.body_timeout = 1;
+        options.min_upload_rate = 1024;
+
+        let test_server = TestServer::new(route(options)).unwrap();
+        let response = trickle(
+            &test_server,
+            1024,
+            1024,
+            256,
+            std::time::Duration::from_millis(200),
+        );
+
+        assert_eq!(response.status(), hyper::StatusCode::CREATED);
+    }
+
+    fn options_with_admin_token() -> options::Options {
+        let mut options = options();
+        options.admin_token = Some(String::from("hunter2"));
+        options
+    }
+
+    fn admin(
+        test_server: &TestServer,
+        method: hyper::Method,
+        uri: &str,
+        token: Option<&'static str>,
+    ) -> gotham::test::TestResponse {
+        let client = test_server.client();
+        let mut request = client.build_request(method, uri);
+        if let Some(token) = token {
+            request = request.with_header(
+                hyper::header::AUTHORIZATION,
+                hyper::header::HeaderValue::from_static(token),
+            );
+        }
+        request.perform().unwrap()
+    }
+
+    #[test]
+    fn admin_requires_token() {
+        let test_server = TestServer::new(route(options())).unwrap();
+        let response = admin(
+            &test_server,
+            hyper::Method::GET,
+            host_path!("admin/stats"),
+            Some("Bearer hunter2"),
+        );
+        assert_eq!(response.status(), hyper::StatusCode::NOT_FOUND);
+
+        let test_server = TestServer::new(route(options_with_admin_token())).unwrap();
+        for token in [None, Some("Bearer hunter3"), Some("hunter2")] {
+            let response = admin(
+                &test_server,
+                hyper::Method::GET,
+                host_path!("admin/stats"),
+                token,
+            );
+            assert_eq!(response.status(), hyper::StatusCode::UNAUTHORIZED);
+            assert_eq!(
+                response.headers()[hyper::header::WWW_AUTHENTICATE],
+                "Bearer"
+            );
+            assert_eq!(read_json(response)["code"], "unauthorized");
+        }
+
+        let response = admin(
+            &test_server,
+            hyper::Method::GET,
+            host_path!("admin/stats"),
+            Some("Bearer hunter2"),
+        );
+        assert_eq!(response.status(), hyper::StatusCode::OK);
+        let stats = read_json(response);
+        assert_eq!(stats["secrets"], 0);
+        assert_eq!(stats["maintenance"], false);
+    }
+
+    #[test]
+    fn admin_revoke() {
+        let test_server = TestServer::new(route(options_with_admin_token())).unwrap();
+        let id = read_json(post_v2(&test_server, r#"{"data":"Zm9v","ttl_seconds":60}"#))["id"]
+            .as_str()
+            .unwrap()
+            .to_owned();
+
+        let uri = format!(concat!(host_path!("admin/secrets/"), "{}"), id);
+        let response = admin(&test_server, hyper::Method::DELETE, &uri, None);
+        assert_eq!(response.status(), hyper::StatusCode::UNAUTHORIZED);
+
+        let response = admin(
+            &test_server,
+            hyper::Method::DELETE,
+            &uri,
+            Some("Bearer hunter2"),
+        );
+        assert_eq!(response.status(), hyper::StatusCode::NO_CONTENT);
+
+        let response = admin(
+            &test_server,
+            hyper::Method::DELETE,
+            &uri,
+            Some("Bearer hunter2"),
+        );
+        assert_eq!(response.status(), hyper::StatusCode::NOT_FOUND);
+
+        let response = test_server
+            .client()
+            .get(format!(concat!(host_path!("v2/secrets/"), "{}"), id))
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), hyper::StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn admin_purge() {
+        let test_server = TestServer::new(route(options_with_admin_token())).unwrap();
+        for _ in 0..2 {
+            let response = post_v2(&test_server, r#"{"data":"Zm9v","ttl_seconds":60}"#);
+            assert_eq!(response.status(), hyper::StatusCode::CREATED);
+        }
+        let response = post_v2(&test_server, r#"{"data":"Zm9v","ttl_seconds":1}"#);
+        assert_eq!(response.status(), hyper::StatusCode::CREATED);
+        std::thread::sleep(std::time::Duration::from_millis(1100));
+
+        let response = admin(
+            &test_server,
+            hyper::Method::POST,
+            host_path!("admin/purge?scope=expired"),
+            Some("Bearer hunter2"),
+        );
+        assert_eq!(response.status(), hyper::StatusCode::OK);
+        assert_eq!(read_json(response)["purged"], 1);
+
+        let response = admin(
+            &test_server,
+            hyper::Method::POST,
+            host_path!("admin/purge?scope=all"),
+            Some("Bearer hunter2"),
+        );
+        assert_eq!(response.status(), hyper::StatusCode::OK);
+        assert_eq!(read_json(response)["purged"], 2);
+
+        let response = admin(
+            &test_server,
+            hyper::Method::POST,
+            host_path!("admin/purge?scope=some"),
+            Some("Bearer hunter2"),
+        );
+        assert_eq!(response.status(), hyper::StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn admin_maintenance() {
+        let test_server = TestServer::new(route(options_with_admin_token())).unwrap();
+        let id = read_json(post_v2(&test_server, r#"{"data":"Zm9v","ttl_seconds":60}"#))["id"]
+            .as_str()
+            .unwrap()
+            .to_owned();
+
+        let response = admin(
+            &test_server,
+            hyper::Method::PUT,
+            host_path!("admin/maintenance"),
+            Some("Bearer hunter2"),
+        );
+        assert_eq!(response.status(), hyper::StatusCode::OK);
+        assert_eq!(read_json(response)["enabled"], true);
+
+        let response = post_v2(&test_server, r#"{"data":"Zm9v","ttl_seconds":60}"#);
+        assert_eq!(response.status(), hyper::StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(read_json(response)["code"], "read_only");
+
+        let response = test_server
+            .client()
+            .post(concat!(host_path!(), "?ttl=1m"), "foo", mime::TEXT_PLAIN)
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), hyper::StatusCode::SERVICE_UNAVAILABLE);
+
+        let response = test_server
+            .client()
+            .get(format!(concat!(host_path!("v2/secrets/"), "{}"), id))
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), hyper::StatusCode::OK);
+
+        let response = admin(
+            &test_server,
+            hyper::Method::GET,
+            host_path!("admin/maintenance"),
+            Some("Bearer hunter2"),
+        );
+        assert_eq!(read_json(response)["enabled"], true);
+
+        let response = admin(
+            &test_server,
+            hyper::Method::DELETE,
+            host_path!("admin/maintenance"),
+            Some("Bearer hunter2"),
+        );
+        assert_eq!(read_json(response)["enabled"], false);
+
+        let response = post_v2(&test_server, r#"{"data":"Zm9v","ttl_seconds":60}"#);
+        assert_eq!(response.status(), hyper::StatusCode::CREATED);
+    }
+
+    #[test]
+    fn admin_api_on_dedicated_listener() {
+        let mut options = options_with_admin_token();
+        options.admin_listen = vec!["127.0.0.1:0".parse().unwrap()];
+
+        let routers = super::route(options, false).unwrap();
+        let public = TestServer::new(routers.public).unwrap();
+        let admin_server = TestServer::new(routers.admin.unwrap()).unwrap();
+
+        let response = admin(
+            &public,
+            hyper::Method::GET,
+            host_path!("admin/stats"),
+            Some("Bearer hunter2"),
+        );
+        assert_eq!(response.status(), hyper::StatusCode::NOT_FOUND);
+
+        let response = admin(
+            &admin_server,
+            hyper::Method::GET,
+            host_path!("admin/stats"),
+            None,
+        );
+        assert_eq!(response.status(), hyper::StatusCode::UNAUTHORIZED);
+
+        let response = admin(
+            &admin_server,
+            hyper::Method::GET,
+            host_path!("admin/stats"),
+            Some("Bearer hunter2"),
+        );
+        assert_eq!(response.status(), hyper::StatusCode::OK);
+    }
+
+    // The SHA-256 of "secret"
+    const API_KEY: &str = "ci:2bb80d537b1da3e38bd30361aa855686bde0eacd7162fef6a25fe97bf527a25b";
+
+    fn options_with_api_key() -> options::Options {
+        let mut options = options();
+        options.api_key = vec![API_KEY.parse().unwrap()];
+        options
+    }
+
+    #[test]
+    fn api_key_options() {
+        let key = API_KEY.parse::<super::ApiKey>().unwrap();
+        assert_eq!(key.label(), "ci");
+
+        assert!("ci".parse::<super::ApiKey>().is_err());
+        assert!(
+            ":2bb80d537b1da3e38bd30361aa855686bde0eacd7162fef6a25fe97bf527a25b"
+                .parse::<super::ApiKey>()
+                .is_err()
+        );
+        assert!("ci:2bb80d537b1da3e3".parse::<super::ApiKey>().is_err());
+        assert!(
+            "ci:zzb80d537b1da3e38bd30361aa855686bde0eacd7162fef6a25fe97bf527a25b"
+                .parse::<super::ApiKey>()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn upload_requires_api_key() {
+        let test_server = TestServer::new(route(options_with_api_key())).unwrap();
+
+        for token in [None, Some("Bearer nope"), Some("secret")] {
+            let client = test_server.client();
+            let mut request =
+                client.post(concat!(host_path!(), "?ttl=1m"), "foo", mime::TEXT_PLAIN);
+            if let Some(token) = token {
+                request = request.with_header(
+                    hyper::header::AUTHORIZATION,
+                    hyper::header::HeaderValue::from_static(token),
+                );
+            }
+            let response = request.perform().unwrap();
+            assert_eq!(response.status(), hyper::StatusCode::UNAUTHORIZED);
+            assert_eq!(
+                response.headers()[hyper::header::WWW_AUTHENTICATE],
+                "Bearer"
+            );
+            assert!(response.read_body().unwrap().is_empty());
+        }
+
+        let response = test_server
+            .client()
+            .post(concat!(host_path!(), "?ttl=1m"), "foo", mime::TEXT_PLAIN)
+            .with_header(
+                hyper::header::AUTHORIZATION,
+                hyper::header::HeaderValue::from_static("Bearer secret"),
+            )
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), hyper::StatusCode::CREATED);
+        let id = String::from_utf8(response.read_body().unwrap()).unwrap();
+
+        // Reading stays public
+        let response = test_server
+            .client()
+            .get(format!(concat!(host_path!(), "{}"), id))
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), hyper::StatusCode::OK);
+    }
+
+    #[test]
+    fn upload_v2_requires_api_key() {
+        let test_server = TestServer::new(route(options_with_api_key())).unwrap();
+
+        let response = post_v2(&test_server, r#"{"data":"Zm9v","ttl_seconds":60}"#);
+        assert_eq!(response.status(), hyper::StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            response.headers()[hyper::header::CONTENT_TYPE],
+            "application/problem+json"
+        );
+        assert_eq!(read_json(response)["code"], "unauthorized");
+
+        let response = test_server
+            .client()
+            .post(
+                host_path!("v2/secrets"),
+                r#"{"data":"Zm9v","ttl_seconds":60}"#,
+                mime::APPLICATION_JSON,
+            )
+            .with_header(
+                hyper::header::AUTHORIZATION,
+                hyper::header::HeaderValue::from_static("Bearer secret"),
+            )
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), hyper::StatusCode::CREATED);
+
+        let response = test_server
+            .client()
+            .get(host_path!("config.json"))
+            .perform()
+            .unwrap();
+        assert_eq!(
+            read_json(response)["features"],
+            serde_json::json!(["api_key"])
+        );
+
+        let response = test_server
+            .client()
+            .get(host_path!("openapi.json"))
+            .perform()
+            .unwrap();
+        let spec = read_json(response);
+        assert_eq!(
+            spec["paths"]["/v2/secrets"]["post"]["security"],
+            serde_json::json!([{ "apiKey": [] }])
+        );
+        assert!(spec["paths"]["/v2/secrets"]["post"]["responses"]["401"].is_object());
+        assert!(spec["paths"]["/{id}"]["get"]["security"].is_null());
+    }
+
+    fn post_v2_with_key(
+        test_server: &TestServer,
+        body: &str,
+        key: &'static str,
+    ) -> gotham::test::TestResponse {
+        test_server
+            .client()
+            .post(
+                host_path!("v2/secrets"),
+                body.to_owned(),
+                mime::APPLICATION_JSON,
+            )
+            .with_header(
+                hyper::header::AUTHORIZATION,
+                hyper::header::HeaderValue::from_static(key),
+            )
+            .perform()
+            .unwrap()
+    }
+
+    #[test]
+    fn quota_secrets() {
+        let mut options = options();
+        options.quota_secrets = Some(2);
+        let test_server = TestServer::new(route(options)).unwrap();
+
+        let id = read_json(post_v2(&test_server, r#"{"data":"Zm9v","ttl_seconds":60}"#))["id"]
+            .as_str()
+            .unwrap()
+            .to_owned();
+        let response = post_v2(&test_server, r#"{"data":"Zm9v","ttl_seconds":60}"#);
+        assert_eq!(response.status(), hyper::StatusCode::CREATED);
+
+        let response = post_v2(&test_server, r#"{"data":"Zm9v","ttl_seconds":60}"#);
+        assert_eq!(response.status(), hyper::StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(read_json(response)["code"], "secret_quota");
+
+        // Reading a secret gives its room back
+        let response = test_server
+            .client()
+            .get(format!(concat!(host_path!("v2/secrets/"), "{}"), id))
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), hyper::StatusCode::OK);
+
+        let response = post_v2(&test_server, r#"{"data":"Zm9v","ttl_seconds":60}"#);
+        assert_eq!(response.status(), hyper::StatusCode::CREATED);
+    }
+
+    #[test]
+    fn quota_outlives_restart() {
+        let path = std::env::temp_dir().join("passer_test_quota_outlives_restart");
+        let _ = std::fs::remove_dir_all(&path);
+
+        let options = || {
+            let mut options = options();
+            options.quota_secrets = Some(1);
+            options.store_path = Some(path.clone());
+            options
+        };
+        let body = r#"{"data":"Zm9v","ttl_seconds":60}"#;
+
+        let test_server = TestServer::new(route(options())).unwrap();
+        let response = post_v2(&test_server, body);
+        assert_eq!(response.status(), hyper::StatusCode::CREATED);
+        let id = read_json(response)["id"].as_str().unwrap().to_owned();
+        drop(test_server);
+
+        // The secret kept by the store still counts once restarted, until it is read
+        let test_server = TestServer::new(route(options())).unwrap();
+        let response = post_v2(&test_server, body);
+        assert_eq!(response.status(), hyper::StatusCode::TOO_MANY_REQUESTS);
+
+        let response = test_server
+            .client()
+            .get(format!(concat!(host_path!("v2/secrets/"), "{}"), id))
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), hyper::StatusCode::OK);
+        let response = post_v2(&test_server, body);
+        assert_eq!(response.status(), hyper::StatusCode::CREATED);
+
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn quota_bytes() {
+        let mut options = options();
+        options.quota_bytes = Some(5);
+        let test_server = TestServer::new(route(options)).unwrap();
+
+        let response = test_server
+            .client()
+            .post(concat!(host_path!(), "?ttl=1m"), "foo", mime::TEXT_PLAIN)
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), hyper::StatusCode::CREATED);
+
+        let response = test_server
+            .client()
+            .post(concat!(host_path!(), "?ttl=1m"), "bar", mime::TEXT_PLAIN)
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), hyper::StatusCode::TOO_MANY_REQUESTS);
+
+        let response = post_v2(&test_server, r#"{"data":"Zm8=","ttl_seconds":60}"#);
+        assert_eq!(response.status(), hyper::StatusCode::CREATED);
+    }
+
+    #[test]
+    fn quota_ttl() {
+        let mut options = options();
+        options.quota_ttl = Some(std::time::Duration::from_hours(1));
+        let test_server = TestServer::new(route(options)).unwrap();
+
+        let response = post_v2(&test_server, r#"{"data":"Zm9v","ttl_seconds":3601}"#);
+        assert_eq!(response.status(), hyper::StatusCode::FORBIDDEN);
+        let problem = read_json(response);
+        assert_eq!(problem["code"], "ttl_quota");
+        assert_eq!(
+            problem["detail"],
+            "ttl longer than the 60m allowed to this client"
+        );
+
+        let response = post_v2(&test_server, r#"{"data":"Zm9v","ttl_seconds":3600}"#);
+        assert_eq!(response.status(), hyper::StatusCode::CREATED);
+    }
+
+    #[test]
+    fn quota_per_key() {
+        let mut options = options_with_api_key();
+        options.api_key.push(
+            "other:d9298a10d1b0735837dc4bd85dac641b0f3cef27a47e5d53a54f2f3f5b2fcffa"
+                .parse()
+                .unwrap(),
+        );
+        options.quota_secrets = Some(1);
+        let test_server = TestServer::new(route(options)).unwrap();
+
+        let body = r#"{"data":"Zm9v","ttl_seconds":60}"#;
+        let response = post_v2_with_key(&test_server, body, "Bearer secret");
+        assert_eq!(response.status(), hyper::StatusCode::CREATED);
+        let response = post_v2_with_key(&test_server, body, "Bearer secret");
+        assert_eq!(response.status(), hyper::StatusCode::TOO_MANY_REQUESTS);
+
+        let response = post_v2_with_key(&test_server, body, "Bearer other");
+        assert_eq!(response.status(), hyper::StatusCode::CREATED);
+
+        let response = test_server
+            .client()
+            .get(host_path!("config.json"))
+            .perform()
+            .unwrap();
+        assert_eq!(
+            read_json(response)["features"],
+            serde_json::json!(["api_key", "quotas"])
+        );
+    }
+
+    // An OpenID Connect provider serving its discovery document, its keys, and `token` as the ID
+    // token of every sign-in
+    struct Issuer {
+        port: u16,
+        token: std::sync::Arc<std::sync::Mutex<String>>,
+    }
+
+    impl Issuer {
+        fn new() -> Self {
+            use std::io::BufRead;
+            use std::io::Read;
+            use std::io::Write;
+
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            let port = listener.local_addr().unwrap().port();
+            let token = std::sync::Arc::new(std::sync::Mutex::new(String::new()));
+            let id_token = token.clone();
+            std::thread::spawn(move || {
+                for stream in listener.incoming() {
+                    let stream = stream.unwrap();
+                    let mut reader = std::io::BufReader::new(&stream);
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    let path = line.split(' ').nth(1).unwrap_or_default().to_owned();
+
+                    let mut length = 0;
+                    loop {
+                        line.clear();
+                        reader.read_line(&mut line).unwrap();
+                        let Some((name, value)) = line.split_once(':') else {
+                            break;
+                        };
+                        if name.eq_ignore_ascii_case("content-length") {
+                            length = value.trim().parse().unwrap();
+                        }
+                    }
+                    let mut body = vec![0; length];
+                    reader.read_exact(&mut body).unwrap();
+                    let body = String::from_utf8(body).unwrap();
+
+                    let issuer = format!("http://127.0.0.1:{port}");
+                    let (status, response) = match path.as_str() {
+                        "/.well-known/openid-configuration" => (
+                            200,
+                            serde_json::json!({
+                                "issuer": issuer,
+                                "authorization_endpoint": format!("{issuer}/authorize"),
+                                "token_endpoint": format!("{issuer}/token"),
+                                "jwks_uri": format!("{issuer}/jwks"),
+                            })
+                            .to_string(),
+                        ),
+                        "/jwks" => (
+                            200,
+                            include_str!("../../res/test/oidc.jwks.json").to_owned(),
+                        ),
+                        "/token" if body.contains("code=good") => (
+                            200,
+                            serde_json::json!({ "id_token": *id_token.lock().unwrap() })
+                                .to_string(),
+                        ),
+                        "/token" => (400, String::from(r#"{"error":"invalid_grant"}"#)),
+                        _ => (404, String::new()),
+                    };
+                    write!(
+                        &stream,
+                        "HTTP/1.1 {status} Status\r\ncontent-type: application/json\r\n\
+                         content-length: {}\r\nconnection: close\r\n\r\n{response}",
+                        response.len()
+                    )
+                    .unwrap();
+                }
+            });
+
+            Self { port, token }
+        }
+
+        fn url(&self) -> String {
+            format!("http://127.0.0.1:{}", self.port)
+        }
+
+        // A token of the user `alice` of the group `staff`, meant for `passer`, with `claims` on
+        // top
+        fn sign(&self, claims: &serde_json::Value) -> String {
+            let exp = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_secs()
+                + 300;
+            let mut all = serde_json::json!({
+                "iss": self.url(),
+                "aud": "passer",
+                "sub": "alice",
+                "exp": exp,
+                "groups": ["staff"],
+            });
+            for (name, value) in claims.as_object().unwrap() {
+                all[name] = value.clone();
+            }
+
+            let mut header = jsonwebtoken::Header::new(jsonwebtoken::Algorithm::RS256);
+            header.kid = Some(String::from("test"));
+            let key =
+                jsonwebtoken::EncodingKey::from_rsa_pem(include_bytes!("../../res/test/oidc.pem"))
+                    .unwrap();
+            jsonwebtoken::encode(&header, &all, &key).unwrap()
+        }
+    }
+
+    fn options_with_oidc(issuer: &Issuer) -> options::Options {
+        let mut options = options();
+        options.oidc_issuer = Some(issuer.url());
+        options.oidc_client_id = Some(String::from("passer"));
+        options.oidc_group = vec![String::from("staff")];
+        options
+    }
+
+    fn post_with_header(
+        test_server: &TestServer,
+        name: hyper::header::HeaderName,
+        value: &str,
+    ) -> gotham::test::TestResponse {
+        test_server
+            .client()
+            .post(concat!(host_path!(), "?ttl=1m"), "foo", mime::TEXT_PLAIN)
+            .with_header(name, hyper::header::HeaderValue::from_str(value).unwrap())
+            .perform()
+            .unwrap()
+    }
+
+    #[test]
+    fn oidc_bearer_tokens() {
+        let issuer = Issuer::new();
+        let test_server = TestServer::new(route(options_with_oidc(&issuer))).unwrap();
+        let upload = |token: &str| {
+            post_with_header(
+                &test_server,
+                hyper::header::AUTHORIZATION,
+                &format!("Bearer {token}"),
+            )
+            .status()
+        };
+
+        let token = issuer.sign(&serde_json::json!({}));
+        assert_eq!(upload(&token), hyper::StatusCode::CREATED);
+
+        let cases = [
+            (
+                serde_json::json!({ "aud": "other" }),
+                hyper::StatusCode::UNAUTHORIZED,
+            ),
+            (
+                serde_json::json!({ "iss": "https://other.example" }),
+                hyper::StatusCode::UNAUTHORIZED,
+            ),
+            (
+                serde_json::json!({ "exp": 1 }),
+                hyper::StatusCode::UNAUTHORIZED,
+            ),
+            (
+                serde_json::json!({ "groups": ["guests"] }),
+                hyper::StatusCode::FORBIDDEN,
+            ),
+            (
+                serde_json::json!({ "groups": "staff" }),
+                hyper::StatusCode::CREATED,
+            ),
+        ];
+        for (claims, status) in cases {
+            assert_eq!(upload(&issuer.sign(&claims)), status, "{claims}");
+        }
+
+        // Signed with another key, or altered after signing
+        let (signed, _) = token.rsplit_once('.').unwrap();
+        let forged = jsonwebtoken::encode(
+            &jsonwebtoken::Header::new(jsonwebtoken::Algorithm::HS256),
+            &serde_json::json!({ "iss": issuer.url(), "aud": "passer", "sub": "mallory" }),
+            &jsonwebtoken::EncodingKey::from_secret(b"secret"),
+        )
+        .unwrap();
+        assert_eq!(upload(&forged), hyper::StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            upload(&format!("{signed}.AAAA")),
+            hyper::StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(upload("nope"), hyper::StatusCode::UNAUTHORIZED);
+
+        let response = test_server
+            .client()
+            .get(host_path!("config.json"))
+            .perform()
+            .unwrap();
+        let config = read_json(response);
+        assert_eq!(config["features"], serde_json::json!(["api_key", "oidc"]));
+        assert!(config.get("login_url").is_none());
+
+        let response = test_server
+            .client()
+            .get(host_path!("openapi.json"))
+            .perform()
+            .unwrap();
+        let responses = &read_json(response)["paths"]["/v2/secrets"]["post"]["responses"];
+        assert_eq!(
+            responses["401"]["description"],
+            "unauthorized, sign_in_failed"
+        );
+        assert_eq!(responses["403"]["description"], "not_in_group");
+        assert_eq!(responses["502"]["description"], "provider_unavailable");
+
+        // Only the front-end signs in through the browser
+        let response = test_server
+            .client()
+            .get(host_path!("auth/login"))
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), hyper::StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn oidc_unavailable() {
+        let mut options = options();
+        options.oidc_issuer = Some(String::from("http://127.0.0.1:1"));
+        options.oidc_client_id = Some(String::from("passer"));
+        let test_server = TestServer::new(route(options)).unwrap();
+
+        let response = post_with_header(
+            &test_server,
+            hyper::header::AUTHORIZATION,
+            "Bearer eyJhbGciOiJSUzI1NiJ9.e30.AAAA",
+        );
+        assert_eq!(response.status(), hyper::StatusCode::BAD_GATEWAY);
+    }
+
+    // The names and values of the cookies set by `response`
+    fn set_cookies(response: &gotham::test::TestResponse) -> Vec<String> {
+        response
+            .headers()
+            .get_all(hyper::header::SET_COOKIE)
+            .iter()
+            .filter_map(|cookie| cookie.to_str().unwrap().split(';').next())
+            .map(ToOwned::to_owned)
+            .collect()
+    }
+
+    // Starts signing in from `path`, returning the cookie of the sign-in and the query sent to
+    // the provider
+    fn start_sign_in(
+        test_server: &TestServer,
+        path: &str,
+    ) -> (String, std::collections::HashMap<String, String>) {
+        let response = test_server
+            .client()
+            .get(format!("{}{path}", host_path!()))
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), hyper::StatusCode::FOUND);
+
+        let location = response.headers()[hyper::header::LOCATION]
+            .to_str()
+            .unwrap();
+        let (_, query) = location.split_once('?').unwrap();
+        let query = query
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .map(|(name, value)| {
+                let value = percent_encoding::percent_decode_str(value).decode_utf8_lossy();
+                (name.to_owned(), value.into_owned())
+            })
+            .collect();
+        (set_cookies(&response).remove(0), query)
+    }
+
+    fn finish_sign_in(
+        test_server: &TestServer,
+        code: &str,
+        state: &str,
+        cookie: &str,
+    ) -> gotham::test::TestResponse {
+        test_server
+            .client()
+            .get(format!(
+                "{}auth/callback?code={code}&state={state}",
+                host_path!()
+            ))
+            .with_header(
+                hyper::header::COOKIE,
+                hyper::header::HeaderValue::from_str(cookie).unwrap(),
+            )
+            .perform()
+            .unwrap()
+    }
+
+    #[test]
+    fn oidc_sign_in() {
+        let issuer = Issuer::new();
+        let mut options = options_with_oidc(&issuer);
+        options.oidc_redirect_url = Some(String::from("http://localhost/auth/callback"));
+        let test_server = TestServer::new(route(options)).unwrap();
+
+        let response = test_server
+            .client()
+            .get(host_path!("config.json"))
+            .perform()
+            .unwrap();
+        let config = read_json(response);
+        assert_eq!(
+            config["features"],
+            serde_json::json!(["api_key", "oidc", "sign_in"])
+        );
+        assert_eq!(config["login_url"], "/auth/login");
+
+        let (login, query) = start_sign_in(&test_server, "auth/login?return=/d/");
+        assert!(login.starts_with("passer_login="));
+        assert_eq!(query["client_id"], "passer");
+        assert_eq!(query["redirect_uri"], "http://localhost/auth/callback");
+        assert_eq!(query["code_challenge_method"], "S256");
+        *issuer.token.lock().unwrap() =
+            issuer.sign(&serde_json::json!({ "nonce": query["nonce"] }));
+        let state = &query["state"];
+
+        // The state must match the sign-in in progress, and the provider must accept the code
+        let response = finish_sign_in(&test_server, "good", "other", &login);
+        assert_eq!(response.status(), hyper::StatusCode::UNAUTHORIZED);
+        let response = finish_sign_in(&test_server, "good", state, "passer_login=forged.AAAA");
+        assert_eq!(response.status(), hyper::StatusCode::UNAUTHORIZED);
+        let response = finish_sign_in(&test_server, "bad", state, &login);
+        assert_eq!(response.status(), hyper::StatusCode::UNAUTHORIZED);
+
+        let response = finish_sign_in(&test_server, "good", state, &login);
+        assert_eq!(response.status(), hyper::StatusCode::FOUND);
+        assert_eq!(response.headers()[hyper::header::LOCATION], "/d/");
+        let cookies = set_cookies(&response);
+        assert_eq!(cookies[1], "passer_login=");
+        let session = &cookies[0];
+        assert!(session.starts_with("passer_session="));
+
+        let response = post_with_header(&test_server, hyper::header::COOKIE, session);
+        assert_eq!(response.status(), hyper::StatusCode::CREATED);
+
+        let (payload, _) = session.rsplit_once('.').unwrap();
+        let forged = format!("{payload}.AAAA");
+        let response = post_with_header(&test_server, hyper::header::COOKIE, &forged);
+        assert_eq!(response.status(), hyper::StatusCode::UNAUTHORIZED);
+    }
+
+    #[test]
+    fn oidc_sign_in_checks_nonce() {
+        let issuer = Issuer::new();
+        let mut options = options_with_oidc(&issuer);
+        options.oidc_redirect_url = Some(String::from("http://localhost/auth/callback"));
+        let test_server = TestServer::new(route(options)).unwrap();
+
+        // The token was issued for another sign-in
+        let (login, query) = start_sign_in(&test_server, "auth/login");
+        *issuer.token.lock().unwrap() = issuer.sign(&serde_json::json!({ "nonce": "other" }));
+        let response = finish_sign_in(&test_server, "good", &query["state"], &login);
+        assert_eq!(response.status(), hyper::StatusCode::UNAUTHORIZED);
+    }
+
+    #[test]
+    fn rate_limit_create() {
+        let mut options = options();
+        options.create_rate = Some("2/m".parse().unwrap());
+        let test_server = TestServer::new(route(options)).unwrap();
+
+        let response = test_server
+            .client()
+            .post(concat!(host_path!(), "?ttl=1m"), "foo", mime::TEXT_PLAIN)
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), hyper::StatusCode::CREATED);
+        let response = post_v2(&test_server, r#"{"data":"Zm9v","ttl_seconds":60}"#);
+        assert_eq!(response.status(), hyper::StatusCode::CREATED);
+
+        // Both versions of the API share the limit
+        let response = test_server
+            .client()
+            .post(concat!(host_path!(), "?ttl=1m"), "foo", mime::TEXT_PLAIN)
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), hyper::StatusCode::TOO_MANY_REQUESTS);
+        let retry = response.headers()[hyper::header::RETRY_AFTER]
+            .to_str()
+            .unwrap()
+            .parse::<u64>()
+            .unwrap();
+        assert!((1..=30).contains(&retry));
+
+        let response = post_v2(&test_server, r#"{"data":"Zm9v","ttl_seconds":60}"#);
+        assert_eq!(response.status(), hyper::StatusCode::TOO_MANY_REQUESTS);
+        assert!(response.headers().contains_key(hyper::header::RETRY_AFTER));
+        assert_eq!(read_json(response)["code"], "rate_limited");
+
+        // Lookups are not limited by the create rate
+        let response = test_server
+            .client()
+            .get(host_path!(
+                "v2/secrets/AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA"
+            ))
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), hyper::StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn rate_limit_ipv6_network() {
+        let mut options = options();
+        options.create_rate = Some("1/m".parse().unwrap());
+        options.trusted_proxy = vec!["127.0.0.1".parse().unwrap()];
+        let test_server = TestServer::new(route(options)).unwrap();
+        let upload = |client: &'static str| {
+            test_server
+                .client()
+                .post(concat!(host_path!(), "?ttl=1m"), "foo", mime::TEXT_PLAIN)
+                .with_header(
+                    "x-forwarded-for",
+                    hyper::header::HeaderValue::from_static(client),
+                )
+                .perform()
+                .unwrap()
+                .status()
+        };
+
+        // Addresses of the same /64 share their bucket
+        assert_eq!(upload("2001:db8::1"), hyper::StatusCode::CREATED);
+        assert_eq!(
+            upload("2001:db8::2:3"),
+            hyper::StatusCode::TOO_MANY_REQUESTS
+        );
+        assert_eq!(upload("2001:db8:0:1::1"), hyper::StatusCode::CREATED);
+    }
+
+    #[test]
+    fn rate_limit_retrieve() {
+        let mut options = options();
+        options.retrieve_rate = Some("1/h".parse().unwrap());
+        let test_server = TestServer::new(route(options)).unwrap();
+
+        let missing = host_path!("AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA");
+        let response = test_server.client().get(missing).perform().unwrap();
+        assert_eq!(response.status(), hyper::StatusCode::NOT_FOUND);
+        let response = test_server.client().get(missing).perform().unwrap();
+        assert_eq!(response.status(), hyper::StatusCode::TOO_MANY_REQUESTS);
+        assert!(response.headers().contains_key(hyper::header::RETRY_AFTER));
+
+        let response = post_v2(&test_server, r#"{"data":"Zm9v","ttl_seconds":60}"#);
+        assert_eq!(response.status(), hyper::StatusCode::CREATED);
+
+        let response = test_server
+            .client()
+            .get(host_path!("config.json"))
+            .perform()
+            .unwrap();
+        assert_eq!(
+            read_json(response)["features"],
+            serde_json::json!(["rate_limit"])
+        );
+    }
+
+    #[test]
+    fn ban_after_misses() {
+        let mut options = options();
+        options.ban_after = Some(2);
+        let test_server = TestServer::new(route(options)).unwrap();
+
+        let id = read_json(post_v2(&test_server, r#"{"data":"Zm9v","ttl_seconds":60}"#))["id"]
+            .as_str()
+            .unwrap()
+            .to_owned();
+
+        let missing = host_path!("v2/secrets/AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA");
+        for _ in 0..2 {
+            let response = test_server.client().get(missing).perform().unwrap();
+            assert_eq!(response.status(), hyper::StatusCode::NOT_FOUND);
+        }
+
+        // Even existing secrets are out of reach during the ban
+        let response = test_server
+            .client()
+            .get(format!(concat!(host_path!("v2/secrets/"), "{}"), id))
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), hyper::StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(
+            response.headers()[hyper::header::RETRY_AFTER],
+            (15 * 60).to_string()
+        );
+        assert_eq!(read_json(response)["code"], "banned");
+
+        // Uploads are not affected
+        let response = post_v2(&test_server, r#"{"data":"Zm9v","ttl_seconds":60}"#);
+        assert_eq!(response.status(), hyper::StatusCode::CREATED);
+    }
+
+    // Fetches a challenge for `body` and solves it
+    fn prove_work(test_server: &TestServer, body: &str) -> String {
+        use sha2::Digest;
+
+        let response = test_server
+            .client()
+            .get(format!(
+                concat!(host_path!("v2/challenge"), "?size={}"),
+                body.len()
+            ))
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), hyper::StatusCode::OK);
+        let puzzle = read_json(response);
+        let challenge = puzzle["challenge"].as_str().unwrap();
+        let difficulty = puzzle["difficulty"].as_u64().unwrap();
+
+        (0..u64::MAX)
+            .map(|nonce| format!("{challenge}:{nonce}"))
+            .find(|proof| {
+                let hash = sha2::Sha256::digest(proof);
+                let zeros = u128::from_be_bytes(hash[..16].try_into().unwrap()).leading_zeros();
+                u64::from(zeros) >= difficulty
+            })
+            .unwrap()
+    }
+
+    fn post_v2_with_proof(
+        test_server: &TestServer,
+        body: &str,
+        proof: &str,
+    ) -> gotham::test::TestResponse {
+        test_server
+            .client()
+            .post(
+                host_path!("v2/secrets"),
+                body.to_owned(),
+                mime::APPLICATION_JSON,
+            )
+            .with_header(
+                super::handler::PROOF_OF_WORK,
+                hyper::header::HeaderValue::from_str(proof).unwrap(),
+            )
+            .perform()
+            .unwrap()
+    }
+
+    #[test]
+    fn proof_of_work() {
+        let mut options = options();
+        options.proof_of_work = Some(4);
+        let test_server = TestServer::new(route(options)).unwrap();
+
+        let body = r#"{"data":"Zm9v","ttl_seconds":60}"#;
+        let response = post_v2(&test_server, body);
+        assert_eq!(response.status(), hyper::StatusCode::FORBIDDEN);
+        let problem = read_json(response);
+        assert_eq!(problem["code"], "proof_of_work");
+        assert_eq!(problem["detail"], "invalid proof of work: missing");
+
+        let proof = prove_work(&test_server, body);
+        let response = post_v2_with_proof(&test_server, body, &proof);
+        assert_eq!(response.status(), hyper::StatusCode::CREATED);
+
+        // Each proof pays for a single upload
+        let response = post_v2_with_proof(&test_server, body, &proof);
+        assert_eq!(response.status(), hyper::StatusCode::FORBIDDEN);
+        assert_eq!(
+            read_json(response)["detail"],
+            "invalid proof of work: already used"
+        );
+
+        // The challenge covers no more than the size it was asked for
+        let proof = prove_work(&test_server, body);
+        let response = post_v2_with_proof(
+            &test_server,
+            r#"{"data":"Zm9vYmFy","ttl_seconds":60}"#,
+            &proof,
+        );
+        assert_eq!(response.status(), hyper::StatusCode::FORBIDDEN);
+
+        // Raw body uploads are asked too
+        let response = test_server
+            .client()
+            .post(concat!(host_path!(), "?ttl=1m"), "foo", mime::TEXT_PLAIN)
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), hyper::StatusCode::FORBIDDEN);
+
+        let response = test_server
+            .client()
+            .get(host_path!("config.json"))
+            .perform()
+            .unwrap();
+        assert_eq!(
+            read_json(response)["features"],
+            serde_json::json!(["proof_of_work"])
+        );
+    }
+
+    #[test]
+    fn proof_of_work_not_asked_with_api_key() {
+        let mut options = options_with_api_key();
+        options.proof_of_work = Some(4);
+        let test_server = TestServer::new(route(options)).unwrap();
+
+        let body = r#"{"data":"Zm9v","ttl_seconds":60}"#;
+        let response = post_v2_with_key(&test_server, body, "Bearer secret");
+        assert_eq!(response.status(), hyper::StatusCode::CREATED);
+    }
+
+    #[test]
+    fn challenge_without_proof_of_work() {
+        let test_server = TestServer::new(route(options())).unwrap();
+
+        let response = test_server
+            .client()
+            .get(host_path!("v2/challenge?size=3"))
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), hyper::StatusCode::OK);
+        assert_eq!(read_json(response)["difficulty"], 0);
+
+        let response = test_server
+            .client()
+            .get(host_path!("v2/challenge"))
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), hyper::StatusCode::BAD_REQUEST);
+    }
+
+    // A local HTTP server answering webhooks with `statuses` in turn, then with `200`
+    struct Receiver {
+        port: u16,
+        requests: std::sync::mpsc::Receiver<(std::collections::HashMap<String, String>, String)>,
+    }
+
+    impl Receiver {
+        fn new(statuses: &'static [u16]) -> Self {
+            use std::io::BufRead;
+            use std::io::Read;
+            use std::io::Write;
+
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            let port = listener.local_addr().unwrap().port();
+            let (sender, requests) = std::sync::mpsc::channel();
+            std::thread::spawn(move || {
+                let statuses = statuses.iter().copied().chain(std::iter::repeat(200));
+                for (stream, status) in listener.incoming().zip(statuses) {
+                    let stream = stream.unwrap();
+                    let mut reader = std::io::BufReader::new(&stream);
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+
+                    let mut headers = std::collections::HashMap::new();
+                    loop {
+                        line.clear();
+                        reader.read_line(&mut line).unwrap();
+                        let Some((name, value)) = line.split_once(':') else {
+                            break;
+                        };
+                        headers.insert(name.to_ascii_lowercase(), value.trim().to_owned());
+                    }
+                    let mut body = vec![0; headers["content-length"].parse().unwrap()];
+                    reader.read_exact(&mut body).unwrap();
+
+                    write!(
+                        &stream,
+                        "HTTP/1.1 {status} Status\r\ncontent-length: 0\r\nconnection: close\r\n\r\n"
+                    )
+                    .unwrap();
+                    if sender
+                        .send((headers, String::from_utf8(body).unwrap()))
+                        .is_err()
+                    {
+                        return;
+                    }
+                }
+            });
+
+            Self { port, requests }
+        }
+
+        fn url(&self) -> String {
+            format!("http://127.0.0.1:{}/hook", self.port)
+        }
+
+        // The next webhook, whose signature is checked
+        fn next(&self) -> (std::collections::HashMap<String, String>, serde_json::Value) {
+            let (headers, body) = self
+                .requests
+                .recv_timeout(std::time::Duration::from_secs(10))
+                .unwrap();
+            assert_eq!(
+                headers[super::webhook::SIGNATURE],
+                super::webhook::signature(b"hunter2", body.as_bytes())
+            );
+            (headers, serde_json::from_str(&body).unwrap())
+        }
+    }
+
+    fn options_with_webhooks() -> options::Options {
+        let mut options = options();
+        options.webhook_hosts = vec!["http://127.0.0.1".parse().unwrap()];
+        options.webhook_secret = Some(String::from("hunter2"));
+        options
+    }
+
+    #[test]
+    fn webhook_on_retrieval() {
+        let receiver = Receiver::new(&[500]);
+        let test_server = TestServer::new(route(options_with_webhooks())).unwrap();
+
+        let body = format!(
+            r#"{{"data":"Zm9v","ttl_seconds":60,"callback_url":"{}"}}"#,
+            receiver.url()
+        );
+        let response = post_v2(&test_server, &body);
+        assert_eq!(response.status(), hyper::StatusCode::CREATED);
+        let id = read_json(response)["id"].as_str().unwrap().to_owned();
+
+        let response = test_server
+            .client()
+            .get(format!(concat!(host_path!("v2/secrets/"), "{}"), id))
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), hyper::StatusCode::OK);
+
+        let (headers, event) = receiver.next();
+        assert_eq!(event["event"], "retrieved");
+        assert_eq!(event["secret"], id);
+        assert_eq!(headers["content-type"], "application/json");
+
+        // The failed delivery is retried with the same identifier
+        let (retried, again) = receiver.next();
+        assert_eq!(
+            retried[super::webhook::DELIVERY],
+            headers[super::webhook::DELIVERY]
+        );
+        assert_eq!(again, event);
+    }
+
+    #[test]
+    fn webhook_on_expiry() {
+        let receiver = Receiver::new(&[]);
+        let test_server = TestServer::new(route(options_with_webhooks())).unwrap();
+
+        let body = format!(
+            r#"{{"data":"Zm9v","ttl_seconds":1,"callback_url":"{}"}}"#,
+            receiver.url()
+        );
+        let created = read_json(post_v2(&test_server, &body));
+
+        let (_, event) = receiver.next();
+        assert_eq!(event["event"], "expired");
+        assert_eq!(event["secret"], created["id"]);
+        assert_eq!(event["time"], created["expires_at"]);
+    }
+
+    #[test]
+    fn webhook_slow_receiver() {
+        // Takes connections but never answers them
+        let silent = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let receiver = Receiver::new(&[]);
+        let test_server = TestServer::new(route(options_with_webhooks())).unwrap();
+
+        let urls = [
+            format!("http://{}/hook", silent.local_addr().unwrap()),
+            receiver.url(),
+        ];
+        let mut id = String::new();
+        for url in urls {
+            let body = format!(r#"{{"data":"Zm9v","ttl_seconds":60,"callback_url":"{url}"}}"#);
+            id = read_json(post_v2(&test_server, &body))["id"]
+                .as_str()
+                .unwrap()
+                .to_owned();
+            let response = test_server
+                .client()
+                .get(format!(concat!(host_path!("v2/secrets/"), "{}"), id))
+                .perform()
+                .unwrap();
+            assert_eq!(response.status(), hyper::StatusCode::OK);
+        }
+
+        // Delivered well before the first one times out
+        let started = std::time::Instant::now();
+        let (_, event) = receiver.next();
+        assert_eq!(event["secret"], id);
+        assert!(started.elapsed() < std::time::Duration::from_secs(5));
+    }
+
+    #[test]
+    fn webhook_over_tls() {
+        use std::io::Read;
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let test_server = TestServer::new(route(options_with_webhooks())).unwrap();
+
+        let url = format!("https://{}/hook", listener.local_addr().unwrap());
+        let body = format!(r#"{{"data":"Zm9v","ttl_seconds":60,"callback_url":"{url}"}}"#);
+        let id = read_json(post_v2(&test_server, &body))["id"]
+            .as_str()
+            .unwrap()
+            .to_owned();
+        test_server
+            .client()
+            .get(format!(concat!(host_path!("v2/secrets/"), "{}"), id))
+            .perform()
+            .unwrap();
+
+        // The event is only sent once the receiver answers the TLS handshake
+        let (mut stream, _) = listener.accept().unwrap();
+        stream
+            .set_read_timeout(Some(std::time::Duration::from_secs(10)))
+            .unwrap();
+        let mut record = [0; 3];
+        stream.read_exact(&mut record).unwrap();
+        assert_eq!(record[0], 0x16, "not a TLS handshake: {record:02x?}");
+    }
+
+    #[test]
+    fn webhook_after_restart() {
+        let path = std::env::temp_dir().join("passer_test_webhook_after_restart");
+        let _ = std::fs::remove_dir_all(&path);
+        let receiver = Receiver::new(&[]);
+
+        let options = || {
+            let mut options = options_with_webhooks();
+            options.store_path = Some(path.clone());
+            options
+        };
+        let upload = |test_server: &TestServer, ttl: u64| {
+            let body = format!(
+                r#"{{"data":"Zm9v","ttl_seconds":{ttl},"callback_url":"{}"}}"#,
+                receiver.url()
+            );
+            read_json(post_v2(test_server, &body))["id"]
+                .as_str()
+                .unwrap()
+                .to_owned()
+        };
+
+        let test_server = TestServer::new(route(options())).unwrap();
+        let read = upload(&test_server, 60);
+        let expired = upload(&test_server, 2);
+        drop(test_server);
+        std::thread::sleep(std::time::Duration::from_millis(2100));
+
+        // The callbacks kept by the store are followed once restarted, including the one of the
+        // secret that expired meanwhile
+        let test_server = TestServer::new(route(options())).unwrap();
+        let response = test_server
+            .client()
+            .get(format!(concat!(host_path!("v2/secrets/"), "{}"), read))
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), hyper::StatusCode::OK);
+
+        let mut events = [receiver.next().1, receiver.next().1];
+        events.sort_by_key(|event| event["secret"] != read.as_str());
+        assert_eq!(events[0]["event"], "retrieved");
+        assert_eq!(events[1]["event"], "expired");
+        assert_eq!(events[1]["secret"], expired);
+
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn webhook_callback_refused() {
+        let test_server = TestServer::new(route(options_with_webhooks())).unwrap();
+
+        for url in ["http://localhost/hook", "ftp://127.0.0.1/hook", "hook"] {
+            let body = format!(r#"{{"data":"Zm9v","ttl_seconds":60,"callback_url":"{url}"}}"#);
+            let response = post_v2(&test_server, &body);
+            assert_eq!(response.status(), hyper::StatusCode::BAD_REQUEST);
+            assert_eq!(read_json(response)["code"], "invalid_callback");
+        }
+
+        let response = test_server
+            .client()
+            .post(
+                concat!(
+                    host_path!(),
+                    "?ttl=1m&callback=http%3A%2F%2F10.0.0.1%2Fhook"
+                ),
+                "foo",
+                mime::TEXT_PLAIN,
+            )
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), hyper::StatusCode::BAD_REQUEST);
+
+        let response = test_server
+            .client()
+            .get(host_path!("openapi.json"))
+            .perform()
+            .unwrap();
+        let spec = read_json(response);
+        assert!(spec["paths"]["/v2/secrets"]["post"]["callbacks"].is_object());
+        assert!(
+            spec["components"]["schemas"]["CreateRequest"]["properties"]["callback_url"]
+                .is_object()
+        );
+
+        // Callbacks are refused when webhooks are not enabled
+        let test_server = TestServer::new(route(options())).unwrap();
+        let response = post_v2(
+            &test_server,
+            r#"{"data":"Zm9v","ttl_seconds":60,"callback_url":"http://127.0.0.1/hook"}"#,
+        );
+        assert_eq!(response.status(), hyper::StatusCode::BAD_REQUEST);
+        assert_eq!(
+            read_json(response)["detail"],
+            "invalid callback: webhooks are not enabled"
+        );
+    }
 }
@@ -0,0 +1,557 @@
+// Signs uploaders in with an OpenID Connect provider
+//
+// API clients send a token issued by the provider as a bearer token. The front-end goes through
+// the authorization code flow instead, which ends with a signed session cookie. Tokens are checked
+// against the keys the provider publishes, which are fetched on first use and again when a token
+// is signed with an unknown one
+//
+// The session and the sign-in in progress are kept in cookies as `<payload>.<signature>` in
+// URL-safe base64, the payload being JSON, so that nothing is remembered on the server
+
+use gotham::hyper;
+
+pub const SESSION_COOKIE: &str = "passer_session";
+const LOGIN_COOKIE: &str = "passer_login";
+
+// How long a session lasts after signing in
+const SESSION_LIFETIME: std::time::Duration = std::time::Duration::from_hours(8);
+
+// How long a sign-in may take at the provider
+const LOGIN_LIFETIME: std::time::Duration = std::time::Duration::from_mins(10);
+
+// How long the provider may take to answer
+const TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+// Difference allowed between the clocks of the provider and the server, in seconds
+const LEEWAY: u64 = 60;
+
+// Least time between two fetches of the keys, so that tokens naming made up keys cannot make the
+// server hammer the provider
+const REFRESH: std::time::Duration = std::time::Duration::from_mins(1);
+
+// Signatures made with keys that cannot be confused with the shared secrets of HMAC
+const ALGORITHMS: [jsonwebtoken::Algorithm; 9] = [
+    jsonwebtoken::Algorithm::RS256,
+    jsonwebtoken::Algorithm::RS384,
+    jsonwebtoken::Algorithm::RS512,
+    jsonwebtoken::Algorithm::PS256,
+    jsonwebtoken::Algorithm::PS384,
+    jsonwebtoken::Algorithm::PS512,
+    jsonwebtoken::Algorithm::ES256,
+    jsonwebtoken::Algorithm::ES384,
+    jsonwebtoken::Algorithm::EdDSA,
+];
+
+type Hmac = hmac::Hmac<sha2::Sha256>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Invalid {
+    #[error("provider unavailable: {0}")]
+    Provider(String),
+    #[error("refused by the provider: {0}")]
+    Refused(String),
+    #[error("{0}")]
+    Token(#[from] jsonwebtoken::errors::Error),
+    #[error("unsupported algorithm")]
+    Algorithm,
+    #[error("unknown signing key")]
+    UnknownKey,
+    #[error("nonce mismatch")]
+    Nonce,
+    #[error("no subject")]
+    Subject,
+    #[error("not in an allowed group")]
+    Group,
+    #[error("expired or forged session")]
+    Session,
+}
+
+pub struct Settings {
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    // Accepted audiences of bearer tokens. ID tokens are always meant for the client
+    pub audiences: Vec<String>,
+    pub scopes: Vec<String>,
+    // Users must be in one of them, when there are any
+    pub groups: Vec<String>,
+    pub groups_claim: String,
+    // Where the provider sends the browser back to, which enables the front-end to sign in
+    pub redirect_url: Option<String>,
+    pub session_secret: Option<String>,
+}
+
+// What a request signs in with
+pub enum Credentials {
+    Bearer(String),
+    Session(String),
+}
+
+impl Credentials {
+    pub fn from_headers(headers: &hyper::HeaderMap) -> Option<Self> {
+        headers
+            .get(hyper::header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(|token| Self::Bearer(token.trim().to_owned()))
+            .or_else(|| cookie(headers, SESSION_COOKIE).map(Self::Session))
+    }
+}
+
+// Where to send the browser next, with the cookies to set
+pub struct Redirect {
+    pub location: String,
+    pub cookies: Vec<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct Metadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Default)]
+struct Keys {
+    set: Option<std::sync::Arc<jsonwebtoken::jwk::JwkSet>>,
+    fetched: Option<std::time::Instant>,
+}
+
+#[derive(serde::Deserialize)]
+struct Tokens {
+    id_token: String,
+}
+
+#[derive(serde::Deserialize)]
+struct Refusal {
+    error: String,
+    error_description: Option<String>,
+}
+
+// A sign-in in progress
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Login {
+    state: String,
+    nonce: String,
+    verifier: String,
+    return_to: String,
+    expires: u64,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Session {
+    subject: String,
+    expires: u64,
+}
+
+pub struct Provider {
+    settings: Settings,
+    key: [u8; 32],
+    // Middlewares must be unwind safe. A panic while the client is used only loses that request
+    client: std::panic::AssertUnwindSafe<
+        hyper::Client<hyper_rustls::HttpsConnector<hyper::client::HttpConnector>>,
+    >,
+    metadata: std::sync::Mutex<Option<std::sync::Arc<Metadata>>>,
+    keys: std::sync::Mutex<Keys>,
+}
+
+impl Provider {
+    // Sessions are signed with the secret of `settings` when given, so that they are kept over
+    // restarts and accepted by every instance sharing it, or else with a random key
+    pub fn new(settings: Settings) -> Self {
+        use sha2::Digest;
+
+        if !settings.issuer.starts_with("https://") {
+            log::warn!(
+                "Talking to the identity provider {} without TLS",
+                settings.issuer
+            );
+        }
+
+        let connector = hyper_rustls::HttpsConnectorBuilder::new()
+            .with_webpki_roots()
+            .https_or_http()
+            .enable_http1()
+            .build();
+
+        Self {
+            key: settings
+                .session_secret
+                .as_deref()
+                .map_or_else(rand::random, |secret| sha2::Sha256::digest(secret).into()),
+            settings,
+            client: std::panic::AssertUnwindSafe(hyper::Client::builder().build(connector)),
+            metadata: std::sync::Mutex::default(),
+            keys: std::sync::Mutex::default(),
+        }
+    }
+
+    // Whether the front-end can sign in, rather than only API clients with their own tokens
+    pub fn signs_in(&self) -> bool {
+        self.settings.redirect_url.is_some()
+    }
+
+    // The subject of the user signed in with `credentials`
+    pub async fn authenticate(&self, credentials: Credentials) -> Result<String, Invalid> {
+        match credentials {
+            Credentials::Bearer(token) => self.verify(&token, &self.settings.audiences, None).await,
+            Credentials::Session(sealed) => self
+                .open::<Session>("session", &sealed)
+                .filter(|session| session.expires > seconds(std::time::SystemTime::now()))
+                .map(|session| session.subject)
+                .ok_or(Invalid::Session),
+        }
+    }
+
+    // Sends the browser to the provider, remembering the sign-in until it comes back. It is then
+    // sent to `return_to`, a path on this server
+    pub async fn login(&self, return_to: Option<&str>) -> Result<Redirect, Invalid> {
+        use sha2::Digest;
+
+        let metadata = self.metadata().await?;
+        let login = Login {
+            state: random(),
+            nonce: random(),
+            verifier: random(),
+            return_to: return_to
+                .filter(|path| {
+                    path.starts_with('/')
+                        && !path.starts_with("//")
+                        && !path.contains('\\')
+                        && !path.contains(char::is_control)
+                })
+                .unwrap_or("/")
+                .to_owned(),
+            expires: seconds(std::time::SystemTime::now() + LOGIN_LIFETIME),
+        };
+
+        let challenge = encode(sha2::Sha256::digest(&login.verifier));
+        let scopes = self.settings.scopes.join(" ");
+        let query = form(&[
+            ("response_type", "code"),
+            ("client_id", &self.settings.client_id),
+            ("redirect_uri", self.redirect_url()),
+            ("scope", &scopes),
+            ("state", &login.state),
+            ("nonce", &login.nonce),
+            ("code_challenge", &challenge),
+            ("code_challenge_method", "S256"),
+        ]);
+        let separator = if metadata.authorization_endpoint.contains('?') {
+            '&'
+        } else {
+            '?'
+        };
+
+        Ok(Redirect {
+            location: format!("{}{separator}{query}", metadata.authorization_endpoint),
+            cookies: vec![self.cookie(LOGIN_COOKIE, &self.seal("login", &login), LOGIN_LIFETIME)],
+        })
+    }
+
+    // Trades the code the provider sent the browser back with for an ID token, which starts the
+    // session
+    pub async fn callback(
+        &self,
+        code: &str,
+        state: &str,
+        headers: &hyper::HeaderMap,
+    ) -> Result<Redirect, Invalid> {
+        let login = cookie(headers, LOGIN_COOKIE)
+            .and_then(|sealed| self.open::<Login>("login", &sealed))
+            .filter(|login| {
+                login.state == state && login.expires > seconds(std::time::SystemTime::now())
+            })
+            .ok_or(Invalid::Session)?;
+
+        let metadata = self.metadata().await?;
+        let mut fields = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", self.redirect_url()),
+            ("client_id", &self.settings.client_id),
+            ("code_verifier", &login.verifier),
+        ];
+        if let Some(secret) = &self.settings.client_secret {
+            fields.push(("client_secret", secret));
+        }
+        let request = hyper::Request::post(&metadata.token_endpoint)
+            .header(
+                hyper::header::CONTENT_TYPE,
+                "application/x-www-form-urlencoded",
+            )
+            .header(hyper::header::ACCEPT, "application/json")
+            .body(hyper::Body::from(form(&fields)))
+            .map_err(|e| Invalid::Provider(e.to_string()))?;
+        let tokens = self.fetch::<Tokens>(request).await?;
+
+        let subject = self
+            .verify(
+                &tokens.id_token,
+                std::slice::from_ref(&self.settings.client_id),
+                Some(&login.nonce),
+            )
+            .await?;
+        let session = Session {
+            subject,
+            expires: seconds(std::time::SystemTime::now() + SESSION_LIFETIME),
+        };
+
+        Ok(Redirect {
+            location: login.return_to,
+            cookies: vec![
+                self.cookie(
+                    SESSION_COOKIE,
+                    &self.seal("session", &session),
+                    SESSION_LIFETIME,
+                ),
+                self.cookie(LOGIN_COOKIE, "", std::time::Duration::ZERO),
+            ],
+        })
+    }
+
+    // Checks the signature and claims of `token`, returning its subject
+    async fn verify(
+        &self,
+        token: &str,
+        audiences: &[String],
+        nonce: Option<&str>,
+    ) -> Result<String, Invalid> {
+        let header = jsonwebtoken::decode_header(token)?;
+        if !ALGORITHMS.contains(&header.alg) {
+            return Err(Invalid::Algorithm);
+        }
+        let key = jsonwebtoken::DecodingKey::from_jwk(&self.key(header.kid.as_deref()).await?)?;
+
+        let mut validation = jsonwebtoken::Validation::new(header.alg);
+        validation.leeway = LEEWAY;
+        validation.validate_nbf = true;
+        validation.set_issuer(&[&self.settings.issuer]);
+        validation.set_audience(audiences);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+        let claims = jsonwebtoken::decode::<serde_json::Map<String, serde_json::Value>>(
+            token,
+            &key,
+            &validation,
+        )?
+        .claims;
+
+        if let Some(nonce) = nonce
+            && claims.get("nonce").and_then(serde_json::Value::as_str) != Some(nonce)
+        {
+            return Err(Invalid::Nonce);
+        }
+        if !self.in_group(&claims) {
+            return Err(Invalid::Group);
+        }
+        claims
+            .get("sub")
+            .and_then(serde_json::Value::as_str)
+            .map(ToOwned::to_owned)
+            .ok_or(Invalid::Subject)
+    }
+
+    // Whether the groups claim holds one of the allowed groups, as a list or a single name
+    fn in_group(&self, claims: &serde_json::Map<String, serde_json::Value>) -> bool {
+        if self.settings.groups.is_empty() {
+            return true;
+        }
+
+        let groups = match claims.get(&self.settings.groups_claim) {
+            Some(serde_json::Value::Array(groups)) => groups
+                .iter()
+                .filter_map(serde_json::Value::as_str)
+                .collect(),
+            Some(serde_json::Value::String(group)) => vec![group.as_str()],
+            _ => Vec::new(),
+        };
+        groups
+            .iter()
+            .any(|group| self.settings.groups.iter().any(|allowed| allowed == group))
+    }
+
+    // The key named `kid`, or the only one when the token does not name it
+    async fn key(&self, kid: Option<&str>) -> Result<jsonwebtoken::jwk::Jwk, Invalid> {
+        let find = |set: &jsonwebtoken::jwk::JwkSet| match kid {
+            Some(kid) => set.find(kid).cloned(),
+            None => match set.keys.as_slice() {
+                [key] => Some(key.clone()),
+                _ => None,
+            },
+        };
+
+        {
+            let mut keys = lock(&self.keys);
+            if let Some(key) = keys.set.as_deref().and_then(find) {
+                return Ok(key);
+            }
+            // Providers publish new keys before they sign with them, so an unknown key may be new
+            if keys
+                .fetched
+                .is_some_and(|fetched| fetched.elapsed() < REFRESH)
+            {
+                return Err(Invalid::UnknownKey);
+            }
+            keys.fetched = Some(std::time::Instant::now());
+        }
+
+        let metadata = self.metadata().await?;
+        let request = hyper::Request::get(&metadata.jwks_uri)
+            .header(hyper::header::ACCEPT, "application/json")
+            .body(hyper::Body::empty())
+            .map_err(|e| Invalid::Provider(e.to_string()))?;
+        let set = std::sync::Arc::new(self.fetch::<jsonwebtoken::jwk::JwkSet>(request).await?);
+        lock(&self.keys).set = Some(set.clone());
+        find(&set).ok_or(Invalid::UnknownKey)
+    }
+
+    // The endpoints of the provider, from its discovery document
+    async fn metadata(&self) -> Result<std::sync::Arc<Metadata>, Invalid> {
+        if let Some(metadata) = lock(&self.metadata).clone() {
+            return Ok(metadata);
+        }
+
+        let url = format!(
+            "{}/.well-known/openid-configuration",
+            self.settings.issuer.trim_end_matches('/')
+        );
+        let request = hyper::Request::get(url)
+            .header(hyper::header::ACCEPT, "application/json")
+            .body(hyper::Body::empty())
+            .map_err(|e| Invalid::Provider(e.to_string()))?;
+        let metadata = self.fetch::<Metadata>(request).await?;
+        if metadata.issuer != self.settings.issuer {
+            return Err(Invalid::Provider(format!(
+                "describes the issuer {}",
+                metadata.issuer
+            )));
+        }
+
+        let metadata = std::sync::Arc::new(metadata);
+        *lock(&self.metadata) = Some(metadata.clone());
+        Ok(metadata)
+    }
+
+    async fn fetch<T: serde::de::DeserializeOwned>(
+        &self,
+        request: hyper::Request<hyper::Body>,
+    ) -> Result<T, Invalid> {
+        let body = tokio::time::timeout(TIMEOUT, async {
+            let response = self.client.request(request).await?;
+            let status = response.status();
+            hyper::body::to_bytes(response.into_body())
+                .await
+                .map(|body| (status, body))
+        })
+        .await
+        .map_err(|_| Invalid::Provider(String::from("timeout")))?
+        .map_err(|e| Invalid::Provider(e.to_string()))?;
+
+        match body {
+            (status, body) if status.is_success() => {
+                serde_json::from_slice(&body).map_err(|e| Invalid::Provider(e.to_string()))
+            }
+            (status, body) => Err(match serde_json::from_slice::<Refusal>(&body) {
+                Ok(refusal) => Invalid::Refused(
+                    refusal
+                        .error_description
+                        .map_or(refusal.error.clone(), |description| {
+                            format!("{}: {description}", refusal.error)
+                        }),
+                ),
+                Err(_) => Invalid::Provider(format!("answered {status}")),
+            }),
+        }
+    }
+
+    fn redirect_url(&self) -> &str {
+        self.settings.redirect_url.as_deref().unwrap_or_default()
+    }
+
+    // Cookies are only sent back over TLS when the server is reached over it
+    fn cookie(&self, name: &str, value: &str, lifetime: std::time::Duration) -> String {
+        let secure = if self.redirect_url().starts_with("https://") {
+            "; Secure"
+        } else {
+            ""
+        };
+        format!(
+            "{name}={value}; Path=/; Max-Age={}; HttpOnly; SameSite=Lax{secure}",
+            lifetime.as_secs()
+        )
+    }
+
+    // Signs `value` for `purpose`, so that a sealed value is not accepted for another
+    fn seal(&self, purpose: &str, value: &impl serde::Serialize) -> String {
+        use hmac::Mac;
+
+        let payload = encode(serde_json::to_vec(value).expect("values serialize to JSON"));
+        let signature = self.mac(purpose, &payload).finalize().into_bytes();
+        format!("{payload}.{}", encode(signature))
+    }
+
+    fn open<T: serde::de::DeserializeOwned>(&self, purpose: &str, sealed: &str) -> Option<T> {
+        use hmac::Mac;
+
+        let engine = base64::engine::general_purpose::URL_SAFE_NO_PAD;
+        let (payload, signature) = sealed.split_once('.')?;
+        let signature = base64::Engine::decode(&engine, signature).ok()?;
+        self.mac(purpose, payload).verify_slice(&signature).ok()?;
+        serde_json::from_slice(&base64::Engine::decode(&engine, payload).ok()?).ok()
+    }
+
+    fn mac(&self, purpose: &str, payload: &str) -> Hmac {
+        use hmac::Mac;
+
+        let mut mac = <Hmac as Mac>::new_from_slice(&self.key).expect("HMAC takes any key size");
+        mac.update(purpose.as_bytes());
+        mac.update(b".");
+        mac.update(payload.as_bytes());
+        mac
+    }
+}
+
+// The value of the cookie `name` sent with a request
+fn cookie(headers: &hyper::HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get_all(hyper::header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(cookie, _)| *cookie == name)
+        .map(|(_, value)| value.to_owned())
+}
+
+fn form(fields: &[(&str, &str)]) -> String {
+    fields
+        .iter()
+        .map(|(name, value)| {
+            format!(
+                "{name}={}",
+                percent_encoding::utf8_percent_encode(value, percent_encoding::NON_ALPHANUMERIC)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+fn encode(bytes: impl AsRef<[u8]>) -> String {
+    base64::Engine::encode(&base64::engine::general_purpose::URL_SAFE_NO_PAD, bytes)
+}
+
+fn random() -> String {
+    encode(rand::random::<[u8; 32]>())
+}
+
+fn seconds(time: std::time::SystemTime) -> u64 {
+    time.duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+fn lock<T>(mutex: &std::sync::Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+}
@@ -0,0 +1,536 @@
+use super::error::Error;
+use super::store;
+
+// The OpenAPI 3 description of the routes drawn by `add_routes`
+//
+// Error responses are derived from the status and code of the errors each operation can fail with,
+// so that they follow any change to `Error`
+//
+// The optional features of the server, as listed in the front-end configuration, add their own
+// errors and requirements
+pub fn spec(
+    api_url: &str,
+    max_ttl: Option<std::time::Duration>,
+    features: &[&str],
+) -> serde_json::Value {
+    let api_key = features.contains(&"api_key");
+    let proof_of_work = features.contains(&"proof_of_work");
+    let webhooks = features.contains(&"webhooks");
+    let create_errors = create_errors(max_ttl, features);
+    let retrieve_errors = retrieve_errors(features);
+    let mut paths = v1(max_ttl, &create_errors, &retrieve_errors);
+    paths.extend(v2(&create_errors, &retrieve_errors));
+    paths.insert(
+        String::from("/openapi.json"),
+        serde_json::json!({
+            "get": {
+                "operationId": "openapi",
+                "summary": "This document",
+                "responses": {
+                    "200": {
+                        "description": "The OpenAPI description of the API",
+                        "content": { "application/json": { "schema": { "type": "object" } } },
+                    },
+                },
+            },
+        }),
+    );
+
+    let mut components = serde_json::json!({ "schemas": schemas(max_ttl) });
+    if api_key {
+        for path in ["/", "/v2/secrets"] {
+            if let Some(operation) = paths.get_mut(path) {
+                operation["post"]["security"] = serde_json::json!([{ "apiKey": [] }]);
+            }
+        }
+        components["securitySchemes"] = serde_json::json!({
+            "apiKey": { "type": "http", "scheme": "bearer" },
+        });
+        if features.contains(&"oidc") {
+            components["securitySchemes"]["apiKey"]["description"] =
+                "An API key, or a token issued by the OpenID Connect provider".into();
+        }
+    }
+    if proof_of_work {
+        for path in ["/", "/v2/secrets"] {
+            if let Some(operation) = paths.get_mut(path) {
+                let parameters = &mut operation["post"]["parameters"];
+                if parameters.is_null() {
+                    *parameters = serde_json::json!([]);
+                }
+                if let Some(parameters) = parameters.as_array_mut() {
+                    parameters.push(proof_parameter(api_key));
+                }
+            }
+        }
+    }
+
+    if webhooks {
+        add_callbacks(&mut paths, &mut components);
+    }
+
+    serde_json::json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "passer",
+            "description": "Stores encrypted secrets until they are first read or expire",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "servers": [{ "url": api_url }],
+        "paths": paths,
+        "components": components,
+    })
+}
+
+fn create_errors(max_ttl: Option<std::time::Duration>, features: &[&str]) -> Vec<Error> {
+    let mut errors = vec![
+        Error::ContentLengthMissing,
+        Error::NothingToInsert,
+        Error::PayloadTooLarge,
+        Error::Store(store::Error::TooLarge),
+        Error::ReadTimeout,
+        Error::TooSlow(0),
+        Error::Store(store::Error::StoreFull),
+        Error::ReadOnly,
+        Error::FailedToAcquireStore,
+    ];
+    if let Some(max_ttl) = max_ttl {
+        errors.push(Error::TtlTooLong(max_ttl));
+    }
+    if features.contains(&"api_key") {
+        errors.push(Error::Unauthorized);
+    }
+    if features.contains(&"oidc") {
+        errors.extend([
+            Error::SignIn(super::oidc::Invalid::Session),
+            Error::SignIn(super::oidc::Invalid::Group),
+            Error::SignIn(super::oidc::Invalid::Provider(String::new())),
+        ]);
+    }
+    if features.contains(&"quotas") {
+        errors.extend([
+            Error::SecretQuota(0),
+            Error::ByteQuota(0),
+            Error::TtlQuota(std::time::Duration::ZERO),
+        ]);
+    }
+    if features.contains(&"rate_limit") {
+        errors.push(Error::RateLimited(std::time::Duration::ZERO));
+    }
+    if features.contains(&"proof_of_work") {
+        errors.push(Error::ProofOfWork(super::pow::Invalid::Missing));
+    }
+    if features.contains(&"webhooks") {
+        errors.push(Error::Callback(super::webhook::Refused::Host));
+    }
+    errors
+}
+
+fn retrieve_errors(features: &[&str]) -> Vec<Error> {
+    let mut errors = vec![
+        Error::Store(store::Error::InvalidId(base64::DecodeError::InvalidLength(
+            0,
+        ))),
+        Error::Store(store::Error::SecretNotFound),
+        Error::FailedToAcquireStore,
+    ];
+    if features.contains(&"rate_limit") {
+        errors.extend([
+            Error::RateLimited(std::time::Duration::ZERO),
+            Error::Banned(std::time::Duration::ZERO),
+        ]);
+    }
+    errors
+}
+
+fn id_parameter() -> serde_json::Value {
+    serde_json::json!({
+        "name": "id",
+        "in": "path",
+        "required": true,
+        "description": "Identifier returned when the secret was created",
+        "schema": { "type": "string", "pattern": "^[a-zA-Z0-9_-]{43}$" },
+    })
+}
+
+fn proof_parameter(api_key: bool) -> serde_json::Value {
+    let description = if api_key {
+        "Solution of a challenge from `/v2/challenge`, as `<challenge>:<nonce>`. Not needed \
+         with an API key"
+    } else {
+        "Solution of a challenge from `/v2/challenge`, as `<challenge>:<nonce>`"
+    };
+
+    serde_json::json!({
+        "name": super::handler::PROOF_OF_WORK,
+        "in": "header",
+        "required": !api_key,
+        "description": description,
+        "schema": { "type": "string" },
+    })
+}
+
+// Lets uploads give a callback URL, and describes the events it is sent
+fn add_callbacks(
+    paths: &mut serde_json::Map<String, serde_json::Value>,
+    components: &mut serde_json::Value,
+) {
+    use serde_json::json;
+
+    let description = "Where to send a signed `POST` when the secret is read or expires unread. \
+        Must be an `http` URL on one of the hosts allowed by the server";
+    let callback = |expression: &str| {
+        json!({
+            "event": {
+                expression: {
+                    "post": {
+                        "summary": "Tells that the secret was read or expired",
+                        "parameters": [
+                            {
+                                "name": super::webhook::SIGNATURE,
+                                "in": "header",
+                                "required": true,
+                                "description": "`sha256=` followed by the hex HMAC-SHA256 of the \
+                                    body, keyed with the webhook secret of the server",
+                                "schema": { "type": "string" },
+                            },
+                            {
+                                "name": super::webhook::DELIVERY,
+                                "in": "header",
+                                "required": true,
+                                "description": "Identifier of the event, kept by its retries",
+                                "schema": { "type": "string" },
+                            },
+                        ],
+                        "requestBody": {
+                            "required": true,
+                            "content": {
+                                "application/json": {
+                                    "schema": { "$ref": "#/components/schemas/WebhookEvent" },
+                                },
+                            },
+                        },
+                        "responses": {
+                            "2XX": { "description": "Received. Anything else is retried" },
+                        },
+                    },
+                },
+            },
+        })
+    };
+
+    if let Some(post) = paths.get_mut("/").map(|operation| &mut operation["post"]) {
+        if let Some(parameters) = post["parameters"].as_array_mut() {
+            parameters.push(json!({
+                "name": "callback",
+                "in": "query",
+                "required": false,
+                "description": description,
+                "schema": { "type": "string", "format": "uri" },
+            }));
+        }
+        post["callbacks"] = callback("{$request.query.callback}");
+    }
+    if let Some(post) = paths
+        .get_mut("/v2/secrets")
+        .map(|operation| &mut operation["post"])
+    {
+        post["callbacks"] = callback("{$request.body#/callback_url}");
+    }
+
+    let schemas = &mut components["schemas"];
+    schemas["CreateRequest"]["properties"]["callback_url"] = json!({
+        "type": "string",
+        "format": "uri",
+        "description": description,
+    });
+    schemas["WebhookEvent"] = json!({
+        "type": "object",
+        "required": ["event", "secret", "time"],
+        "properties": {
+            "event": { "type": "string", "enum": ["retrieved", "expired"] },
+            "secret": { "type": "string", "description": "Identifier of the secret" },
+            "time": {
+                "type": "integer",
+                "description": "When it happened, in seconds since the Unix epoch",
+            },
+        },
+    });
+}
+
+// The raw body routes
+fn v1(
+    max_ttl: Option<std::time::Duration>,
+    create_errors: &[Error],
+    retrieve_errors: &[Error],
+) -> serde_json::Map<String, serde_json::Value> {
+    use serde_json::json;
+
+    let format = "How long the secret is kept, in `<amount>[m|h|d]` format";
+    let description = match max_ttl {
+        Some(max_ttl) => format!("{format}. At most {}m", max_ttl.as_secs() / 60),
+        None => String::from(format),
+    };
+    let ttl = json!({
+        "name": "ttl",
+        "in": "query",
+        "required": true,
+        "description": description,
+        "schema": { "type": "string", "pattern": "^[0-9]+[mhd]$" },
+    });
+
+    object(json!({
+        "/": {
+            "post": {
+                "operationId": "create",
+                "summary": "Stores a secret",
+                "parameters": [ttl],
+                "requestBody": {
+                    "required": true,
+                    "content": {
+                        "application/octet-stream": {
+                            "schema": {
+                                "type": "string",
+                                "format": "binary",
+                                "maxLength": store::MAX_SECRET_SIZE,
+                            },
+                        },
+                    },
+                },
+                "responses": responses(
+                    "201",
+                    json!({
+                        "description": "The identifier of the secret",
+                        "content": { "text/plain": { "schema": { "type": "string" } } },
+                    }),
+                    create_errors,
+                    false,
+                ),
+            },
+        },
+        "/{id}": {
+            "get": {
+                "operationId": "retrieve",
+                "summary": "Reads and deletes a secret",
+                "parameters": [id_parameter()],
+                "responses": responses(
+                    "200",
+                    json!({
+                        "description": "The secret",
+                        "content": {
+                            "application/octet-stream": {
+                                "schema": { "type": "string", "format": "binary" },
+                            },
+                        },
+                    }),
+                    retrieve_errors,
+                    false,
+                ),
+            },
+        },
+    }))
+}
+
+// The JSON routes
+fn v2(
+    create_errors: &[Error],
+    retrieve_errors: &[Error],
+) -> serde_json::Map<String, serde_json::Value> {
+    use serde_json::json;
+
+    let invalid_body = Error::InvalidBody(String::new());
+
+    object(json!({
+        "/v2/secrets": {
+            "post": {
+                "operationId": "create_v2",
+                "summary": "Stores a secret",
+                "requestBody": {
+                    "required": true,
+                    "content": {
+                        "application/json": {
+                            "schema": { "$ref": "#/components/schemas/CreateRequest" },
+                        },
+                    },
+                },
+                "responses": responses(
+                    "201",
+                    json!({
+                        "description": "The stored secret",
+                        "content": {
+                            "application/json": {
+                                "schema": { "$ref": "#/components/schemas/Created" },
+                            },
+                        },
+                    }),
+                    std::iter::once(&invalid_body).chain(create_errors),
+                    true,
+                ),
+            },
+        },
+        "/v2/challenge": {
+            "get": {
+                "operationId": "challenge",
+                "summary": "Issues a puzzle to solve before uploading",
+                "description": "The SHA-256 of the proof `<challenge>:<nonce>` must start with \
+                    `difficulty` zero bits, for any nonce of up to 32 characters. Each challenge \
+                    pays for a single upload. No work is needed when the difficulty is 0",
+                "parameters": [{
+                    "name": "size",
+                    "in": "query",
+                    "required": true,
+                    "description": "Length of the request body to upload, in bytes",
+                    "schema": { "type": "integer", "minimum": 0 },
+                }],
+                "responses": responses(
+                    "200",
+                    json!({
+                        "description": "The challenge",
+                        "content": {
+                            "application/json": {
+                                "schema": { "$ref": "#/components/schemas/Challenge" },
+                            },
+                        },
+                    }),
+                    &[Error::FailedToAcquireStore],
+                    true,
+                ),
+            },
+        },
+        "/v2/secrets/{id}": {
+            "get": {
+                "operationId": "retrieve_v2",
+                "summary": "Reads and deletes a secret",
+                "parameters": [id_parameter()],
+                "responses": responses(
+                    "200",
+                    json!({
+                        "description": "The secret",
+                        "content": {
+                            "application/json": {
+                                "schema": { "$ref": "#/components/schemas/Secret" },
+                            },
+                        },
+                    }),
+                    retrieve_errors,
+                    true,
+                ),
+            },
+        },
+    }))
+}
+
+fn schemas(max_ttl: Option<std::time::Duration>) -> serde_json::Value {
+    let mut schemas = serde_json::json!({
+        "CreateRequest": {
+            "type": "object",
+            "required": ["data", "ttl_seconds"],
+            "additionalProperties": false,
+            "properties": {
+                "data": {
+                    "type": "string",
+                    "format": "byte",
+                    "description": "The encrypted secret in base64",
+                },
+                "ttl_seconds": {
+                    "type": "integer",
+                    "minimum": 0,
+                },
+            },
+        },
+        "Created": {
+            "type": "object",
+            "required": ["id", "expires_at", "size"],
+            "properties": {
+                "id": { "type": "string" },
+                "expires_at": {
+                    "type": "integer",
+                    "description": "Seconds since the Unix epoch",
+                },
+                "size": { "type": "integer" },
+            },
+        },
+        "Secret": {
+            "type": "object",
+            "required": ["id", "data", "size"],
+            "properties": {
+                "id": { "type": "string" },
+                "data": { "type": "string", "format": "byte" },
+                "size": { "type": "integer" },
+            },
+        },
+        "Challenge": {
+            "type": "object",
+            "required": ["challenge", "difficulty", "expires_at"],
+            "properties": {
+                "challenge": { "type": "string" },
+                "difficulty": {
+                    "type": "integer",
+                    "maximum": super::pow::MAX_DIFFICULTY,
+                    "description": "Leading zero bits the hash of the proof must have",
+                },
+                "expires_at": {
+                    "type": "integer",
+                    "description": "Seconds since the Unix epoch",
+                },
+            },
+        },
+        "Problem": {
+            "type": "object",
+            "required": ["type", "title", "status"],
+            "properties": {
+                "type": { "type": "string" },
+                "title": { "type": "string" },
+                "status": { "type": "integer" },
+                "detail": { "type": "string" },
+                "code": {
+                    "type": "string",
+                    "description": "Stable identifier of the error",
+                },
+            },
+        },
+    });
+    if let Some(max_ttl) = max_ttl {
+        schemas["CreateRequest"]["properties"]["ttl_seconds"]["maximum"] = max_ttl.as_secs().into();
+    }
+    schemas
+}
+
+fn object(value: serde_json::Value) -> serde_json::Map<String, serde_json::Value> {
+    match value {
+        serde_json::Value::Object(object) => object,
+        _ => serde_json::Map::new(),
+    }
+}
+
+// Groups the errors by status, listing the codes that can be answered with each
+fn responses<'a>(
+    status: &str,
+    success: serde_json::Value,
+    errors: impl IntoIterator<Item = &'a Error>,
+    problems: bool,
+) -> serde_json::Value {
+    let mut codes = std::collections::BTreeMap::<u16, Vec<&'static str>>::new();
+    for error in errors {
+        let codes = codes.entry(error.status_code().as_u16()).or_default();
+        if !codes.contains(&error.code()) {
+            codes.push(error.code());
+        }
+    }
+
+    let mut responses = serde_json::Map::new();
+    responses.insert(String::from(status), success);
+    for (status, codes) in codes {
+        let mut response = serde_json::json!({ "description": codes.join(", ") });
+        if problems {
+            response["content"] = serde_json::json!({
+                "application/problem+json": {
+                    "schema": { "$ref": "#/components/schemas/Problem" },
+                },
+            });
+        }
+        responses.insert(status.to_string(), response);
+    }
+
+    serde_json::Value::Object(responses)
+}
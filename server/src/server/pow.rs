@@ -0,0 +1,338 @@
+// Hashcash-style puzzles that anonymous clients solve before uploading
+//
+// Challenges are signed rather than remembered, so that issuing them costs nothing. A challenge
+// is `<payload>.<signature>` in URL-safe base64, the payload holding when it expires, the largest
+// upload it allows, the number of leading zero bits required and some randomness. A proof is
+// `<challenge>:<nonce>` whose SHA-256 starts with that many zero bits
+//
+// Proofs are remembered until their challenge expires, so that each one pays for a single upload
+
+// How long clients have to solve a challenge and upload
+const LIFETIME: std::time::Duration = std::time::Duration::from_mins(5);
+
+// Each doubling of the payload past this size doubles the work
+const SIZE_STEP: u64 = 64 * 1024;
+
+// Longest nonce accepted in a proof, which is plenty for a counter
+const MAX_NONCE: usize = 32;
+
+// Most bits ever asked, which a browser still solves in under a minute
+pub const MAX_DIFFICULTY: u8 = 26;
+
+type Hmac = hmac::Hmac<sha2::Sha256>;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, thiserror::Error)]
+pub enum Invalid {
+    #[error("missing")]
+    Missing,
+    #[error("malformed")]
+    Malformed,
+    #[error("not issued by this server")]
+    Forged,
+    #[error("challenge expired")]
+    Expired,
+    #[error("challenge for a smaller upload")]
+    TooLarge,
+    #[error("not enough work")]
+    Insufficient,
+    #[error("already used")]
+    Reused,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Issued {
+    pub challenge: String,
+    pub difficulty: u8,
+    pub expires: std::time::SystemTime,
+}
+
+struct Claims {
+    expires: u64,
+    size: u64,
+    difficulty: u8,
+}
+
+pub struct Puzzles {
+    key: [u8; 32],
+    // Leading zero bits asked of the smallest uploads on an empty store, when work is required
+    difficulty: Option<u8>,
+    // Signatures of the challenges already used, with when they expire
+    spent: std::sync::Mutex<std::collections::HashMap<[u8; 32], u64>>,
+}
+
+impl Puzzles {
+    // Challenges are signed with `secret` when given, so that they can be verified by every
+    // instance sharing it, or else with a random key
+    pub fn new(difficulty: Option<u8>, secret: Option<&str>) -> Self {
+        use sha2::Digest;
+
+        Self {
+            key: secret.map_or_else(rand::random, |secret| sha2::Sha256::digest(secret).into()),
+            difficulty,
+            spent: std::sync::Mutex::default(),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.difficulty.is_some()
+    }
+
+    // The bits needed for `size` bytes while the store is `used` out of `capacity` bytes full.
+    // Each doubling of the payload past `SIZE_STEP`, and each quarter of the store filled, adds a
+    // bit, up to `MAX_DIFFICULTY`. No work is needed when disabled
+    pub fn difficulty(&self, size: u64, used: u64, capacity: u64) -> u8 {
+        let Some(difficulty) = self.difficulty else {
+            return 0;
+        };
+
+        let size_bits = size.div_ceil(SIZE_STEP).checked_ilog2().unwrap_or(0);
+        let load_bits = u128::from(used) * 4 / u128::from(capacity.max(1));
+
+        let bits = u64::from(difficulty)
+            + u64::from(size_bits)
+            + u64::try_from(load_bits.min(4)).unwrap_or(4);
+        u8::try_from(bits.min(u64::from(MAX_DIFFICULTY))).unwrap_or(MAX_DIFFICULTY)
+    }
+
+    pub fn issue(&self, size: u64, difficulty: u8, now: std::time::SystemTime) -> Issued {
+        let expires = now + LIFETIME;
+        let mut payload = Vec::with_capacity(33);
+        payload.extend(seconds(expires).to_be_bytes());
+        payload.extend(size.to_be_bytes());
+        payload.push(difficulty);
+        payload.extend(rand::random::<[u8; 16]>());
+
+        let engine = base64::engine::general_purpose::URL_SAFE_NO_PAD;
+        let challenge = format!(
+            "{}.{}",
+            base64::Engine::encode(&engine, &payload),
+            base64::Engine::encode(&engine, self.sign(&payload)),
+        );
+
+        Issued {
+            challenge,
+            difficulty,
+            expires,
+        }
+    }
+
+    // Accepts a proof for an upload of `size` bytes, which cannot be used again
+    pub fn verify(
+        &self,
+        proof: &str,
+        size: u64,
+        now: std::time::SystemTime,
+    ) -> Result<(), Invalid> {
+        use sha2::Digest;
+
+        let (challenge, nonce) = proof.rsplit_once(':').ok_or(Invalid::Malformed)?;
+        if nonce.is_empty() || nonce.len() > MAX_NONCE {
+            return Err(Invalid::Malformed);
+        }
+        let (claims, signature) = self.open(challenge)?;
+
+        let now = seconds(now);
+        if claims.expires <= now {
+            return Err(Invalid::Expired);
+        }
+        if size > claims.size {
+            return Err(Invalid::TooLarge);
+        }
+        if leading_zeros(&sha2::Sha256::digest(proof)) < u32::from(claims.difficulty) {
+            return Err(Invalid::Insufficient);
+        }
+
+        // Challenges are kept only until they expire, and only once paid for, so this stays small
+        let mut spent = self
+            .spent
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        spent.retain(|_, expires| *expires > now);
+        if spent.insert(signature, claims.expires).is_some() {
+            return Err(Invalid::Reused);
+        }
+        Ok(())
+    }
+
+    fn sign(&self, payload: &[u8]) -> [u8; 32] {
+        use hmac::Mac;
+
+        let mut mac = <Hmac as Mac>::new_from_slice(&self.key).expect("HMAC takes any key size");
+        mac.update(payload);
+        mac.finalize().into_bytes().into()
+    }
+
+    // Checks the signature of the challenge, returning what it claims and its signature
+    fn open(&self, challenge: &str) -> Result<(Claims, [u8; 32]), Invalid> {
+        let engine = base64::engine::general_purpose::URL_SAFE_NO_PAD;
+        let (payload, signature) = challenge.split_once('.').ok_or(Invalid::Malformed)?;
+        let payload = base64::Engine::decode(&engine, payload).map_err(|_| Invalid::Malformed)?;
+        let signature = base64::Engine::decode(&engine, signature)
+            .ok()
+            .and_then(|signature| <[u8; 32]>::try_from(signature).ok())
+            .ok_or(Invalid::Malformed)?;
+        if payload.len() != 33 {
+            return Err(Invalid::Malformed);
+        }
+        if !super::middleware::same_digest(&self.sign(&payload), &signature) {
+            return Err(Invalid::Forged);
+        }
+
+        let (expires, rest) = payload.split_at(8);
+        let (size, rest) = rest.split_at(8);
+        let claims = Claims {
+            expires: u64::from_be_bytes(expires.try_into().map_err(|_| Invalid::Malformed)?),
+            size: u64::from_be_bytes(size.try_into().map_err(|_| Invalid::Malformed)?),
+            difficulty: rest[0],
+        };
+        Ok((claims, signature))
+    }
+}
+
+fn seconds(time: std::time::SystemTime) -> u64 {
+    time.duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs())
+}
+
+fn leading_zeros(hash: &[u8]) -> u32 {
+    let mut zeros = 0;
+    for byte in hash {
+        zeros += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    zeros
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Invalid, MAX_DIFFICULTY, Puzzles};
+    use crate::store::MAX_SECRET_SIZE;
+
+    // Finds a nonce the way clients do, starting with `prefix`
+    fn solve(challenge: &str, prefix: &str, difficulty: u8) -> String {
+        use sha2::Digest;
+
+        (0..u64::MAX)
+            .map(|nonce| format!("{challenge}:{prefix}{nonce}"))
+            .find(|proof| {
+                super::leading_zeros(&sha2::Sha256::digest(proof)) >= u32::from(difficulty)
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn difficulty() {
+        let puzzles = Puzzles::new(Some(8), None);
+
+        assert_eq!(puzzles.difficulty(1, 0, 100), 8);
+        assert_eq!(puzzles.difficulty(64 * 1024, 0, 100), 8);
+        assert_eq!(puzzles.difficulty(64 * 1024 + 1, 0, 100), 9);
+        assert_eq!(puzzles.difficulty(1024 * 1024, 0, 100), 12);
+        assert_eq!(puzzles.difficulty(1, 50, 100), 10);
+        assert_eq!(puzzles.difficulty(1, 100, 100), 12);
+        assert_eq!(
+            Puzzles::new(Some(255), None).difficulty(1024 * 1024, 100, 100),
+            MAX_DIFFICULTY
+        );
+        assert_eq!(
+            Puzzles::new(None, None).difficulty(1024 * 1024, 100, 100),
+            0
+        );
+    }
+
+    #[test]
+    fn difficulty_bounded() {
+        let capacity = 30 * MAX_SECRET_SIZE;
+
+        // The largest uploads to a full store add 14 bits, which the cap then clips
+        for (base, hardest) in [(8, 22), (12, 26), (16, 26), (MAX_DIFFICULTY, 26)] {
+            let puzzles = Puzzles::new(Some(base), None);
+            assert_eq!(
+                puzzles.difficulty(MAX_SECRET_SIZE, capacity, capacity),
+                hardest
+            );
+        }
+        assert_eq!(MAX_DIFFICULTY, 26);
+    }
+
+    #[test]
+    fn verify() {
+        let puzzles = Puzzles::new(Some(0), None);
+        let now = std::time::SystemTime::now();
+        let challenge = puzzles.issue(100, 8, now).challenge;
+        let proof = solve(&challenge, "", 8);
+
+        assert_eq!(puzzles.verify(&proof, 101, now), Err(Invalid::TooLarge));
+        assert_eq!(
+            puzzles.verify(&proof, 100, now + super::LIFETIME),
+            Err(Invalid::Expired)
+        );
+        assert_eq!(
+            puzzles.verify(&challenge, 100, now),
+            Err(Invalid::Malformed)
+        );
+
+        assert_eq!(puzzles.verify(&proof, 100, now), Ok(()));
+        assert_eq!(puzzles.verify(&proof, 100, now), Err(Invalid::Reused));
+
+        // Another nonce for the same challenge is no new proof
+        let other = solve(&challenge, "x", 8);
+        assert_eq!(puzzles.verify(&other, 100, now), Err(Invalid::Reused));
+    }
+
+    #[test]
+    fn reject_tampering() {
+        let puzzles = Puzzles::new(Some(0), None);
+        let now = std::time::SystemTime::now();
+        let challenge = puzzles.issue(100, 16, now).challenge;
+
+        // Lowering the difficulty breaks the signature
+        let engine = base64::engine::general_purpose::URL_SAFE_NO_PAD;
+        let (payload, signature) = challenge.split_once('.').unwrap();
+        let mut payload = base64::Engine::decode(&engine, payload).unwrap();
+        payload[16] = 0;
+        let forged = format!("{}.{signature}:0", base64::Engine::encode(&engine, payload));
+        assert_eq!(puzzles.verify(&forged, 100, now), Err(Invalid::Forged));
+
+        // Challenges of other servers are not accepted
+        let other = Puzzles::new(Some(0), None).issue(100, 0, now).challenge;
+        assert_eq!(
+            puzzles.verify(&format!("{other}:0"), 100, now),
+            Err(Invalid::Forged)
+        );
+
+        // Unless they share the secret
+        let shared = Puzzles::new(Some(0), Some("secret"));
+        let challenge = Puzzles::new(Some(0), Some("secret"))
+            .issue(100, 0, now)
+            .challenge;
+        assert_eq!(shared.verify(&format!("{challenge}:0"), 100, now), Ok(()));
+    }
+
+    // Kept the same as in `wasm/src/lib.rs`, so that the server and its clients agree on what a
+    // valid proof is
+    #[test]
+    fn leading_zeros() {
+        use sha2::Digest;
+
+        for (hash, zeros) in [
+            (&[][..], 0),
+            (&[0xff][..], 0),
+            (&[0x0f, 0x00][..], 4),
+            (&[0x00, 0x80][..], 8),
+            (&[0x00, 0x00, 0x01, 0xff][..], 23),
+            (&[0x00; 32][..], 256),
+        ] {
+            assert_eq!(super::leading_zeros(hash), zeros, "{hash:02x?}");
+        }
+
+        // The first proof of `foo.bar` with 16 zero bits
+        assert_eq!(
+            super::leading_zeros(&sha2::Sha256::digest("foo.bar:3099")),
+            16
+        );
+        assert_eq!(solve("foo.bar", "", 16), "foo.bar:3099");
+    }
+}
@@ -0,0 +1,340 @@
+use gotham::hyper;
+
+// A network in CIDR notation, such as `10.0.0.0/8` or `fd00::/8`
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Cidr {
+    network: std::net::IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: std::net::IpAddr) -> bool {
+        match (self.network, canonical(ip)) {
+            (std::net::IpAddr::V4(network), std::net::IpAddr::V4(ip)) => {
+                mask(u32::from(network), self.prefix) == mask(u32::from(ip), self.prefix)
+            }
+            (std::net::IpAddr::V6(network), std::net::IpAddr::V6(ip)) => {
+                mask(u128::from(network), self.prefix) == mask(u128::from(ip), self.prefix)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl std::str::FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (network, prefix) = value
+            .split_once('/')
+            .map_or((value, None), |(network, prefix)| (network, Some(prefix)));
+
+        let network = network
+            .parse::<std::net::IpAddr>()
+            .map(canonical)
+            .map_err(|e| format!("invalid network address: {e}"))?;
+        let max = if network.is_ipv4() { 32 } else { 128 };
+
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse::<u8>()
+                .ok()
+                .filter(|prefix| *prefix <= max)
+                .ok_or_else(|| format!("prefix length must be between 0 and {max}"))?,
+            None => max,
+        };
+
+        Ok(Self { network, prefix })
+    }
+}
+
+impl std::fmt::Display for Cidr {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(fmt, "{}/{}", self.network, self.prefix)
+    }
+}
+
+// Keeps the `prefix` highest bits of an address
+fn mask<T>(bits: T, prefix: u8) -> T
+where
+    T: Default + std::ops::Shl<u32, Output = T> + std::ops::Shr<u32, Output = T>,
+{
+    let width = u32::try_from(std::mem::size_of::<T>() * 8).unwrap_or(u32::MAX);
+    let shift = width - u32::from(prefix);
+    if shift >= width {
+        T::default()
+    } else {
+        (bits >> shift) << shift
+    }
+}
+
+// IPv4 clients of a dual stack socket show up as IPv4-mapped IPv6 addresses
+fn canonical(ip: std::net::IpAddr) -> std::net::IpAddr {
+    match ip {
+        std::net::IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, std::net::IpAddr::V4),
+        std::net::IpAddr::V4(_) => ip,
+    }
+}
+
+// Resolves the address of the client behind the trusted proxies
+//
+// The chain of forwarded addresses is walked from the closest hop, and the first address that is
+// not a trusted proxy is the client. Headers are ignored unless the peer itself is trusted, so
+// that clients cannot choose the address they are known by. `Forwarded` takes precedence over
+// `X-Forwarded-For` when both are present.
+//
+// Returns the address and whether it was taken from the headers.
+pub fn resolve(
+    peer: std::net::IpAddr,
+    headers: &hyper::HeaderMap,
+    trusted: &[Cidr],
+) -> (std::net::IpAddr, bool) {
+    let is_trusted = |ip| trusted.iter().any(|cidr| cidr.contains(ip));
+
+    let peer = canonical(peer);
+    if !is_trusted(peer) {
+        return (peer, false);
+    }
+
+    let chain = if headers.contains_key(hyper::header::FORWARDED) {
+        forwarded(headers)
+    } else {
+        x_forwarded_for(headers)
+    };
+
+    let mut client = (peer, false);
+    for hop in chain.iter().rev() {
+        // Obfuscated or unknown hops cannot be followed further, so the proxy that reported them
+        // is the best known address
+        let Some(ip) = hop.map(canonical) else {
+            break;
+        };
+
+        client = (ip, true);
+        if !is_trusted(ip) {
+            break;
+        }
+    }
+
+    client
+}
+
+fn values<'a>(headers: &'a hyper::HeaderMap, name: &str) -> impl Iterator<Item = &'a str> {
+    headers
+        .get_all(name)
+        .into_iter()
+        // A header that is not valid text cannot be trusted as a whole, so it ends the chain early
+        .map(|value| value.to_str().unwrap_or("unknown"))
+}
+
+fn x_forwarded_for(headers: &hyper::HeaderMap) -> Vec<Option<std::net::IpAddr>> {
+    values(headers, "x-forwarded-for")
+        .flat_map(|value| value.split(','))
+        .map(|hop| node(hop.trim()))
+        .collect()
+}
+
+// Parses the `for` parameters of RFC 7239 `Forwarded` headers
+fn forwarded(headers: &hyper::HeaderMap) -> Vec<Option<std::net::IpAddr>> {
+    values(headers, hyper::header::FORWARDED.as_str())
+        .flat_map(split_quoted(','))
+        .map(|element| {
+            split_quoted(';')(element)
+                .filter_map(|pair| pair.split_once('='))
+                .find(|(key, _)| key.trim().eq_ignore_ascii_case("for"))
+                .and_then(|(_, value)| node(value.trim().trim_matches('"')))
+        })
+        .collect()
+}
+
+// Splits on a separator that is not within a quoted string
+fn split_quoted(separator: char) -> impl Fn(&str) -> std::vec::IntoIter<&str> {
+    move |value| {
+        let mut parts = Vec::new();
+        let mut quoted = false;
+        let mut escaped = false;
+        let mut start = 0;
+
+        for (index, c) in value.char_indices() {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' if quoted => escaped = true,
+                '"' => quoted = !quoted,
+                c if c == separator && !quoted => {
+                    parts.push(&value[start..index]);
+                    start = index + 1;
+                }
+                _ => {}
+            }
+        }
+        parts.push(&value[start..]);
+
+        parts.into_iter()
+    }
+}
+
+// Parses a node, which may carry a port and brackets around IPv6 addresses
+fn node(node: &str) -> Option<std::net::IpAddr> {
+    if let Some(bracketed) = node.strip_prefix('[') {
+        return bracketed.split_once(']')?.0.parse().ok();
+    }
+
+    node.parse().ok().or_else(|| {
+        let (ip, port) = node.rsplit_once(':')?;
+        port.parse::<u16>().ok()?;
+        ip.parse::<std::net::Ipv4Addr>().ok().map(Into::into)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Cidr;
+    use gotham::hyper;
+
+    fn headers(headers: &[(&'static str, &'static str)]) -> hyper::HeaderMap {
+        headers
+            .iter()
+            .map(|(name, value)| {
+                (
+                    hyper::header::HeaderName::from_static(name),
+                    hyper::header::HeaderValue::from_static(value),
+                )
+            })
+            .collect()
+    }
+
+    fn trusted() -> Vec<Cidr> {
+        vec!["10.0.0.0/8".parse().unwrap(), "fd00::/8".parse().unwrap()]
+    }
+
+    fn ip(ip: &str) -> std::net::IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn parse_cidr() {
+        assert_eq!(
+            "10.0.0.0/8".parse::<Cidr>().unwrap().to_string(),
+            "10.0.0.0/8"
+        );
+        assert_eq!(
+            "10.1.2.3".parse::<Cidr>().unwrap().to_string(),
+            "10.1.2.3/32"
+        );
+        assert_eq!("fd00::/8".parse::<Cidr>().unwrap().to_string(), "fd00::/8");
+        assert_eq!("::1".parse::<Cidr>().unwrap().to_string(), "::1/128");
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("fd00::/129".parse::<Cidr>().is_err());
+        assert!("localhost/8".parse::<Cidr>().is_err());
+        assert!("10.0.0.0/".parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn cidr_contains() {
+        let cidr = "10.1.0.0/16".parse::<Cidr>().unwrap();
+        assert!(cidr.contains(ip("10.1.2.3")));
+        assert!(cidr.contains(ip("::ffff:10.1.2.3")));
+        assert!(!cidr.contains(ip("10.2.0.0")));
+        assert!(!cidr.contains(ip("fd00::1")));
+
+        assert!("0.0.0.0/0".parse::<Cidr>().unwrap().contains(ip("1.2.3.4")));
+        assert!("::/0".parse::<Cidr>().unwrap().contains(ip("2001:db8::1")));
+        assert!("fd00::/8".parse::<Cidr>().unwrap().contains(ip("fd12::1")));
+    }
+
+    #[test]
+    fn untrusted_peer() {
+        let headers = headers(&[("x-forwarded-for", "1.2.3.4")]);
+        assert_eq!(
+            super::resolve(ip("5.6.7.8"), &headers, &trusted()),
+            (ip("5.6.7.8"), false)
+        );
+        assert_eq!(
+            super::resolve(ip("10.0.0.1"), &headers, &[]),
+            (ip("10.0.0.1"), false)
+        );
+    }
+
+    #[test]
+    fn x_forwarded_for() {
+        let resolve = |value| {
+            super::resolve(
+                ip("10.0.0.1"),
+                &headers(&[("x-forwarded-for", value)]),
+                &trusted(),
+            )
+        };
+
+        assert_eq!(resolve("1.2.3.4"), (ip("1.2.3.4"), true));
+        // Only the hops added by trusted proxies are believed
+        assert_eq!(resolve("6.6.6.6, 1.2.3.4, 10.0.0.2"), (ip("1.2.3.4"), true));
+        assert_eq!(resolve("10.0.0.3, 10.0.0.2"), (ip("10.0.0.3"), true));
+        assert_eq!(resolve("2001:db8::1"), (ip("2001:db8::1"), true));
+        assert_eq!(resolve("1.2.3.4:4711"), (ip("1.2.3.4"), true));
+        assert_eq!(resolve("1.2.3.4, garbage"), (ip("10.0.0.1"), false));
+        assert_eq!(resolve("garbage, 10.0.0.2"), (ip("10.0.0.2"), true));
+    }
+
+    #[test]
+    fn x_forwarded_for_multiple_headers() {
+        let headers = headers(&[
+            ("x-forwarded-for", "6.6.6.6, 1.2.3.4"),
+            ("x-forwarded-for", "10.0.0.2"),
+        ]);
+        assert_eq!(
+            super::resolve(ip("10.0.0.1"), &headers, &trusted()),
+            (ip("1.2.3.4"), true)
+        );
+    }
+
+    #[test]
+    fn forwarded() {
+        let resolve = |value| {
+            super::resolve(
+                ip("10.0.0.1"),
+                &headers(&[("forwarded", value)]),
+                &trusted(),
+            )
+        };
+
+        assert_eq!(resolve("for=1.2.3.4"), (ip("1.2.3.4"), true));
+        assert_eq!(resolve("For=\"1.2.3.4:4711\""), (ip("1.2.3.4"), true));
+        assert_eq!(
+            resolve("for=\"[2001:db8::1]:4711\";proto=https"),
+            (ip("2001:db8::1"), true)
+        );
+        assert_eq!(
+            resolve("for=6.6.6.6, for=1.2.3.4;by=10.0.0.1, for=\"[fd00::1]\""),
+            (ip("1.2.3.4"), true)
+        );
+        assert_eq!(
+            resolve("for=6.6.6.6;host=\"a,b\", for=1.2.3.4"),
+            (ip("1.2.3.4"), true)
+        );
+        assert_eq!(resolve("for=_hidden, for=10.0.0.2"), (ip("10.0.0.2"), true));
+        assert_eq!(resolve("for=unknown"), (ip("10.0.0.1"), false));
+        assert_eq!(resolve("proto=https"), (ip("10.0.0.1"), false));
+    }
+
+    #[test]
+    fn forwarded_takes_precedence() {
+        let headers = headers(&[("x-forwarded-for", "6.6.6.6"), ("forwarded", "for=1.2.3.4")]);
+        assert_eq!(
+            super::resolve(ip("10.0.0.1"), &headers, &trusted()),
+            (ip("1.2.3.4"), true)
+        );
+    }
+
+    #[test]
+    fn mapped_peer() {
+        let headers = headers(&[("x-forwarded-for", "1.2.3.4")]);
+        assert_eq!(
+            super::resolve(ip("::ffff:10.0.0.1"), &headers, &trusted()),
+            (ip("1.2.3.4"), true)
+        );
+        assert_eq!(
+            super::resolve(ip("::ffff:5.6.7.8"), &headers, &trusted()),
+            (ip("5.6.7.8"), false)
+        );
+    }
+}
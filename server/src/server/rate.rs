@@ -0,0 +1,307 @@
+// How many requests may be made over a period, such as `10/m`
+//
+// Bursts of up to `requests` are allowed, after which requests are spread evenly over the period
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Rate {
+    requests: u32,
+    per: std::time::Duration,
+}
+
+impl Rate {
+    // Time for a token to come back
+    fn interval(self) -> std::time::Duration {
+        self.per / self.requests
+    }
+
+    // How far ahead of the present the bucket may be spent, which allows the bursts
+    fn burst(self) -> std::time::Duration {
+        self.per.saturating_sub(self.interval())
+    }
+}
+
+impl std::str::FromStr for Rate {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (requests, unit) = value.split_once('/').ok_or("expected <count>/<s|m|h|d>")?;
+
+        let requests = requests
+            .parse::<u32>()
+            .ok()
+            .filter(|requests| *requests > 0)
+            .ok_or("count must be a positive integer")?;
+        let per = match unit {
+            "s" => std::time::Duration::from_secs(1),
+            "m" => std::time::Duration::from_mins(1),
+            "h" => std::time::Duration::from_hours(1),
+            "d" => std::time::Duration::from_hours(24),
+            unit => return Err(format!("invalid period unit: {unit}")),
+        };
+
+        Ok(Self { requests, per })
+    }
+}
+
+// Clients that ask for `misses` missing secrets within `time` are turned away for `time`
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Ban {
+    pub misses: u32,
+    pub time: std::time::Duration,
+}
+
+// Why a request was turned away, and for how long
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Denied {
+    Limited(std::time::Duration),
+    Banned(std::time::Duration),
+}
+
+struct Client {
+    // The bucket is kept as the time at which it is full again, so that it needs no refilling
+    full_at: std::time::Instant,
+    misses: u32,
+    misses_since: std::time::Instant,
+    banned_until: Option<std::time::Instant>,
+    last_seen: std::time::Instant,
+}
+
+impl Client {
+    fn new(now: std::time::Instant) -> Self {
+        Self {
+            full_at: now,
+            misses: 0,
+            misses_since: now,
+            banned_until: None,
+            last_seen: now,
+        }
+    }
+
+    // Whether forgetting the client would change nothing
+    fn is_idle(&self, now: std::time::Instant) -> bool {
+        self.banned_until.is_none() && self.misses == 0 && self.full_at <= now
+    }
+}
+
+// A token bucket for each client, and the count of its lookups of missing secrets
+pub struct Limiter<K> {
+    rate: Option<Rate>,
+    ban: Option<Ban>,
+    clients: std::sync::Mutex<std::collections::HashMap<K, Client>>,
+}
+
+impl<K> Limiter<K>
+where
+    K: Eq + std::hash::Hash + Clone,
+{
+    // Idle clients are forgotten once there are this many, and then the least recently seen ones
+    // if there are still too many, so that new clients always get a bucket
+    const MAX_CLIENTS: usize = 10_000;
+    // How many of the least recently seen clients are forgotten at once
+    const EVICTED: usize = Self::MAX_CLIENTS / 10;
+
+    pub fn new(rate: Option<Rate>, ban: Option<Ban>) -> Self {
+        Self {
+            rate,
+            ban,
+            clients: std::sync::Mutex::default(),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.rate.is_some() || self.ban.is_some()
+    }
+
+    pub fn bans(&self) -> bool {
+        self.ban.is_some()
+    }
+
+    fn clients(&self) -> std::sync::MutexGuard<'_, std::collections::HashMap<K, Client>> {
+        // The buckets are always left consistent, so they can be used after a panic
+        self.clients
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    // Takes a token for the request, unless the client is banned or out of tokens
+    pub fn acquire(&self, key: &K, now: std::time::Instant) -> Result<(), Denied> {
+        let mut clients = self.clients();
+        if clients.len() >= Self::MAX_CLIENTS && !clients.contains_key(key) {
+            clients.retain(|_, client| !client.is_idle(now));
+            evict(&mut clients, Self::MAX_CLIENTS - Self::EVICTED);
+        }
+
+        let client = clients
+            .entry(key.clone())
+            .or_insert_with(|| Client::new(now));
+        client.last_seen = client.last_seen.max(now);
+
+        if let Some(until) = client.banned_until {
+            if until > now {
+                return Err(Denied::Banned(until - now));
+            }
+            client.banned_until = None;
+            client.misses = 0;
+        }
+
+        let Some(rate) = self.rate else {
+            return Ok(());
+        };
+
+        let full_at = client.full_at.max(now);
+        let spent = full_at - now;
+        if spent <= rate.burst() {
+            client.full_at = full_at + rate.interval();
+            Ok(())
+        } else {
+            Err(Denied::Limited(spent.saturating_sub(rate.burst())))
+        }
+    }
+
+    // Counts a lookup of a missing secret, returning whether it got the client banned
+    pub fn miss(&self, key: &K, now: std::time::Instant) -> bool {
+        let Some(ban) = self.ban else {
+            return false;
+        };
+
+        let mut clients = self.clients();
+        let client = clients
+            .entry(key.clone())
+            .or_insert_with(|| Client::new(now));
+        client.last_seen = client.last_seen.max(now);
+
+        if now.duration_since(client.misses_since) > ban.time {
+            client.misses = 0;
+            client.misses_since = now;
+        }
+        client.misses += 1;
+
+        if client.misses >= ban.misses {
+            client.banned_until = Some(now + ban.time);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+// Forgets the least recently seen clients until there are at most `keep`
+fn evict<K>(clients: &mut std::collections::HashMap<K, Client>, keep: usize) {
+    let Some(excess) = clients.len().checked_sub(keep).filter(|excess| *excess > 0) else {
+        return;
+    };
+
+    let mut seen = clients
+        .values()
+        .map(|client| client.last_seen)
+        .collect::<Vec<_>>();
+    let (older, oldest, _) = seen.select_nth_unstable(excess - 1);
+    let oldest = *oldest;
+    // Clients seen at that same time are forgotten until there are few enough
+    let mut ties = excess - older.iter().filter(|seen| **seen < oldest).count();
+    clients.retain(|_, client| {
+        if client.last_seen == oldest && ties > 0 {
+            ties -= 1;
+            return false;
+        }
+        client.last_seen > oldest
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Ban, Denied, Limiter, Rate};
+
+    fn secs(secs: u64) -> std::time::Duration {
+        std::time::Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn parse_rate() {
+        assert_eq!(
+            "10/m".parse::<Rate>().unwrap(),
+            Rate {
+                requests: 10,
+                per: secs(60)
+            }
+        );
+        assert!("10".parse::<Rate>().is_err());
+        assert!("0/m".parse::<Rate>().is_err());
+        assert!("10/w".parse::<Rate>().is_err());
+        assert!("-1/s".parse::<Rate>().is_err());
+    }
+
+    #[test]
+    fn token_bucket() {
+        let limiter = Limiter::new(Some("2/m".parse().unwrap()), None);
+        let now = std::time::Instant::now();
+
+        assert_eq!(limiter.acquire(&"a", now), Ok(()));
+        assert_eq!(limiter.acquire(&"a", now), Ok(()));
+        assert_eq!(limiter.acquire(&"a", now), Err(Denied::Limited(secs(30))));
+
+        // Clients have their own buckets
+        assert_eq!(limiter.acquire(&"b", now), Ok(()));
+
+        // A token comes back every 30 seconds
+        assert_eq!(limiter.acquire(&"a", now + secs(30)), Ok(()));
+        assert_eq!(
+            limiter.acquire(&"a", now + secs(40)),
+            Err(Denied::Limited(secs(20)))
+        );
+    }
+
+    #[test]
+    fn ban_after_misses() {
+        let limiter = Limiter::new(
+            None,
+            Some(Ban {
+                misses: 3,
+                time: secs(60),
+            }),
+        );
+        let now = std::time::Instant::now();
+
+        assert!(!limiter.miss(&"a", now));
+        assert!(!limiter.miss(&"a", now + secs(1)));
+        assert_eq!(limiter.acquire(&"a", now + secs(1)), Ok(()));
+        assert!(limiter.miss(&"a", now + secs(2)));
+
+        assert_eq!(
+            limiter.acquire(&"a", now + secs(12)),
+            Err(Denied::Banned(secs(50)))
+        );
+        assert_eq!(limiter.acquire(&"b", now + secs(12)), Ok(()));
+        assert_eq!(limiter.acquire(&"a", now + secs(62)), Ok(()));
+
+        // Misses spread over more than the ban time are forgiven
+        assert!(!limiter.miss(&"a", now + secs(100)));
+        assert!(!limiter.miss(&"a", now + secs(150)));
+        assert!(!limiter.miss(&"a", now + secs(200)));
+    }
+
+    #[test]
+    fn forget_least_recently_seen() {
+        let limiter = Limiter::new(Some("1/d".parse().unwrap()), None);
+        let now = std::time::Instant::now();
+
+        // None of the clients is idle, as they all spent their token
+        for client in 0..Limiter::<usize>::MAX_CLIENTS {
+            let seen = now + std::time::Duration::from_millis(client as u64);
+            assert_eq!(limiter.acquire(&client, seen), Ok(()));
+        }
+        let later = now + secs(60);
+        assert!(limiter.acquire(&0, later).is_err());
+
+        // A new client gets a bucket, and the oldest ones but the one seen since are forgotten
+        assert_eq!(limiter.acquire(&usize::MAX, later), Ok(()));
+        let clients = limiter.clients();
+        assert_eq!(
+            clients.len(),
+            Limiter::<usize>::MAX_CLIENTS - Limiter::<usize>::EVICTED + 1
+        );
+        assert!(clients.contains_key(&0));
+        assert!(!clients.contains_key(&1));
+        assert!(!clients.contains_key(&Limiter::<usize>::EVICTED));
+        assert!(clients.contains_key(&(Limiter::<usize>::EVICTED + 1)));
+    }
+}
@@ -0,0 +1,630 @@
+// Signed notifications telling senders that their secret was read, or that it expired unread
+//
+// Senders give a callback URL when storing a secret, on one of the allowed hosts. Callbacks are sent
+// over HTTPS, or plain HTTP to the hosts allowed with it, such as a relay on the local network.
+// Events are queued
+// and sent from a thread of their own, so that slow receivers never hold up requests, and sent
+// again with a growing delay until they are answered with a 2xx status. Deliveries are sent
+// concurrently, a few at a time to each host, so that one slow receiver only holds up its own
+//
+// Bodies are signed with HMAC-SHA256 in the `X-Webhook-Signature` header, as `sha256=<hex>`.
+// Retries keep the `X-Webhook-Id` of the first attempt, so that receivers can ignore duplicates
+//
+// Callbacks are kept with the secrets, so that those of a file store are still followed after a
+// restart
+
+use super::store;
+
+use gotham::hyper;
+
+pub const SIGNATURE: &str = "x-webhook-signature";
+pub const DELIVERY: &str = "x-webhook-id";
+
+// How long receivers have to answer
+const TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+// Delay before the first retry, which doubles with every attempt up to `MAX_RETRY`
+const FIRST_RETRY: std::time::Duration = std::time::Duration::from_secs(1);
+const MAX_RETRY: std::time::Duration = std::time::Duration::from_mins(10);
+
+// How often expired secrets are looked for
+const EXPIRY_CHECK: std::time::Duration = std::time::Duration::from_secs(1);
+
+// Deliveries sent at once to the same host, the others waiting for one of them to be answered
+const MAX_PER_HOST: usize = 4;
+
+// Deliveries past this many waiting are dropped, so that unanswered receivers cannot exhaust memory
+const MAX_QUEUE: usize = 10_000;
+
+type Hmac = hmac::Hmac<sha2::Sha256>;
+
+type Client = hyper::Client<hyper_rustls::HttpsConnector<hyper::client::HttpConnector>>;
+
+// A host callbacks may be sent to, on any port unless one is given. Only over HTTPS, unless given
+// as `http://<host>`
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Host {
+    name: String,
+    port: Option<u16>,
+    plain: bool,
+}
+
+impl Host {
+    fn allows(&self, uri: &hyper::Uri, port: u16) -> bool {
+        let Some(name) = uri.host() else {
+            return false;
+        };
+
+        unbracket(name).eq_ignore_ascii_case(&self.name) && self.port.is_none_or(|p| p == port)
+    }
+}
+
+impl std::str::FromStr for Host {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (value, plain) = match value.get(..7) {
+            Some(scheme) if scheme.eq_ignore_ascii_case("http://") => (&value[7..], true),
+            _ => (value, false),
+        };
+        let authority = value
+            .parse::<hyper::http::uri::Authority>()
+            .map_err(|e| format!("invalid host: {e}"))?;
+        if authority.as_str().contains('@') {
+            return Err(String::from("host must not contain credentials"));
+        }
+
+        Ok(Self {
+            name: unbracket(authority.host()).to_ascii_lowercase(),
+            port: authority.port_u16(),
+            plain,
+        })
+    }
+}
+
+// IPv6 addresses are bracketed in URLs, but not when given alone
+fn unbracket(host: &str) -> &str {
+    host.trim_start_matches('[').trim_end_matches(']')
+}
+
+// Why a callback URL was refused
+#[derive(Debug, Clone, Copy, Eq, PartialEq, thiserror::Error)]
+pub enum Refused {
+    #[error("webhooks are not enabled")]
+    Disabled,
+    #[error("not a valid URL")]
+    Malformed,
+    #[error("only https URLs are supported, or http to the hosts allowed with it")]
+    Scheme,
+    #[error("host not allowed")]
+    Host,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Event {
+    Retrieved,
+    Expired,
+}
+
+#[derive(serde::Serialize)]
+struct Payload<'a> {
+    event: Event,
+    secret: &'a str,
+    // Seconds since the Unix epoch
+    time: u64,
+}
+
+struct Delivery {
+    id: String,
+    url: hyper::Uri,
+    body: String,
+    attempts: u32,
+    due: std::time::Instant,
+}
+
+impl Delivery {
+    fn new(url: hyper::Uri, id: &store::Id, event: Event, time: std::time::SystemTime) -> Self {
+        let payload = Payload {
+            event,
+            secret: &id.encode(),
+            time: time
+                .duration_since(std::time::UNIX_EPOCH)
+                .map_or(0, |since| since.as_secs()),
+        };
+
+        Self {
+            id: hex(&rand::random::<[u8; 16]>()),
+            url,
+            body: serde_json::to_string(&payload).unwrap_or_default(),
+            attempts: 0,
+            due: std::time::Instant::now(),
+        }
+    }
+
+    fn host(&self) -> &str {
+        self.url.host().unwrap_or_default()
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+enum Failed {
+    #[error("no answer within {}s", TIMEOUT.as_secs())]
+    Timeout,
+    #[error("answered {0}")]
+    Status(hyper::StatusCode),
+    #[error("{0}")]
+    Hyper(#[from] hyper::Error),
+    #[error("{0}")]
+    Http(#[from] hyper::http::Error),
+}
+
+struct Subscription {
+    url: hyper::Uri,
+    expires: std::time::SystemTime,
+}
+
+type Subscriptions =
+    std::sync::Arc<std::sync::Mutex<std::collections::HashMap<store::Id, Subscription>>>;
+
+// The callbacks of the outstanding secrets, and the queue of their events
+pub struct Hooks {
+    hosts: Vec<Host>,
+    subscriptions: Subscriptions,
+    // Only there when webhooks are enabled. Dropping it stops the delivery thread
+    queue: Option<std::sync::Mutex<tokio::sync::mpsc::UnboundedSender<Delivery>>>,
+}
+
+impl Hooks {
+    // Webhooks are enabled with both allowed hosts and a secret to sign them with. Each event is
+    // sent up to `attempts` times
+    pub fn new(hosts: Vec<Host>, secret: Option<&str>, attempts: u32) -> Self {
+        let subscriptions = Subscriptions::default();
+        let queue = secret.filter(|_| !hosts.is_empty()).and_then(|secret| {
+            let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+            let worker = Worker {
+                subscriptions: subscriptions.clone(),
+                key: secret.as_bytes().into(),
+                attempts: attempts.max(1),
+                queue: Vec::new(),
+                sending: std::collections::HashMap::new(),
+            };
+            std::thread::Builder::new()
+                .name(String::from("webhooks"))
+                .spawn(move || worker.run(receiver))
+                .map_err(|e| log::error!("Could not start sending webhooks: {e}"))
+                .ok()?;
+            Some(std::sync::Mutex::new(sender))
+        });
+
+        Self {
+            hosts,
+            subscriptions,
+            queue,
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.queue.is_some()
+    }
+
+    // Parses a callback URL, which must be on one of the allowed hosts over HTTPS, or plain HTTP
+    // when the host allows it
+    pub fn check(&self, url: &str) -> Result<hyper::Uri, Refused> {
+        if !self.enabled() {
+            return Err(Refused::Disabled);
+        }
+        if url.len() > store::MAX_CALLBACK_LENGTH {
+            return Err(Refused::Malformed);
+        }
+
+        let uri = url.parse::<hyper::Uri>().map_err(|_| Refused::Malformed)?;
+        let (plain, port) = match uri.scheme_str() {
+            Some("https") => (false, 443),
+            Some("http") => (true, 80),
+            _ => return Err(Refused::Scheme),
+        };
+        if uri.authority().is_some_and(|a| a.as_str().contains('@')) {
+            return Err(Refused::Malformed);
+        }
+
+        let port = uri.port_u16().unwrap_or(port);
+        let mut hosts = self
+            .hosts
+            .iter()
+            .filter(|host| host.allows(&uri, port))
+            .peekable();
+        if hosts.peek().is_none() {
+            return Err(Refused::Host);
+        }
+        if plain && !hosts.any(|host| host.plain) {
+            return Err(Refused::Scheme);
+        }
+        Ok(uri)
+    }
+
+    // Follows the callbacks of the secrets a file store kept from a previous run again, as long as
+    // they are still allowed
+    pub fn rebuild(&self, store: &dyn store::Store) {
+        if !self.enabled() {
+            return;
+        }
+
+        let mut subscriptions = self.subscriptions();
+        for (id, url, expires) in store.callbacks() {
+            match self.check(&url) {
+                Ok(url) => {
+                    subscriptions.insert(id, Subscription { url, expires });
+                }
+                Err(e) => log::warn!("Dropped the callback of a stored secret: {e}"),
+            }
+        }
+        if !subscriptions.is_empty() {
+            log::info!(
+                "Following the callbacks of {} stored secrets",
+                subscriptions.len()
+            );
+        }
+    }
+
+    fn subscriptions(
+        &self,
+    ) -> std::sync::MutexGuard<'_, std::collections::HashMap<store::Id, Subscription>> {
+        // Subscriptions are always left consistent, so they can be used after a panic
+        self.subscriptions
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    pub fn subscribe(&self, id: store::Id, url: hyper::Uri, expires: std::time::SystemTime) {
+        self.subscriptions()
+            .insert(id, Subscription { url, expires });
+    }
+
+    // Tells the sender of the secret `id` that it was read, if they asked to
+    pub fn retrieved(&self, id: &store::Id) {
+        let Some(subscription) = self.subscriptions().remove(id) else {
+            return;
+        };
+        let delivery = Delivery::new(
+            subscription.url,
+            id,
+            Event::Retrieved,
+            std::time::SystemTime::now(),
+        );
+        if let Some(queue) = &self.queue {
+            let queue = queue
+                .lock()
+                .unwrap_or_else(std::sync::PoisonError::into_inner);
+            if queue.send(delivery).is_err() {
+                log::error!("Could not queue webhook: the delivery thread stopped");
+            }
+        }
+    }
+
+    // Forgets the callback of a secret that is gone without being read or expiring
+    pub fn cancel(&self, id: &store::Id) {
+        self.subscriptions().remove(id);
+    }
+
+    pub fn clear(&self) {
+        self.subscriptions().clear();
+    }
+}
+
+// Sends the queued events, and those of the secrets that expire, from its own thread
+struct Worker {
+    subscriptions: Subscriptions,
+    key: std::sync::Arc<[u8]>,
+    attempts: u32,
+    // Deliveries waiting for their next attempt
+    queue: Vec<Delivery>,
+    // How many deliveries are being sent to each host
+    sending: std::collections::HashMap<String, usize>,
+}
+
+type Sent = (Delivery, Result<(), Failed>);
+
+impl Worker {
+    fn run(self, receiver: tokio::sync::mpsc::UnboundedReceiver<Delivery>) {
+        match tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+        {
+            Ok(runtime) => runtime.block_on(self.deliver(receiver)),
+            Err(e) => log::error!("Could not start sending webhooks: {e}"),
+        }
+    }
+
+    // Runs until the hooks are dropped, along with the deliveries still being sent
+    async fn deliver(mut self, mut receiver: tokio::sync::mpsc::UnboundedReceiver<Delivery>) {
+        let connector = hyper_rustls::HttpsConnectorBuilder::new()
+            .with_webpki_roots()
+            .https_or_http()
+            .enable_http1()
+            .build();
+        let client = hyper::Client::builder().build(connector);
+        // Each attempt is sent from a task of its own, which reports back here to be settled
+        let (sent, mut results) = tokio::sync::mpsc::unbounded_channel::<Sent>();
+
+        loop {
+            let now = std::time::Instant::now();
+            let wait = self
+                .queue
+                .iter()
+                .filter(|delivery| self.ready(delivery))
+                .map(|delivery| delivery.due.saturating_duration_since(now))
+                .min()
+                .map_or(EXPIRY_CHECK, |wait| wait.min(EXPIRY_CHECK));
+
+            tokio::select! {
+                delivery = receiver.recv() => match delivery {
+                    Some(delivery) => self.enqueue(delivery),
+                    None => return,
+                },
+                Some((delivery, result)) = results.recv() => self.settle(delivery, result),
+                () = tokio::time::sleep(wait) => {}
+            }
+            while let Ok(delivery) = receiver.try_recv() {
+                self.enqueue(delivery);
+            }
+            while let Ok((delivery, result)) = results.try_recv() {
+                self.settle(delivery, result);
+            }
+            for delivery in self.expired(std::time::SystemTime::now()) {
+                self.enqueue(delivery);
+            }
+
+            let now = std::time::Instant::now();
+            for delivery in std::mem::take(&mut self.queue) {
+                if delivery.due > now || !self.ready(&delivery) {
+                    self.queue.push(delivery);
+                    continue;
+                }
+                *self.sending.entry(delivery.host().to_owned()).or_default() += 1;
+
+                let (client, key, sent) = (client.clone(), self.key.clone(), sent.clone());
+                tokio::spawn(async move {
+                    let result = send(&client, &key, &delivery).await;
+                    // The worker outlives its tasks, so there is always someone to tell
+                    let _ = sent.send((delivery, result));
+                });
+            }
+        }
+    }
+
+    // Whether a delivery can be sent without going over the limit of its host
+    fn ready(&self, delivery: &Delivery) -> bool {
+        self.sending
+            .get(delivery.host())
+            .is_none_or(|&sending| sending < MAX_PER_HOST)
+    }
+
+    fn enqueue(&mut self, delivery: Delivery) {
+        if self.queue.len() >= MAX_QUEUE {
+            log::warn!(
+                "Dropped webhook {} to {}: too many waiting",
+                delivery.id,
+                delivery.host()
+            );
+            return;
+        }
+        self.queue.push(delivery);
+    }
+
+    // Takes the callbacks of the secrets that expired, dated by when they did
+    fn expired(&self, now: std::time::SystemTime) -> Vec<Delivery> {
+        let mut subscriptions = self
+            .subscriptions
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let expired = subscriptions
+            .iter()
+            .filter(|(_, subscription)| subscription.expires <= now)
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+
+        expired
+            .into_iter()
+            .filter_map(|id| {
+                let subscription = subscriptions.remove(&id)?;
+                Some(Delivery::new(
+                    subscription.url,
+                    &id,
+                    Event::Expired,
+                    subscription.expires,
+                ))
+            })
+            .collect()
+    }
+
+    // Schedules another attempt of a failed delivery, unless it ran out of them
+    fn settle(&mut self, mut delivery: Delivery, result: Result<(), Failed>) {
+        if let Some(sending) = self.sending.get_mut(delivery.host()) {
+            *sending -= 1;
+            if *sending == 0 {
+                self.sending.remove(delivery.host());
+            }
+        }
+        delivery.attempts += 1;
+        match result {
+            Ok(()) => log::debug!("Delivered webhook {} to {}", delivery.id, delivery.host()),
+            Err(e) if delivery.attempts >= self.attempts => log::warn!(
+                "Gave up on webhook {} to {} after {} attempts: {e}",
+                delivery.id,
+                delivery.host(),
+                delivery.attempts
+            ),
+            Err(e) => {
+                let delay = backoff(delivery.attempts);
+                log::info!(
+                    "Could not deliver webhook {} to {}, retrying in {}s: {e}",
+                    delivery.id,
+                    delivery.host(),
+                    delay.as_secs()
+                );
+                delivery.due = std::time::Instant::now() + delay;
+                self.enqueue(delivery);
+            }
+        }
+    }
+}
+
+// The delay before the next attempt, after `attempts` failed ones
+fn backoff(attempts: u32) -> std::time::Duration {
+    FIRST_RETRY
+        .saturating_mul(2_u32.saturating_pow(attempts.saturating_sub(1)))
+        .min(MAX_RETRY)
+}
+
+async fn send(client: &Client, key: &[u8], delivery: &Delivery) -> Result<(), Failed> {
+    let request = hyper::Request::post(delivery.url.clone())
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .header(
+            hyper::header::USER_AGENT,
+            concat!("passer/", env!("CARGO_PKG_VERSION")),
+        )
+        .header(DELIVERY, &delivery.id)
+        .header(SIGNATURE, signature(key, delivery.body.as_bytes()))
+        .body(hyper::Body::from(delivery.body.clone()))?;
+
+    let response = tokio::time::timeout(TIMEOUT, client.request(request))
+        .await
+        .map_err(|_| Failed::Timeout)??;
+    if response.status().is_success() {
+        Ok(())
+    } else {
+        Err(Failed::Status(response.status()))
+    }
+}
+
+// The value of the signature header of `body`
+pub fn signature(key: &[u8], body: &[u8]) -> String {
+    use hmac::Mac;
+
+    let mut mac = <Hmac as Mac>::new_from_slice(key).expect("HMAC takes any key size");
+    mac.update(body);
+    format!("sha256={}", hex(&mac.finalize().into_bytes()))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    use std::fmt::Write;
+
+    bytes
+        .iter()
+        .fold(String::with_capacity(bytes.len() * 2), |mut hex, byte| {
+            write!(hex, "{byte:02x}").unwrap();
+            hex
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Hooks, Host, Refused};
+
+    fn hooks(hosts: &[&str]) -> Hooks {
+        Hooks::new(
+            hosts.iter().map(|host| host.parse().unwrap()).collect(),
+            Some("secret"),
+            1,
+        )
+    }
+
+    #[test]
+    fn parse_host() {
+        assert_eq!(
+            "Example.com".parse::<Host>().unwrap(),
+            Host {
+                name: String::from("example.com"),
+                port: None,
+                plain: false,
+            }
+        );
+        assert_eq!(
+            "[::1]:8080".parse::<Host>().unwrap(),
+            Host {
+                name: String::from("::1"),
+                port: Some(8080),
+                plain: false,
+            }
+        );
+        assert_eq!(
+            "HTTP://relay.internal".parse::<Host>().unwrap(),
+            Host {
+                name: String::from("relay.internal"),
+                port: None,
+                plain: true,
+            }
+        );
+        assert!("".parse::<Host>().is_err());
+        assert!("user@example.com".parse::<Host>().is_err());
+        assert!("example.com/path".parse::<Host>().is_err());
+        assert!("https://example.com".parse::<Host>().is_err());
+    }
+
+    #[test]
+    fn check_callback() {
+        let hooks = hooks(&[
+            "example.com",
+            "http://10.0.0.1:8080",
+            "[::1]",
+            "http://[::1]:80",
+        ]);
+
+        assert!(hooks.check("https://example.com/hook?a=b").is_ok());
+        assert!(hooks.check("https://EXAMPLE.com:9000/").is_ok());
+        assert!(hooks.check("https://10.0.0.1:8080/hook").is_ok());
+        assert!(hooks.check("http://10.0.0.1:8080/hook").is_ok());
+        assert!(hooks.check("https://[::1]:8443/hook").is_ok());
+        assert!(hooks.check("http://[::1]/hook").is_ok());
+
+        // Plain HTTP only goes to the hosts allowed with it, on their ports
+        assert_eq!(hooks.check("http://example.com/"), Err(Refused::Scheme));
+        assert_eq!(hooks.check("http://[::1]:8080/"), Err(Refused::Scheme));
+        assert_eq!(hooks.check("https://10.0.0.1/hook"), Err(Refused::Host));
+        assert_eq!(hooks.check("https://example.org/"), Err(Refused::Host));
+        assert_eq!(
+            hooks.check("https://example.com.evil.org/"),
+            Err(Refused::Host)
+        );
+        assert_eq!(
+            hooks.check("https://user@example.com/"),
+            Err(Refused::Malformed)
+        );
+        assert_eq!(hooks.check("ftp://example.com/"), Err(Refused::Scheme));
+        assert_eq!(hooks.check("/hook"), Err(Refused::Scheme));
+        assert_eq!(
+            hooks.check("https://exa mple.com/"),
+            Err(Refused::Malformed)
+        );
+        let long = format!("https://example.com/{}", "a".repeat(2048));
+        assert_eq!(hooks.check(&long), Err(Refused::Malformed));
+
+        // Without a secret there is nothing to sign the events with
+        let disabled = Hooks::new(vec!["example.com".parse().unwrap()], None, 1);
+        assert_eq!(
+            disabled.check("http://example.com/"),
+            Err(Refused::Disabled)
+        );
+    }
+
+    #[test]
+    fn backoff() {
+        let secs = std::time::Duration::from_secs;
+
+        assert_eq!(super::backoff(1), secs(1));
+        assert_eq!(super::backoff(2), secs(2));
+        assert_eq!(super::backoff(5), secs(16));
+        assert_eq!(super::backoff(11), super::MAX_RETRY);
+        assert_eq!(super::backoff(100), super::MAX_RETRY);
+    }
+
+    #[test]
+    fn signature() {
+        // From `printf %s '{}' | openssl dgst -sha256 -hmac secret`
+        assert_eq!(
+            super::signature(b"secret", b"{}"),
+            "sha256=77325902caca812dc259733aacd046b73817372c777b8d95b402647474516e13"
+        );
+    }
+}
@@ -36,11 +36,14 @@ impl From<InternalError> for Error {
 pub struct Store {
     secrets: std::collections::HashMap<Id, Secret>,
     path: std::path::PathBuf,
+    expired: u64,
 }
 
 impl Store {
     const MAX_SIZE: u64 = super::MAX_SECRET_SIZE * 30;
 
+    // Allowed because the lint is newer than this code
+    #[allow(clippy::map_unwrap_or)]
     pub fn new(path: std::path::PathBuf) -> Self {
         log::info!("Serving secrets from file system");
         if path.exists() {
@@ -76,7 +79,11 @@ impl Store {
                 })
                 .collect::<std::collections::HashMap<_, _>>();
 
-            Self { secrets, path }
+            Self {
+                secrets,
+                path,
+                expired: 0,
+            }
         } else {
             log::info!(
                 "Store directory does not exist. Creating {}",
@@ -87,6 +94,7 @@ impl Store {
             Self {
                 secrets: std::collections::HashMap::<_, _>::new(),
                 path,
+                expired: 0,
             }
         }
     }
@@ -117,15 +125,56 @@ impl Store {
     fn size(&self) -> u64 {
         self.secrets.values().map(|s| s.size).sum()
     }
+
+    fn free_space(&self) -> Result<u64, InternalError> {
+        use std::os::unix::ffi::OsStrExt;
+
+        let path = std::ffi::CString::new(self.path.as_os_str().as_bytes())
+            .map_err(|_| InternalError::BadName)?;
+        let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+
+        // SAFETY: The path is a valid C string and `stat` is only read if the call succeeds
+        let stat = unsafe {
+            if libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) != 0 {
+                return Err(std::io::Error::last_os_error().into());
+            }
+            stat.assume_init()
+        };
+
+        // Allowed because the field types differ between platforms
+        #[allow(clippy::useless_conversion)]
+        Ok(u64::from(stat.f_bavail) * u64::from(stat.f_frsize))
+    }
 }
 
 impl super::Store for Store {
     fn refresh(&mut self) {
+        let count = self.secrets.len();
         self.secrets.retain(|_, secret| !secret.expired());
+        self.expired += (count - self.secrets.len()) as u64;
     }
 
-    fn put(&mut self, expiry: std::time::SystemTime, data: Vec<u8>) -> Result<Id, Error> {
-        let size = (data.len() + Secret::HEADER_SIZE) as u64;
+    fn put(
+        &mut self,
+        expiry: std::time::SystemTime,
+        owner: Option<&str>,
+        callback: Option<&str>,
+        data: Vec<u8>,
+    ) -> Result<Id, Error> {
+        if owner.is_some_and(|owner| {
+            owner.is_empty()
+                || owner.len() > Secret::MAX_OWNER_LENGTH
+                || owner.contains(char::is_whitespace)
+        }) {
+            return Err(Error::Generic(String::from("invalid owner")));
+        }
+        if callback.is_some_and(|callback| {
+            callback.len() > super::MAX_CALLBACK_LENGTH || callback.contains(char::is_whitespace)
+        }) {
+            return Err(Error::Generic(String::from("invalid callback")));
+        }
+        let header = Secret::header_size(owner, callback);
+        let size = data.len() as u64 + header;
 
         if size > super::MAX_SECRET_SIZE {
             return Err(Error::TooLarge);
@@ -145,7 +194,14 @@ impl super::Store for Store {
             }
         };
 
-        let mut secret = Secret { expiry, path, size };
+        let mut secret = Secret {
+            expiry,
+            path,
+            size,
+            owner: owner.map(ToOwned::to_owned),
+            callback: callback.map(ToOwned::to_owned),
+            header,
+        };
 
         if let Err(e) = secret.write(&data) {
             log::warn!(
@@ -175,28 +231,115 @@ impl super::Store for Store {
         let mut file = std::fs::File::open(&secret.path)?;
 
         let mut buffer = Vec::new();
-        file.seek(std::io::SeekFrom::Start(Secret::HEADER_SIZE as u64))?;
+        file.seek(std::io::SeekFrom::Start(secret.header))?;
         file.read_to_end(&mut buffer)?;
 
         Ok(buffer)
     }
+
+    fn revoke(&mut self, id: &Id) -> Result<(), Error> {
+        let Some(mut secret) = self.secrets.remove(id) else {
+            return Err(Error::SecretNotFound);
+        };
+        // Expired secrets delete their file once dropped
+        secret.expiry = std::time::UNIX_EPOCH;
+        Ok(())
+    }
+
+    fn purge(&mut self, scope: super::Purge) -> usize {
+        let count = self.secrets.len();
+        match scope {
+            super::Purge::Expired => self.refresh(),
+            super::Purge::All => {
+                // Expired secrets delete their file once dropped
+                for secret in self.secrets.values_mut() {
+                    secret.expiry = std::time::UNIX_EPOCH;
+                }
+                self.secrets.clear();
+            }
+        }
+        count - self.secrets.len()
+    }
+
+    fn stats(&self) -> super::Stats {
+        super::Stats {
+            secrets: self.secrets.len(),
+            size: self.size(),
+            capacity: Self::MAX_SIZE,
+            expired: self.expired,
+        }
+    }
+
+    fn available(&self) -> Result<u64, Error> {
+        // Names starting with a dot are not valid ids, so a leftover probe is ignored by the scan
+        let probe = self.path.join(".probe");
+        std::fs::File::create(&probe)?;
+        std::fs::remove_file(&probe)?;
+
+        Ok(Self::MAX_SIZE
+            .saturating_sub(self.size())
+            .min(self.free_space()?)
+            .saturating_sub(Secret::HEADER_SIZE as u64))
+    }
+
+    fn owned(&self) -> Vec<(Id, super::Owned)> {
+        self.secrets
+            .iter()
+            .filter_map(|(id, secret)| {
+                let owner = secret.owner.clone()?;
+                Some((
+                    *id,
+                    super::Owned {
+                        owner,
+                        size: secret.size - secret.header,
+                        expiry: secret.expiry,
+                    },
+                ))
+            })
+            .collect()
+    }
+
+    fn callbacks(&self) -> Vec<(Id, String, std::time::SystemTime)> {
+        self.secrets
+            .iter()
+            .filter_map(|(id, secret)| Some((*id, secret.callback.clone()?, secret.expiry)))
+            .collect()
+    }
 }
 
+// Stored as `passer\n<expiry>\n<data>`, or `passer\n<expiry> <owner>\n<data>` when it has an owner,
+// or `passer\n<expiry> <owner> <callback>\n<data>` when it has a callback, the owner being left empty
+// if there is none
 struct Secret {
     expiry: std::time::SystemTime,
     path: std::path::PathBuf,
     size: u64,
+    owner: Option<String>,
+    callback: Option<String>,
+    // Bytes before the data
+    header: u64,
 }
 
 impl Secret {
     const HEADER_SIZE: usize = Self::MAGIC_NUMBER_LENGTH + Self::EXPIRY_LENGTH;
     const MAGIC_NUMBER_LENGTH: usize = 6 + 1;
     const EXPIRY_LENGTH: usize = 14 + 1;
+    const MAX_OWNER_LENGTH: usize = 256;
+
+    fn header_size(owner: Option<&str>, callback: Option<&str>) -> u64 {
+        let owner = if owner.is_some() || callback.is_some() {
+            owner.unwrap_or_default().len() + 1
+        } else {
+            0
+        };
+        (Self::HEADER_SIZE + owner + callback.map_or(0, |callback| callback.len() + 1)) as u64
+    }
 
     fn read(path: std::path::PathBuf) -> Result<Self, InternalError> {
+        use std::io::BufRead;
         use std::io::Read;
 
-        let mut file = std::fs::File::open(&path)?;
+        let mut file = std::io::BufReader::new(std::fs::File::open(&path)?);
 
         let mut buffer = [0_u8; Self::HEADER_SIZE];
         file.read_exact(&mut buffer)?;
@@ -205,9 +348,25 @@ impl Secret {
             return Err(InternalError::BadHeader);
         }
 
-        if b'\n' != buffer[Self::HEADER_SIZE - 1] {
-            return Err(InternalError::BadHeader);
-        }
+        let (owner, callback) = match buffer[Self::HEADER_SIZE - 1] {
+            b'\n' => (None, None),
+            b' ' => {
+                let mut line = Vec::new();
+                (&mut file)
+                    .take((Self::MAX_OWNER_LENGTH + 1 + super::MAX_CALLBACK_LENGTH + 1) as u64)
+                    .read_until(b'\n', &mut line)?;
+                if line.pop() != Some(b'\n') {
+                    return Err(InternalError::BadHeader);
+                }
+                let line = String::from_utf8(line).map_err(|_| InternalError::BadHeader)?;
+                let (owner, callback) = match line.split_once(' ') {
+                    Some((owner, callback)) => (owner, Some(callback.to_owned())),
+                    None => (line.as_str(), None),
+                };
+                ((!owner.is_empty()).then(|| owner.to_owned()), callback)
+            }
+            _ => return Err(InternalError::BadHeader),
+        };
 
         let expiry = {
             let mut millis: u64 = 0;
@@ -225,8 +384,16 @@ impl Secret {
         };
 
         let size = path.metadata()?.len();
-
-        Ok(Self { expiry, path, size })
+        let header = Self::header_size(owner.as_deref(), callback.as_deref());
+
+        Ok(Self {
+            expiry,
+            path,
+            size,
+            owner,
+            callback,
+            header,
+        })
     }
 
     fn write(&self, data: &[u8]) -> Result<(), InternalError> {
@@ -240,7 +407,14 @@ impl Secret {
             .map_err(|_| InternalError::InvalidExpiry)?
             .as_millis();
 
-        file.write_all(format!("passer\n{epoch_millis:014}\n").as_bytes())?;
+        let tags = match (&self.owner, &self.callback) {
+            (None, None) => String::new(),
+            (Some(owner), None) => format!(" {owner}"),
+            (owner, Some(callback)) => {
+                format!(" {} {callback}", owner.as_deref().unwrap_or_default())
+            }
+        };
+        file.write_all(format!("passer\n{epoch_millis:014}{tags}\n").as_bytes())?;
         file.write_all(data)?;
         Ok(())
     }
@@ -383,6 +557,8 @@ mod tests {
                 std::time::SystemTime::now()
                     .checked_add(std::time::Duration::from_secs(1))
                     .unwrap(),
+                None,
+                None,
                 data,
             )
             .unwrap()
@@ -404,6 +580,8 @@ mod tests {
                 std::time::SystemTime::now()
                     .checked_add(std::time::Duration::from_secs(1))
                     .unwrap(),
+                None,
+                None,
                 data,
             )
             .unwrap();
@@ -425,6 +603,8 @@ mod tests {
                 std::time::SystemTime::now()
                     .checked_add(std::time::Duration::from_millis(50))
                     .unwrap(),
+                None,
+                None,
                 data,
             )
             .unwrap()
@@ -436,6 +616,7 @@ mod tests {
 
         store.refresh();
         assert!(!path.get().join(&id).exists());
+        assert_eq!(store.stats().expired, 1);
     }
 
     #[test]
@@ -449,6 +630,8 @@ mod tests {
                 std::time::SystemTime::now()
                     .checked_add(std::time::Duration::from_secs(1))
                     .unwrap(),
+                None,
+                None,
                 data,
             )
             .unwrap()
@@ -457,4 +640,156 @@ mod tests {
         assert_eq!(store.size(), 7 + 15 + 4);
         assert_eq!(path.get().join(id).metadata().unwrap().len(), 7 + 15 + 4);
     }
+
+    #[test]
+    fn owner() {
+        let path = TempDir::new("owner");
+
+        let mut store = Store::new(path.clone());
+        let expiry = std::time::UNIX_EPOCH + std::time::Duration::from_secs(4_000_000_000);
+        let owned = store
+            .put(expiry, Some("abc"), None, b"test"[..].into())
+            .unwrap();
+        store.put(expiry, None, None, b"test"[..].into()).unwrap();
+        assert_eq!(store.size(), 2 * (7 + 15 + 4) + 4);
+        assert!(
+            store
+                .put(expiry, Some("a\nb"), None, b"test"[..].into())
+                .is_err()
+        );
+        drop(store);
+
+        let mut store = Store::new(path.clone());
+        assert_eq!(
+            store.owned(),
+            vec![(
+                owned,
+                super::super::Owned {
+                    owner: String::from("abc"),
+                    size: 4,
+                    expiry,
+                }
+            )]
+        );
+        assert_eq!(store.get(&owned).unwrap(), b"test");
+    }
+
+    #[test]
+    fn callback() {
+        let path = TempDir::new("callback");
+
+        let mut store = Store::new(path.clone());
+        let expiry = std::time::UNIX_EPOCH + std::time::Duration::from_secs(4_000_000_000);
+        let url = "https://example.com/hook?a=b";
+        let called = store
+            .put(expiry, None, Some(url), b"test"[..].into())
+            .unwrap();
+        let both = store
+            .put(expiry, Some("abc"), Some(url), b"test"[..].into())
+            .unwrap();
+        assert!(
+            store
+                .put(expiry, None, Some("https://a b"), b"test"[..].into())
+                .is_err()
+        );
+        drop(store);
+
+        let mut store = Store::new(path.clone());
+        let mut callbacks = store.callbacks();
+        callbacks.sort_by_key(|(id, _, _)| *id != called);
+        assert_eq!(
+            callbacks,
+            vec![
+                (called, String::from(url), expiry),
+                (both, String::from(url), expiry)
+            ]
+        );
+        assert_eq!(store.owned().len(), 1);
+        assert_eq!(store.get(&called).unwrap(), b"test");
+        assert_eq!(store.get(&both).unwrap(), b"test");
+    }
+
+    #[test]
+    fn available() {
+        let path = TempDir::new("available");
+
+        let mut store = Store::new(path.clone());
+        let before = store.available().unwrap();
+        assert!(before > 0);
+        assert!(before <= Store::MAX_SIZE - 7 - 15);
+
+        let data: Vec<u8> = b"test"[..].into();
+        store
+            .put(
+                std::time::SystemTime::now()
+                    .checked_add(std::time::Duration::from_secs(1))
+                    .unwrap(),
+                None,
+                None,
+                data,
+            )
+            .unwrap();
+
+        assert!(store.available().unwrap() < before);
+    }
+
+    #[test]
+    fn unavailable_without_directory() {
+        let path = TempDir::new("unavailable_without_directory");
+
+        let store = Store::new(path.clone());
+        std::fs::remove_dir(path.get()).unwrap();
+        assert!(store.available().is_err());
+        std::fs::create_dir(path.get()).unwrap();
+    }
+
+    #[test]
+    fn revoke() {
+        let path = TempDir::new("revoke");
+
+        let mut store = Store::new(path.clone());
+        let id = store
+            .put(
+                std::time::SystemTime::now()
+                    .checked_add(std::time::Duration::from_secs(1))
+                    .unwrap(),
+                None,
+                None,
+                b"test"[..].into(),
+            )
+            .unwrap();
+        assert!(path.get().join(id.encode()).exists());
+
+        store.revoke(&id).unwrap();
+        assert!(!path.get().join(id.encode()).exists());
+        assert_eq!(
+            store.revoke(&id).unwrap_err(),
+            super::super::Error::SecretNotFound
+        );
+    }
+
+    #[test]
+    fn purge() {
+        let path = TempDir::new("purge");
+
+        let mut store = Store::new(path.clone());
+        for millis in [50, 50, 10_000] {
+            store
+                .put(
+                    std::time::SystemTime::now()
+                        .checked_add(std::time::Duration::from_millis(millis))
+                        .unwrap(),
+                    None,
+                    None,
+                    b"test"[..].into(),
+                )
+                .unwrap();
+        }
+        std::thread::sleep(std::time::Duration::from_millis(200));
+
+        assert_eq!(store.purge(super::super::Purge::Expired), 2);
+        assert_eq!(std::fs::read_dir(path.get()).unwrap().count(), 1);
+        assert_eq!(store.purge(super::super::Purge::All), 1);
+        assert_eq!(std::fs::read_dir(path.get()).unwrap().count(), 0);
+    }
 }
@@ -3,6 +3,7 @@ use super::Id;
 
 pub struct Store {
     secrets: std::collections::HashMap<Id, Secret>,
+    expired: u64,
 }
 
 impl Store {
@@ -12,6 +13,7 @@ impl Store {
         log::info!("Serving secrets from memory");
         Self {
             secrets: std::collections::HashMap::<_, _>::new(),
+            expired: 0,
         }
     }
 
@@ -25,11 +27,19 @@ impl Store {
 
 impl super::Store for Store {
     fn refresh(&mut self) {
+        let count = self.secrets.len();
         self.secrets
             .retain(|_, secret| secret.expiry > std::time::SystemTime::now());
+        self.expired += (count - self.secrets.len()) as u64;
     }
 
-    fn put(&mut self, expiry: std::time::SystemTime, data: Vec<u8>) -> Result<Id, Error> {
+    fn put(
+        &mut self,
+        expiry: std::time::SystemTime,
+        owner: Option<&str>,
+        callback: Option<&str>,
+        data: Vec<u8>,
+    ) -> Result<Id, Error> {
         let size = data.len() as u64;
 
         if size > super::MAX_SECRET_SIZE {
@@ -47,7 +57,15 @@ impl super::Store for Store {
             }
         };
 
-        self.secrets.insert(id, Secret { expiry, data });
+        self.secrets.insert(
+            id,
+            Secret {
+                expiry,
+                owner: owner.map(ToOwned::to_owned),
+                callback: callback.map(ToOwned::to_owned),
+                data,
+            },
+        );
         Ok(id)
     }
 
@@ -57,10 +75,65 @@ impl super::Store for Store {
             .map(|s| s.data)
             .ok_or(Error::SecretNotFound)
     }
+
+    fn revoke(&mut self, id: &Id) -> Result<(), Error> {
+        self.secrets
+            .remove(id)
+            .map(|_| ())
+            .ok_or(Error::SecretNotFound)
+    }
+
+    fn purge(&mut self, scope: super::Purge) -> usize {
+        let count = self.secrets.len();
+        match scope {
+            super::Purge::Expired => self.refresh(),
+            super::Purge::All => self.secrets.clear(),
+        }
+        count - self.secrets.len()
+    }
+
+    fn stats(&self) -> super::Stats {
+        super::Stats {
+            secrets: self.secrets.len(),
+            size: self.size(),
+            capacity: Self::MAX_SIZE,
+            expired: self.expired,
+        }
+    }
+
+    fn available(&self) -> Result<u64, Error> {
+        Ok(Self::MAX_SIZE.saturating_sub(self.size()))
+    }
+
+    fn owned(&self) -> Vec<(Id, super::Owned)> {
+        self.secrets
+            .iter()
+            .filter_map(|(id, secret)| {
+                let owner = secret.owner.clone()?;
+                Some((
+                    *id,
+                    super::Owned {
+                        owner,
+                        size: secret.data.len() as u64,
+                        expiry: secret.expiry,
+                    },
+                ))
+            })
+            .collect()
+    }
+
+    fn callbacks(&self) -> Vec<(Id, String, std::time::SystemTime)> {
+        self.secrets
+            .iter()
+            .filter_map(|(id, secret)| Some((*id, secret.callback.clone()?, secret.expiry)))
+            .collect()
+    }
 }
 
 struct Secret {
     expiry: std::time::SystemTime,
+    owner: Option<String>,
+    callback: Option<String>,
     data: Vec<u8>,
 }
 
@@ -78,6 +151,8 @@ mod tests {
                 std::time::SystemTime::now()
                     .checked_add(std::time::Duration::from_secs(1))
                     .unwrap(),
+                None,
+                None,
                 data,
             )
             .unwrap();
@@ -95,6 +170,8 @@ mod tests {
                 std::time::SystemTime::now()
                     .checked_add(std::time::Duration::from_secs(1))
                     .unwrap(),
+                None,
+                None,
                 data,
             )
             .unwrap();
@@ -113,6 +190,8 @@ mod tests {
                 std::time::SystemTime::now()
                     .checked_add(std::time::Duration::from_millis(50))
                     .unwrap(),
+                None,
+                None,
                 data,
             )
             .unwrap();
@@ -122,6 +201,7 @@ mod tests {
 
         store.refresh();
         assert!(store.secrets.is_empty());
+        assert_eq!(store.stats().expired, 1);
     }
 
     #[test]
@@ -134,10 +214,78 @@ mod tests {
                 std::time::SystemTime::now()
                     .checked_add(std::time::Duration::from_secs(1))
                     .unwrap(),
+                None,
+                None,
                 data,
             )
             .unwrap();
 
         assert_eq!(store.size(), len);
     }
+
+    #[test]
+    fn available() {
+        let mut store = Store::new();
+        assert_eq!(store.available().unwrap(), Store::MAX_SIZE);
+
+        let data: Vec<u8> = b"test"[..].into();
+        store
+            .put(
+                std::time::SystemTime::now()
+                    .checked_add(std::time::Duration::from_secs(1))
+                    .unwrap(),
+                None,
+                None,
+                data,
+            )
+            .unwrap();
+
+        assert_eq!(store.available().unwrap(), Store::MAX_SIZE - 4);
+    }
+
+    #[test]
+    fn revoke() {
+        let mut store = Store::new();
+        let data: Vec<u8> = b"test"[..].into();
+        let id = store
+            .put(
+                std::time::SystemTime::now()
+                    .checked_add(std::time::Duration::from_secs(1))
+                    .unwrap(),
+                None,
+                None,
+                data,
+            )
+            .unwrap();
+
+        store.revoke(&id).unwrap();
+        assert!(store.secrets.is_empty());
+        assert_eq!(
+            store.revoke(&id).unwrap_err(),
+            super::super::Error::SecretNotFound
+        );
+    }
+
+    #[test]
+    fn purge() {
+        let mut store = Store::new();
+        for millis in [50, 50, 10_000] {
+            store
+                .put(
+                    std::time::SystemTime::now()
+                        .checked_add(std::time::Duration::from_millis(millis))
+                        .unwrap(),
+                    None,
+                    None,
+                    b"test"[..].into(),
+                )
+                .unwrap();
+        }
+        std::thread::sleep(std::time::Duration::from_millis(200));
+
+        assert_eq!(store.purge(super::super::Purge::Expired), 2);
+        assert_eq!(store.purge(super::super::Purge::Expired), 0);
+        assert_eq!(store.purge(super::super::Purge::All), 1);
+        assert!(store.secrets.is_empty());
+    }
 }
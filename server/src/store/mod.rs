@@ -2,6 +2,8 @@ mod in_file;
 mod in_memory;
 
 pub const MAX_SECRET_SIZE: u64 = 110 * 1024 * 1024;
+// Longest callback URL kept with a secret
+pub const MAX_CALLBACK_LENGTH: usize = 2048;
 
 #[derive(Debug, thiserror::Error, Eq, PartialEq)]
 pub enum Error {
@@ -60,10 +62,54 @@ impl std::fmt::Display for Id {
     }
 }
 
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Stats {
+    pub secrets: usize,
+    pub size: u64,
+    pub capacity: u64,
+    pub expired: u64,
+}
+
+// Which secrets to delete when purging the store
+#[derive(Debug, Copy, Clone, Eq, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Purge {
+    Expired,
+    All,
+}
+
+// Who a secret held by the store is accounted to, as given when storing it
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Owned {
+    pub owner: String,
+    // Of the secret itself, without what the store adds to it
+    pub size: u64,
+    pub expiry: std::time::SystemTime,
+}
+
 pub trait Store {
     fn refresh(&mut self);
-    fn put(&mut self, expiry: std::time::SystemTime, data: Vec<u8>) -> Result<Id, Error>;
+    // `owner` and `callback` are kept with the secret, so that what each owner holds and whom to
+    // notify can be told after a restart. They cannot contain whitespace
+    fn put(
+        &mut self,
+        expiry: std::time::SystemTime,
+        owner: Option<&str>,
+        callback: Option<&str>,
+        data: Vec<u8>,
+    ) -> Result<Id, Error>;
     fn get(&mut self, id: &Id) -> Result<Vec<u8>, Error>;
+    // Deletes a secret without reading it
+    fn revoke(&mut self, id: &Id) -> Result<(), Error>;
+    // Returns how many secrets were deleted
+    fn purge(&mut self, scope: Purge) -> usize;
+    fn stats(&self) -> Stats;
+    // Bytes that can still be stored, verifying that the backing medium is usable
+    fn available(&self) -> Result<u64, Error>;
+    // The secrets stored with an owner
+    fn owned(&self) -> Vec<(Id, Owned)>;
+    // The secrets stored with a callback, with the callback and when they expire
+    fn callbacks(&self) -> Vec<(Id, String, std::time::SystemTime)>;
 }
 
 pub fn in_memory() -> impl Store {
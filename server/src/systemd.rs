@@ -0,0 +1,286 @@
+// The first file descriptor passed by the service manager, right after stdin, stdout and stderr
+const LISTEN_FDS_START: std::os::fd::RawFd = 3;
+
+// Sockets named like this with `FileDescriptorName=` serve the admin API instead of the public one
+pub const ADMIN_FD_NAME: &str = "admin";
+
+#[derive(Default)]
+pub struct ListenFds {
+    pub public: Vec<std::os::fd::OwnedFd>,
+    pub admin: Vec<std::os::fd::OwnedFd>,
+}
+
+// Takes the sockets passed through socket activation
+//
+// Must be called before any other thread is started, as it clears the activation environment so
+// that child processes do not inherit it
+pub fn take_listen_fds() -> ListenFds {
+    use std::os::fd::FromRawFd;
+
+    let (public, admin) = split_listen_fds(
+        std::env::var("LISTEN_PID").ok().as_deref(),
+        std::env::var("LISTEN_FDS").ok().as_deref(),
+        std::env::var("LISTEN_FDNAMES").ok().as_deref(),
+        std::process::id(),
+    );
+
+    // SAFETY: Called from `main` before the runtime threads are spawned
+    unsafe {
+        std::env::remove_var("LISTEN_PID");
+        std::env::remove_var("LISTEN_FDS");
+        std::env::remove_var("LISTEN_FDNAMES");
+    }
+
+    // SAFETY: The service manager hands over ownership of these descriptors
+    let own = |fds: Vec<_>| {
+        fds.into_iter()
+            .map(|fd| unsafe { std::os::fd::OwnedFd::from_raw_fd(fd) })
+            .collect()
+    };
+
+    ListenFds {
+        public: own(public),
+        admin: own(admin),
+    }
+}
+
+// Splits the passed descriptors into public and admin ones by their names, which are ignored
+// unless there is one for each descriptor
+fn split_listen_fds(
+    pid: Option<&str>,
+    fds: Option<&str>,
+    names: Option<&str>,
+    own_pid: u32,
+) -> (Vec<std::os::fd::RawFd>, Vec<std::os::fd::RawFd>) {
+    let count = activated_fd_count(pid, fds, own_pid);
+    let names = names
+        .map(|names| names.split(':').collect::<Vec<_>>())
+        .filter(|names| names.len() == usize::try_from(count).unwrap_or_default())
+        .unwrap_or_default();
+
+    let (mut public, mut admin) = (Vec::new(), Vec::new());
+    for (index, fd) in (LISTEN_FDS_START..LISTEN_FDS_START + count).enumerate() {
+        if names.get(index) == Some(&ADMIN_FD_NAME) {
+            admin.push(fd);
+        } else {
+            public.push(fd);
+        }
+    }
+
+    (public, admin)
+}
+
+fn activated_fd_count(pid: Option<&str>, fds: Option<&str>, own_pid: u32) -> std::os::fd::RawFd {
+    if pid.and_then(|pid| pid.parse::<u32>().ok()) != Some(own_pid) {
+        return 0;
+    }
+
+    fds.and_then(|fds| fds.parse().ok())
+        .filter(|fds| *fds > 0)
+        .unwrap_or(0)
+}
+
+// Sockets named with a leading `@` live in the abstract namespace, which only Linux has
+#[cfg(target_os = "linux")]
+fn abstract_address(name: &str) -> std::io::Result<std::os::unix::net::SocketAddr> {
+    use std::os::linux::net::SocketAddrExt;
+
+    std::os::unix::net::SocketAddr::from_abstract_name(name)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn abstract_address(_: &str) -> std::io::Result<std::os::unix::net::SocketAddr> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "abstract sockets are only supported on Linux",
+    ))
+}
+
+// Reports the service state through `NOTIFY_SOCKET`
+pub struct Notifier {
+    socket: std::os::unix::net::UnixDatagram,
+    address: std::os::unix::net::SocketAddr,
+}
+
+impl Notifier {
+    pub fn from_env() -> Option<Self> {
+        let path = std::env::var("NOTIFY_SOCKET").ok()?;
+        match Self::new(&path) {
+            Ok(notifier) => Some(notifier),
+            Err(e) => {
+                log::warn!("Could not connect to notify socket {path}: {e}");
+                None
+            }
+        }
+    }
+
+    fn new(path: &str) -> std::io::Result<Self> {
+        let address = if let Some(name) = path.strip_prefix('@') {
+            abstract_address(name)?
+        } else {
+            std::os::unix::net::SocketAddr::from_pathname(path)?
+        };
+
+        let socket = std::os::unix::net::UnixDatagram::unbound()?;
+        Ok(Self { socket, address })
+    }
+
+    fn notify(&self, state: &str) {
+        if let Err(e) = self.socket.send_to_addr(state.as_bytes(), &self.address) {
+            log::warn!("Could not notify service manager: {e}");
+        }
+    }
+
+    pub fn ready(&self) {
+        self.notify("READY=1\nSTATUS=Serving secrets");
+    }
+
+    pub fn stopping(&self) {
+        self.notify("STOPPING=1\nSTATUS=Shutting down");
+    }
+
+    pub fn watchdog(&self) {
+        self.notify("WATCHDOG=1");
+    }
+
+    // Half of the watchdog timeout, if the service manager expects heartbeats from this process
+    pub fn watchdog_interval() -> Option<std::time::Duration> {
+        watchdog_interval(
+            std::env::var("WATCHDOG_PID").ok().as_deref(),
+            std::env::var("WATCHDOG_USEC").ok().as_deref(),
+            std::process::id(),
+        )
+    }
+}
+
+fn watchdog_interval(
+    pid: Option<&str>,
+    usec: Option<&str>,
+    own_pid: u32,
+) -> Option<std::time::Duration> {
+    if let Some(pid) = pid
+        && pid.parse::<u32>().ok() != Some(own_pid)
+    {
+        return None;
+    }
+
+    usec.and_then(|usec| usec.parse::<u64>().ok())
+        .filter(|usec| *usec > 0)
+        .map(|usec| std::time::Duration::from_micros(usec / 2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Notifier;
+
+    struct FakeNotifySocket(std::path::PathBuf, std::os::unix::net::UnixDatagram);
+
+    impl FakeNotifySocket {
+        fn new(name: &'static str) -> Self {
+            let path = std::env::temp_dir().join(format!("passer_test_{name}.sock"));
+            let socket = std::os::unix::net::UnixDatagram::bind(&path).unwrap();
+            socket
+                .set_read_timeout(Some(std::time::Duration::from_secs(1)))
+                .unwrap();
+            Self(path, socket)
+        }
+
+        fn path(&self) -> &str {
+            self.0.to_str().unwrap()
+        }
+
+        fn receive(&self) -> String {
+            let mut buffer = [0; 256];
+            let size = self.1.recv(&mut buffer).unwrap();
+            String::from_utf8_lossy(&buffer[..size]).into_owned()
+        }
+    }
+
+    impl std::ops::Drop for FakeNotifySocket {
+        fn drop(&mut self) {
+            std::fs::remove_file(&self.0).unwrap();
+        }
+    }
+
+    #[test]
+    fn activated_fd_count() {
+        use super::activated_fd_count;
+
+        assert_eq!(activated_fd_count(Some("12"), Some("2"), 12), 2);
+        assert_eq!(activated_fd_count(Some("13"), Some("2"), 12), 0);
+        assert_eq!(activated_fd_count(None, Some("2"), 12), 0);
+        assert_eq!(activated_fd_count(Some("12"), None, 12), 0);
+        assert_eq!(activated_fd_count(Some("12"), Some("-1"), 12), 0);
+        assert_eq!(activated_fd_count(Some("12"), Some("two"), 12), 0);
+    }
+
+    #[test]
+    fn split_listen_fds() {
+        use super::split_listen_fds;
+
+        assert_eq!(
+            split_listen_fds(Some("12"), Some("3"), Some("http:admin:https"), 12),
+            (vec![3, 5], vec![4])
+        );
+        assert_eq!(
+            split_listen_fds(Some("12"), Some("2"), None, 12),
+            (vec![3, 4], vec![])
+        );
+        assert_eq!(
+            split_listen_fds(Some("12"), Some("2"), Some("admin"), 12),
+            (vec![3, 4], vec![])
+        );
+        assert_eq!(
+            split_listen_fds(Some("13"), Some("1"), Some("admin"), 12),
+            (vec![], vec![])
+        );
+    }
+
+    #[test]
+    fn watchdog_interval() {
+        use super::watchdog_interval;
+
+        assert_eq!(
+            watchdog_interval(None, Some("30000000"), 12),
+            Some(std::time::Duration::from_secs(15))
+        );
+        assert_eq!(
+            watchdog_interval(Some("12"), Some("30000000"), 12),
+            Some(std::time::Duration::from_secs(15))
+        );
+        assert_eq!(watchdog_interval(Some("13"), Some("30000000"), 12), None);
+        assert_eq!(watchdog_interval(None, Some("0"), 12), None);
+        assert_eq!(watchdog_interval(None, None, 12), None);
+    }
+
+    #[test]
+    fn notify() {
+        let socket = FakeNotifySocket::new("notify");
+        let notifier = Notifier::new(socket.path()).unwrap();
+
+        notifier.ready();
+        assert_eq!(socket.receive(), "READY=1\nSTATUS=Serving secrets");
+
+        notifier.watchdog();
+        assert_eq!(socket.receive(), "WATCHDOG=1");
+
+        notifier.stopping();
+        assert_eq!(socket.receive(), "STOPPING=1\nSTATUS=Shutting down");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn notify_abstract() {
+        use std::os::linux::net::SocketAddrExt;
+
+        let name = format!("passer_test_notify_abstract_{}", std::process::id());
+        let address = std::os::unix::net::SocketAddr::from_abstract_name(&name).unwrap();
+        let socket = std::os::unix::net::UnixDatagram::bind_addr(&address).unwrap();
+
+        Notifier::new(&format!("@{name}")).unwrap().ready();
+
+        let mut buffer = [0; 256];
+        let size = socket.recv(&mut buffer).unwrap();
+        assert_eq!(&buffer[..size], b"READY=1\nSTATUS=Serving secrets");
+    }
+}
@@ -185,6 +185,36 @@ impl Pack {
     }
 }
 
+/// Solves a proof of work challenge issued by the server
+///
+/// Returns the proof to send in the `X-Proof-Of-Work` header of the upload, whose SHA-256
+/// starts with `difficulty` zero bits
+#[wasm_bindgen]
+pub fn solve_challenge(challenge: &str, difficulty: u8) -> Result<js_sys::JsString, JsValue> {
+    solve(challenge, difficulty)
+        .map(Into::into)
+        .ok_or_else(|| Error::FailedToProcess.into_js_value())
+}
+
+fn solve(challenge: &str, difficulty: u8) -> Option<String> {
+    use sha2::Digest;
+
+    (0..u64::MAX)
+        .map(|nonce| format!("{challenge}:{nonce}"))
+        .find(|proof| leading_zeros(&sha2::Sha256::digest(proof)) >= u32::from(difficulty))
+}
+
+fn leading_zeros(hash: &[u8]) -> u32 {
+    let mut zeros = 0;
+    for byte in hash {
+        zeros += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    zeros
+}
+
 #[cfg(test)]
 mod tests {
     #[test]
@@ -208,4 +238,45 @@ mod tests {
         assert_eq!(decrypted.name, "foo");
         assert_eq!(decrypted.data, Vec::from("bar"));
     }
+
+    #[test]
+    fn solve_challenge() {
+        use sha2::Digest;
+
+        let proof = super::solve("foo.bar", 12).unwrap();
+        let (challenge, nonce) = proof.rsplit_once(':').unwrap();
+        assert_eq!(challenge, "foo.bar");
+        assert!(nonce.parse::<u64>().is_ok());
+
+        let hash = sha2::Sha256::digest(&proof);
+        assert_eq!(hash[0], 0);
+        assert_eq!(hash[1] & 0xf0, 0);
+
+        assert_eq!(super::solve("foo.bar", 0).unwrap(), "foo.bar:0");
+    }
+
+    // Kept the same as in `server/src/server/pow.rs`, so that the server and its clients agree on what a valid
+    // proof is
+    #[test]
+    fn leading_zeros() {
+        use sha2::Digest;
+
+        for (hash, zeros) in [
+            (&[][..], 0),
+            (&[0xff][..], 0),
+            (&[0x0f, 0x00][..], 4),
+            (&[0x00, 0x80][..], 8),
+            (&[0x00, 0x00, 0x01, 0xff][..], 23),
+            (&[0x00; 32][..], 256),
+        ] {
+            assert_eq!(super::leading_zeros(hash), zeros, "{hash:02x?}");
+        }
+
+        // The first proof of `foo.bar` with 16 zero bits
+        assert_eq!(
+            super::leading_zeros(&sha2::Sha256::digest("foo.bar:3099")),
+            16
+        );
+        assert_eq!(super::solve("foo.bar", 16).unwrap(), "foo.bar:3099");
+    }
 }
@@ -1,8 +0,0 @@
-export class Path {
-  static readonly ENCRYPT = '/';
-  static readonly DECRYPT_STEPPED = '/d/';
-  static readonly DECRYPT_QUICK = '/q/';
-  static readonly HOW_IT_WORKS = '/howitworks';
-}
-
-export const API = '/api/';
@@ -30,7 +30,7 @@ const App = () => {
   );
 
   const mainContent = () => (
-    <Router>
+    <Router basename={config.BASE_PATH}>
       {alerts.map((alert, i) => (
         <AlertBanner key={i} {...alert} />
       ))}
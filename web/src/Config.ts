@@ -5,4 +5,43 @@ export class Path {
   static readonly HOW_IT_WORKS = '/howitworks';
 }
 
-export const API = 'http://localhost:3030/';
+// The path the front-end is served under, set with PUBLIC_URL at build time
+export const BASE_PATH = process.env.PUBLIC_URL;
+
+interface Runtime {
+  api_url: string;
+  // Where to sign in before uploading, when the server asks for it
+  login_url?: string;
+  max_secret_size: number;
+  ttl: {
+    min_seconds: number;
+    // Absent when the server does not limit how long secrets are kept
+    max_seconds?: number;
+  };
+  features: string[];
+}
+
+// Used until the server provides its own, or if it cannot be reached
+let runtime: Runtime = {
+  api_url: '/api/',
+  max_secret_size: 110 * 1024 * 1024,
+  ttl: {
+    min_seconds: 0,
+    max_seconds: 7 * 24 * 60 * 60,
+  },
+  features: [],
+};
+
+// Fetches the configuration served next to the front-end
+export const load = () =>
+  fetch(`${BASE_PATH}/config.json`)
+    .then(response => (response.ok ? response.json() : Promise.reject(response.status)))
+    .then((loaded: Runtime) => {
+      runtime = loaded;
+    })
+    .catch(() => {});
+
+export const api = () => runtime.api_url;
+export const loginUrl = () => runtime.login_url;
+export const ttl = () => runtime.ttl;
+export const hasFeature = (feature: string) => runtime.features.includes(feature);
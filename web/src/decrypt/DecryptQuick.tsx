@@ -42,7 +42,7 @@ const Decrypt = (props: IProps) => {
       const url = hash.substring(0, 43);
       const key = passer.Key.from_base64(hash.substring(43));
 
-      fetch(`${config.API}${url}`, {
+      fetch(`${config.api()}${url}`, {
         redirect: 'follow',
       })
         .then(response => {
@@ -44,7 +44,7 @@ const DecryptStepped = (props: IProps) => {
   const { hash } = useParams();
 
   useEffect(() => {
-    fetch(`${config.API}${hash}`, {
+    fetch(`${config.api()}${hash}`, {
       redirect: 'follow',
     })
       .then(response => {
@@ -14,6 +14,7 @@ import {
 import { Link } from 'react-router-dom';
 import { useDropzone } from 'react-dropzone';
 import { encode } from '@msgpack/msgpack';
+import * as passer from 'passer_wasm';
 
 import './Encrypt.css';
 
@@ -59,35 +60,32 @@ interface IProps {
   setAlerts: Dispatch<SetStateAction<Alert[]>>;
 }
 
-const ttlToText = (ttl: number) => {
-  switch (ttl) {
-    case 1:
-      return '1 hour';
-    case 2:
-      return '12 hours';
-    case 3:
-      return '1 day';
-    case 4:
-      return '3 days';
-    case 5:
-      return '1 week';
-  }
+const TTLS = [
+  { text: '1 hour', query: '1h', seconds: 60 * 60 },
+  { text: '12 hours', query: '12h', seconds: 12 * 60 * 60 },
+  { text: '1 day', query: '1d', seconds: 24 * 60 * 60 },
+  { text: '3 days', query: '3d', seconds: 3 * 24 * 60 * 60 },
+  { text: '1 week', query: '7d', seconds: 7 * 24 * 60 * 60 },
+];
+
+// The expiry options allowed by the server
+const allowedTtls = () => {
+  const { min_seconds, max_seconds } = config.ttl();
+  const allowed = TTLS.filter(
+    ttl => ttl.seconds >= min_seconds && (max_seconds === undefined || ttl.seconds <= max_seconds),
+  );
+  return allowed.length > 0 ? allowed : TTLS.slice(0, 1);
 };
 
-const ttlToQuery = (ttl: number) => {
-  switch (ttl) {
-    case 1:
-      return '1h';
-    case 2:
-      return '12h';
-    case 3:
-      return '1d';
-    case 4:
-      return '3d';
-    case 5:
-      return '7d';
-  }
-};
+// Solves the puzzle the server asks of uploads of `size` bytes, if any
+const prove = (size: number): Promise<Record<string, string>> =>
+  config.hasFeature('proof_of_work')
+    ? fetch(`${config.api()}v2/challenge?size=${size}`)
+        .then(response => (response.ok ? response.json() : Promise.reject(response.status)))
+        .then(({ challenge, difficulty }) => ({
+          'X-Proof-Of-Work': passer.solve_challenge(challenge, difficulty),
+        }))
+    : Promise.resolve({});
 
 const Encrypt = (props: IProps) => {
   const inputRef = useRef<HTMLInputElement>(null);
@@ -100,7 +98,8 @@ const Encrypt = (props: IProps) => {
   const [totalSize, setTotalSize] = useState(0);
   const [modal, setModal] = useState(false);
   const [secretText, setSecretText] = useState('');
-  const [ttl, setTTL] = useState(3);
+  const ttls = allowedTtls();
+  const [ttl, setTTL] = useState(Math.min(3, ttls.length));
   const [uploadResult, setUploadResult] = useState<UploadResult>();
 
   const sizePercentage = ((totalSize * 100) / pack.MAX_SIZE).toFixed(1);
@@ -162,14 +161,25 @@ const Encrypt = (props: IProps) => {
 
   const send = () => {
     setLoading('Uploading');
-    fetch(`${config.API}?ttl=${ttlToQuery(ttl)}`, {
-      method: 'POST',
-      redirect: 'follow',
-      body: new Uint8Array(encode(packs.map(p => p.data.payload()))),
-    })
+    const body = new Uint8Array(encode(packs.map(p => p.data.payload())));
+    prove(body.length)
+      .then(headers =>
+        fetch(`${config.api()}?ttl=${ttls[ttl - 1].query}`, {
+          method: 'POST',
+          redirect: 'follow',
+          headers,
+          body,
+        }),
+      )
       .then(response => {
+        const login = config.loginUrl();
         if (response.ok) {
           return response.text();
+        } else if (response.status === 401 && login !== undefined) {
+          // Signing in leaves the page, so the secrets have to be added again afterwards
+          const back = encodeURIComponent(window.location.pathname);
+          window.location.assign(`${login}?return=${back}`);
+          throw Alert.ERROR_UPLOADING;
         } else {
           throw Alert.ERROR_UPLOADING;
         }
@@ -242,12 +252,12 @@ const Encrypt = (props: IProps) => {
         <></>
       )}
       <FormGroup>
-        <b>Expiry: {ttlToText(ttl)}</b>
+        <b>Expiry: {ttls[ttl - 1].text}</b>
         <CustomInput
           id='expiry-slider'
           type='range'
           min={1}
-          max={5}
+          max={ttls.length}
           onChange={e => setTTL(Number.parseInt(e.target.value))}
           value={ttl}
         />
@@ -54,7 +54,7 @@ const Result = (props: IProps) => {
             innerRef={singleRef}
             type='text'
             readOnly
-            value={window.location.origin + config.Path.DECRYPT_QUICK + props.url + props.keyString}
+            value={window.location.origin + config.BASE_PATH + config.Path.DECRYPT_QUICK + props.url + props.keyString}
           />
         </InputGroup>
       </div>
@@ -71,7 +71,7 @@ const Result = (props: IProps) => {
             innerRef={urlRef}
             type='text'
             readOnly
-            value={window.location.origin + config.Path.DECRYPT_STEPPED + props.url}
+            value={window.location.origin + config.BASE_PATH + config.Path.DECRYPT_STEPPED + props.url}
           />
         </InputGroup>
       </div>
@@ -2,9 +2,12 @@ import './index.scss';
 
 import React from 'react';
 import { createRoot } from 'react-dom/client';
+import * as config from './Config';
 import * as serviceWorker from './serviceWorker';
 
-import('./App')
+config
+  .load()
+  .then(() => import('./App'))
   .then(app => app.default)
   .then(App => {
     createRoot(document.getElementById('root')!).render(